@@ -26,7 +26,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("parse simple select query 1 alt", |b| {
         b.iter(|| {
             let mut query = black_box(String::from("SELECT test_series[ field1, field2,   field3,field4, field5, field6, field7  , field8 ]"));
-            parse_select(&mut query);
+            black_box(parse_select(&mut query)).unwrap();
         })
     });
 
@@ -41,14 +41,14 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("parse short insert", |b| {
         b.iter(|| {
             let mut query = String::from("INSERT test_series,field1=1.0");
-            parse_insert(&mut query);
+            black_box(parse_insert(&mut query)).unwrap();
         })
     });
 
     c.bench_function("parse longer insert", |b| {
         b.iter(|| {
             let mut query = String::from("INSERT test_series,field1=1.0, field2=0.5123 1663644227213092171");
-            parse_insert(&mut query);
+            black_box(parse_insert(&mut query)).unwrap();
         })
     });
 }
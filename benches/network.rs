@@ -1,57 +1,45 @@
-#[macro_use]
-extern crate criterion;
-
 use criterion::{Criterion, criterion_group, criterion_main};
 
+use rtdb::{DataValue, RecordCollection};
 use rtdb::execution::QueryResult;
-use rtdb::storage::series::{DataRow, RecordCollection};
+use rtdb::wire_protocol::{DataType, FieldDescription};
 use rtdb::wire_protocol::query::{build_query_result, ByteReader, parse_query_result};
 use pprof::criterion::{PProfProfiler, Output};
-use rtdb::wire_protocol::query2::test_fb;
+use tokio::runtime::Runtime;
 
 
 fn all(c: &mut Criterion) {
+    let mut elements = vec![];
+    for time in 1..=4 {
+        elements.extend([DataValue::Timestamp(time), DataValue::from(1.0), DataValue::from(2.0)]);
+    }
     let result = QueryResult {
-        count: 2,
+        count: 4,
         records: RecordCollection {
-            fields: vec![String::from("field1"), String::from("field2")],
-            rows: vec![DataRow { time: 1, elements: vec![Some(1.0), Some(2.0)] },
-                       DataRow { time: 2, elements: vec![Some(1.0), Some(2.0)] },
-                       DataRow { time: 3, elements: vec![Some(1.0), Some(2.0)] },
-                       DataRow { time: 4, elements: vec![Some(1.0), Some(2.0)] }],
+            fields: vec![FieldDescription { name: String::from("field1"), data_type: DataType::Float },
+                         FieldDescription { name: String::from("field2"), data_type: DataType::Float }],
+            elements,
         },
     };
+    let runtime = Runtime::new().unwrap();
 
     c.bench_function("serialize query result", |b| {
         b.iter(|| {
-            let _ = build_query_result(&result, );
+            let mut buffer = vec![];
+            runtime.block_on(build_query_result(&result, &mut buffer)).unwrap();
         })
     });
 
     c.bench_function("deserialize query result", |b| {
-        let buffer = build_query_result(&result, );
+        let mut buffer = vec![];
+        runtime.block_on(build_query_result(&result, &mut buffer)).unwrap();
 
         b.iter(|| {
-            let mut cursor = ByteReader::new(&buffer);
-            let _ = parse_query_result(&mut cursor);
+            // skip the result type
+            let mut cursor = ByteReader::new(&buffer[1..]);
+            let _ = parse_query_result(&mut cursor).unwrap();
         })
     });
-
-
-    c.bench_function("serialize query result flatbuffers", |b| {
-        b.iter(|| {
-            let _ = test_fb();
-        })
-    });
-
-    // c.bench_function("deserialize query result flatbuffers", |b| {
-    //     let buffer = build_query_result(&result);
-    //
-    //     b.iter(|| {
-    //         let mut cursor = ByteReader::new(&buffer);
-    //         let _ = parse_query_result(&mut cursor);
-    //     })
-    // });
 }
 
 criterion_group!{
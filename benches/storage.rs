@@ -9,12 +9,18 @@ use rtdb::storage::block_bool::{deserialize_bools, serialize_bools};
 use rtdb::storage::field::{FieldEntry, FieldStorage};
 use rtdb::storage::field_block::FieldStorageBlock;
 use rtdb::storage::field_index::FieldStorageBlockSummary;
+use rtdb::storage::metadata::DuplicatePolicy;
 use rtdb::storage::series::{merge_records, SeriesEntry, SeriesStorage};
 use rtdb::util::new_timestamp;
+use rtdb::wire_protocol::{DataType, FieldDescription};
+
+fn float_fields(names: &[&str]) -> Vec<FieldDescription> {
+    names.iter().map(|&name| FieldDescription { name: name.to_owned(), data_type: DataType::Float }).collect()
+}
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("write field [single]", |b| {
-        let mut s = FieldStorage::load("bench_tests", "field1");
+        let mut s = FieldStorage::load("bench_tests", "field1", DataType::Float, DuplicatePolicy::default()).unwrap();
 
         b.iter(|| {
             s.insert(FieldEntry { value: DataValue::from(123.0), time: 0 })
@@ -23,10 +29,11 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("load block", |b| {
         let f = File::open("test_series_value1").unwrap();
+        let summaries = FieldStorageBlockSummary::load_all("test_series_value1_index").unwrap();
 
         b.iter(|| {
-            // let blocks: Vec<FieldStorageBlock> = (0..100).map(|offset| FieldStorageBlock::load(&f, offset)).collect();
-            let _block = FieldStorageBlock::load(&f, 0);
+            // let blocks: Vec<FieldStorageBlock> = summaries.iter().map(|summary| FieldStorageBlock::load(&f, summary)).collect();
+            let _block = FieldStorageBlock::load(&f, &summaries[0]).unwrap();
         })
     });
 
@@ -37,10 +44,10 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 
     c.bench_function("read field", |b| {
-        let s = FieldStorage::load("test_series", "value1");
+        let s = FieldStorage::load("test_series", "value1", DataType::Float, DuplicatePolicy::default()).unwrap();
 
         b.iter(|| {
-            let _records = s.read(None, None);
+            let _records = s.read(None, None).unwrap();
         })
     });
 
@@ -48,11 +55,12 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("numbers reference", |b| {
         b.iter(|| {
             black_box(
-                if 3 > 1 {
-                    if 2 < 4 {
-                        let mut a = vec![1, 2, 3, 4];
-                        a.reverse()
-                    }
+                if black_box(3) > 1 && black_box(2) < 4 {
+                    let mut a = [1, 2, 3, 4];
+                    a.reverse();
+                    a
+                } else {
+                    [0; 4]
                 });
         })
     });
@@ -70,7 +78,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         }
 
         b.iter(|| {
-            let serialized = serialize_bools(&vals);
+            let _serialized = serialize_bools(&vals);
         })
     });
 
@@ -87,7 +95,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         let serialized = serialize_bools(&vals);
         b.iter(|| {
-            let deserialized = deserialize_bools(&serialized);
+            let _deserialized = deserialize_bools(&serialized);
         })
     });
 
@@ -110,22 +118,22 @@ fn criterion_benchmark(c: &mut Criterion) {
     // });
 
     c.bench_function("merge aligned records big", |b| {
-        let a: Vec<_> = (0..10000).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
-        let c: Vec<_> = (0..10000).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
+        let a: Vec<_> = (0..10000).map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
+        let c: Vec<_> = (0..10000).map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
         let entries = vec![a, c];
 
         b.iter(|| {
-            let _records = merge_records(&entries, &vec!["field1", "field2"], );
+            let _records = merge_records(&entries, float_fields(&["field1", "field2"]), &["field1", "field2"], None, &[]);
         });
     });
 
     c.bench_function("merge aligned records", |b| {
-        let a: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
-        let c: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
+        let a: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
+        let c: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
         let entries = vec![a, c];
 
         b.iter(|| {
-            let _records = merge_records(&entries, &vec!["field1", "field2"], );
+            let _records = merge_records(&entries, float_fields(&["field1", "field2"]), &["field1", "field2"], None, &[]);
         })
     });
 
@@ -140,12 +148,12 @@ fn criterion_benchmark(c: &mut Criterion) {
     // });
 
     c.bench_function("merge alternating records", |b| {
-        let a: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 2, value: DataValue::from(0.0) }).collect();
-        let c: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 2 + 1, value: DataValue::from(1.0) }).collect();
+        let a: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 2, value: DataValue::from(0.0) }).collect();
+        let c: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 2 + 1, value: DataValue::from(1.0) }).collect();
         let entries = vec![a, c];
 
         b.iter(|| {
-            let _records = merge_records(&entries, &vec!["field1", "field2"], );
+            let _records = merge_records(&entries, float_fields(&["field1", "field2"]), &["field1", "field2"], None, &[]);
         })
     });
 
@@ -175,13 +183,13 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("merge 4 aligned records", |b| {
         b.iter(|| {
-            let a: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
-            let b: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
-            let c: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
-            let d: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
+            let a: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(0.0) }).collect();
+            let b: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
+            let c: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
+            let d: Vec<_> = (0..100).map(|i| FieldEntry { time: i, value: DataValue::from(1.0) }).collect();
             let entries = vec![a, b, c, d];
 
-            let _records = merge_records(&entries, &vec!["field1", "field2", "field3", "field4"], );
+            let _records = merge_records(&entries, float_fields(&["field1", "field2", "field3", "field4"]), &["field1", "field2", "field3", "field4"], None, &[]);
         })
     });
 
@@ -199,31 +207,31 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("merge 4 alternating records", |b| {
         b.iter(|| {
-            let a: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 4, value: DataValue::from(0.0) }).collect();
-            let b: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 4 + 1, value: DataValue::from(1.0) }).collect();
-            let c: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 4 + 2, value: DataValue::from(2.0) }).collect();
-            let d: Vec<_> = (0..100).into_iter().map(|i| FieldEntry { time: i * 4 + 3, value: DataValue::from(3.0) }).collect();
+            let a: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 4, value: DataValue::from(0.0) }).collect();
+            let b: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 4 + 1, value: DataValue::from(1.0) }).collect();
+            let c: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 4 + 2, value: DataValue::from(2.0) }).collect();
+            let d: Vec<_> = (0..100).map(|i| FieldEntry { time: i * 4 + 3, value: DataValue::from(3.0) }).collect();
             let entries = vec![a, b, c, d];
 
-            let _records = merge_records(&entries, &vec!["field1", "field2", "field3", "field4"], );
+            let _records = merge_records(&entries, float_fields(&["field1", "field2", "field3", "field4"]), &["field1", "field2", "field3", "field4"], None, &[]);
         })
     });
 
     c.bench_function("series write", |b| {
         // fs::remove_dir("bench_test");
-        let mut s = SeriesStorage::new("bench_test");
+        let mut s = SeriesStorage::new("bench_test").unwrap();
 
         b.iter(|| {
             s.insert(SeriesEntry {
                 fields: vec![String::from("field1"), String::from("field2")],
                 values: vec![DataValue::from(123.0), DataValue::from(false)],
                 time: new_timestamp(),
-            })
+            }).unwrap()
         })
     });
 
     c.bench_function("read series", |b| {
-        let s = SeriesStorage::load("test_series");
+        let s = SeriesStorage::load("test_series").unwrap();
         // s.insert(SeriesEntry { values: HashMap::from([("value1", 1.0), ("value2", 2.0)]), time: 1 });
 
         b.iter(|| {
@@ -232,7 +240,9 @@ fn criterion_benchmark(c: &mut Criterion) {
                 selections: vec![],
                 start: None,
                 end: None,
-            });
+                condition: None,
+                interval: None,
+            }).unwrap();
         })
     });
 }
@@ -1,10 +1,11 @@
+use std::env;
 use std::time;
 
 use rustyline::error::ReadlineError::{Eof, Interrupted};
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
 
-use rtdb_client::{Client, ClientExecutionResult, ExecutionResult};
+use rtdb_client::{Client, ClientExecutionResult};
 use crate::table::to_table;
 
 mod table;
@@ -15,7 +16,13 @@ static GLOBAL: Jemalloc = Jemalloc;
 
 #[tokio::main]
 async fn main() {
-    let mut client = Client::new("127.0.0.1:2345").unwrap();
+    let mut client = match env::var("RTDB_USER") {
+        Ok(user) => {
+            let password = env::var("RTDB_PASSWORD").ok();
+            Client::connect_with_credentials("127.0.0.1:2345", &user, password.as_deref()).unwrap()
+        }
+        Err(_) => Client::new("127.0.0.1:2345").unwrap(),
+    };
 
     let mut rl = rustyline::Editor::<()>::new().unwrap();
     if rl.load_history("history.txt").is_err() {
@@ -33,10 +40,30 @@ async fn main() {
                 let elapsed = start.elapsed();
 
                 match result {
-                    ClientExecutionResult::Query(data) => {
+                    Ok(ClientExecutionResult::Query(data)) => {
                         println!("{}", to_table(&data));
                     }
-                    ClientExecutionResult::Insert(_) => {}
+                    Ok(ClientExecutionResult::Insert(result)) => {
+                        println!("{} rows inserted", result.rows_inserted);
+                        if !result.failed_rows.is_empty() {
+                            let rows: Vec<String> = result.failed_rows.iter().map(|row| row.to_string()).collect();
+                            println!("Failed rows: {}", rows.join(", "));
+                        }
+                        if let Some(error) = result.error {
+                            println!("Error: {}", error);
+                        }
+                    }
+                    Ok(ClientExecutionResult::Command(command)) => {
+                        println!("{}", command.message);
+                    }
+                    Ok(ClientExecutionResult::Error(error)) => {
+                        println!("Error: {}", error.message);
+                    }
+                    Ok(ClientExecutionResult::Authenticated) => {}
+                    Err(err) => {
+                        println!("Connection error: {}", err);
+                        break;
+                    }
                 }
                 println!("{}us", elapsed.as_micros());
             }
@@ -46,5 +73,7 @@ async fn main() {
         }
     }
 
-    rl.save_history("history.txt");
+    if let Err(err) = rl.save_history("history.txt") {
+        println!("Failed to save history: {}", err);
+    }
 }
\ No newline at end of file
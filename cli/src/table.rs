@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use rtdb_client::{DataType, QueryResult, DataValue, ClientQueryResult};
+use rtdb_client::{DataType, ClientQueryResult};
 
 pub fn to_table(data: &ClientQueryResult) -> String {
     let mut s = String::from("│ ");
@@ -12,20 +12,17 @@ pub fn to_table(data: &ClientQueryResult) -> String {
     // s.push_str(&"-".repeat(header_len));
     // s.push_str(&format!("{:_^8}"));
 
-    let field_count = data.records.fields.len();
-
     for (i, row) in data.records.rows.iter().enumerate() {
         if i > 20 {
             break;
         }
 
-        let now = chrono::NaiveDateTime::from_timestamp(row.time / 1e9 as i64, 0); // TODO: nsecs
-        let dt: DateTime<Utc> = DateTime::from_utc(now, Utc);
+        let dt: DateTime<Utc> = DateTime::from_timestamp(row.time / 1e9 as i64, 0).unwrap_or_default(); // TODO: nsecs
 
         s.push_str(&dt.to_rfc3339());
         s.push_str(" │  ");
 
-        for (i, &elem) in row.elements.iter().enumerate() {
+        for (i, elem) in row.elements.iter().enumerate() {
             let val_s = elem.to_string();
 
             let field = &data.records.fields[i];
@@ -33,7 +30,9 @@ pub fn to_table(data: &ClientQueryResult) -> String {
             match field.data_type {
                 DataType::Timestamp => s.push_str(&format!("{: >7} │", val_s)),
                 DataType::Float => s.push_str(&format!("{: >7} │", val_s)),
+                DataType::Integer => s.push_str(&format!("{: >7} │", val_s)),
                 DataType::Bool => s.push_str(&format!("{: <7} │", val_s)),
+                DataType::String => s.push_str(&format!("{: <7} │", val_s)),
             };
         }
         s.push_str("\n│");
@@ -42,4 +41,4 @@ pub fn to_table(data: &ClientQueryResult) -> String {
     s.push_str(&format!("{}/{}", data.count, data.count));
 
     s
-}
\ No newline at end of file
+}
@@ -1,8 +1,11 @@
-use std::io::{Error, Read, Write};
+use std::io::{Error, ErrorKind, Read, Write};
 use std::net::TcpStream;
 
 use byteorder::{BigEndian, ReadBytesExt};
 pub use rtdb::DataValue;
+use rtdb::network::{ACTION_AUTHENTICATE, ACTION_INSERT, ACTION_QUERY};
+pub use rtdb::network::MAX_STRING_LENGTH;
+use rtdb::users::hash_sha256;
 
 pub use rtdb::execution::{ExecutionResult, InsertionResult, QueryResult};
 pub use rtdb::execution::ClientQueryResult;
@@ -26,25 +29,65 @@ impl Client {
         }
     }
 
-    pub fn execute(&mut self, query: &str) -> ClientExecutionResult {
-        let len = query.len() as u16;
-        let mut buffer = Vec::with_capacity((2 + len) as usize);
-        buffer.write_all(&len.to_be_bytes());
-        buffer.write_all(query.as_bytes());
+    /// Creates a new rtdb client for the database available at `endpoint`, and authenticates as
+    /// the given user. The password is hashed before being sent.
+    pub fn connect_with_credentials(endpoint: &str, username: &str, password: Option<&str>) -> Result<Client, Error> {
+        let mut client = Client::new(endpoint)?;
 
-        self.stream.write_all(&buffer).unwrap();
-        self.stream.flush().unwrap();
+        let password = password.map(hash_sha256).unwrap_or_default();
+        let mut buffer = vec![ACTION_AUTHENTICATE];
+        push_str(&mut buffer, username)?;
+        push_str(&mut buffer, &password)?;
+
+        client.stream.write_all(&buffer)?;
+        client.stream.flush()?;
+
+        match read_from_stream(&mut client.stream)? {
+            ClientExecutionResult::Authenticated => Ok(client),
+            ClientExecutionResult::Error(error) => Err(Error::new(ErrorKind::PermissionDenied, error.message)),
+            _ => Err(Error::new(ErrorKind::InvalidData, "unexpected response to authentication")),
+        }
+    }
+
+    /// Sends a query or insert to the database, and waits for its result. Fails if the connection
+    /// breaks, or the response can't be parsed, and without sending anything if the statement is
+    /// longer than the server accepts, see [MAX_STRING_LENGTH].
+    pub fn execute(&mut self, query: &str) -> Result<ClientExecutionResult, Error> {
+        let action = match query.trim_start().get(..6) {
+            Some(command) if command.eq_ignore_ascii_case("insert") => ACTION_INSERT,
+            _ => ACTION_QUERY,
+        };
+
+        let mut buffer = Vec::with_capacity(5 + query.len());
+        buffer.push(action);
+        push_str(&mut buffer, query)?;
+
+        self.stream.write_all(&buffer)?;
+        self.stream.flush()?;
 
         read_from_stream(&mut self.stream)
     }
 }
 
+/// Pushes a string onto a buffer, prefixing it with the string's length as a u32. Fails if the
+/// string is longer than the server accepts.
+fn push_str(buffer: &mut Vec<u8>, str: &str) -> Result<(), Error> {
+    if str.len() > MAX_STRING_LENGTH as usize {
+        return Err(Error::new(ErrorKind::InvalidInput,
+                              format!("statement is {} bytes long, but may be at most {} bytes", str.len(), MAX_STRING_LENGTH)));
+    }
+
+    buffer.extend((str.len() as u32).to_be_bytes());
+    buffer.extend(str.as_bytes());
+    Ok(())
+}
+
 // TODO: generalize this, and we can probably optimize it a fair bit too, but that'll involve
 //  tweaking the way we serialize responses probably.
-fn read_from_stream(stream: &mut TcpStream) -> ClientExecutionResult {
-    let buf_len = stream.read_u64::<BigEndian>().unwrap();
+fn read_from_stream(stream: &mut TcpStream) -> Result<ClientExecutionResult, Error> {
+    let buf_len = stream.read_u64::<BigEndian>()?;
     let mut response = vec![0; buf_len as usize];
-    stream.read_exact(&mut response).unwrap();
+    stream.read_exact(&mut response)?;
 
     parse_result(&mut response)
 }
@@ -52,14 +95,6 @@ fn read_from_stream(stream: &mut TcpStream) -> ClientExecutionResult {
 
 #[cfg(test)]
 mod tests {
-    use crate::Client;
-
     #[test]
     fn compile() {}
-
-    #[tokio::test]
-    async fn creates_client() {
-        let mut client = Client::new("127.0.0.1:2345").unwrap();
-        client.execute("SELECT test_series");
-    }
 }
\ No newline at end of file
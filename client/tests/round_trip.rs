@@ -0,0 +1,114 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use rtdb::network::{ServerOptions, start_tcp_listener_with};
+use rtdb::wire_protocol::error::ErrorCode;
+use rtdb_client::{Client, ClientExecutionResult, DataValue, MAX_STRING_LENGTH};
+
+/// The tests store their series in a temporary directory, rather than in the working tree.
+fn data_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("rtdb-client-test-data-{}", process::id()))
+}
+
+/// Start a server in the background, and connect to it once it's listening.
+fn connect(address: &'static str) -> Client {
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let options = ServerOptions {
+            address: String::from(address),
+            data_dir: Some(data_dir().to_string_lossy().into_owned()),
+            ..ServerOptions::default()
+        };
+        runtime.block_on(start_tcp_listener_with(options));
+    });
+
+    for _ in 0..100 {
+        if let Ok(client) = Client::new(address) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+#[test]
+fn round_trip() {
+    let _ = fs::remove_dir_all(data_dir().join("test_round_trip"));
+    let mut client = connect("127.0.0.1:2350");
+
+    for query in ["INSERT test_round_trip,value=1.5 1", "INSERT test_round_trip,value=2.5 2"] {
+        match client.execute(query).unwrap() {
+            ClientExecutionResult::Insert(result) => assert!(result.success),
+            _ => panic!("expected an insert result"),
+        }
+    }
+
+    // the connection must stay in sync across several queries
+    for _ in 0..3 {
+        match client.execute("SELECT test_round_trip[value]").unwrap() {
+            ClientExecutionResult::Query(result) => {
+                assert_eq!(result.count, 2);
+                assert_eq!(result.records.rows[0].time, 1);
+                assert_eq!(result.records.rows[0].elements, vec![DataValue::from(1.5)]);
+                assert_eq!(result.records.rows[1].time, 2);
+                assert_eq!(result.records.rows[1].elements, vec![DataValue::from(2.5)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    // fields written at different times leave gaps in each other's rows
+    for query in ["INSERT test_round_trip,other=true 3", "INSERT test_round_trip,value=4.5 4"] {
+        match client.execute(query).unwrap() {
+            ClientExecutionResult::Insert(result) => assert!(result.success),
+            _ => panic!("expected an insert result"),
+        }
+    }
+    match client.execute("SELECT test_round_trip[value, other]").unwrap() {
+        ClientExecutionResult::Query(result) => {
+            assert_eq!(result.count, 4);
+            assert_eq!(result.records.rows[1].elements, vec![DataValue::from(2.5), DataValue::None]);
+            assert_eq!(result.records.rows[2].elements, vec![DataValue::None, DataValue::from(true)]);
+            assert_eq!(result.records.rows[3].elements, vec![DataValue::from(4.5), DataValue::None]);
+        }
+        _ => panic!("expected a query result"),
+    }
+
+    match client.execute("SELECT test_round_trip_missing").unwrap() {
+        ClientExecutionResult::Error(error) => assert_eq!(error.code, ErrorCode::SeriesNotFound),
+        _ => panic!("expected an error"),
+    }
+}
+
+#[test]
+fn round_trip_large_batches() {
+    let _ = fs::remove_dir_all(data_dir().join("test_round_trip_batch"));
+    let mut client = connect("127.0.0.1:2351");
+
+    // well over the 64 KiB a u16 length could describe
+    let rows: Vec<_> = (1..=2000).map(|i| format!("test_round_trip_batch,value={} {}", i as f64 + 0.5, i)).collect();
+    let batch = format!("INSERT {}", rows.join("\n"));
+    assert!(batch.len() > 64 * 1024);
+
+    match client.execute(&batch).unwrap() {
+        ClientExecutionResult::Insert(result) => {
+            assert!(result.success, "{:?}", result.error);
+            assert_eq!(result.rows_inserted, 2000);
+        }
+        _ => panic!("expected an insert result"),
+    }
+
+    // statements the server won't accept are rejected before anything is sent, so the connection
+    // stays in sync
+    let oversized = format!("INSERT {}", "x".repeat(MAX_STRING_LENGTH as usize));
+    assert_eq!(client.execute(&oversized).err().unwrap().kind(), ErrorKind::InvalidInput);
+
+    match client.execute("SELECT test_round_trip_batch[value]").unwrap() {
+        ClientExecutionResult::Query(result) => assert_eq!(result.count, 2000),
+        _ => panic!("expected a query result"),
+    }
+}
@@ -1,22 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::{fmt, io};
 use std::sync::{Arc, Mutex};
 
 use fnv::FnvHashMap;
+use log::error;
 use serde::Serialize;
 
 use crate::lang::{Action, SelectQuery};
+use crate::lang::delete::Deletion;
+use crate::lang::drop::DropTarget;
+use crate::lang::duplicates::Duplicates;
 use crate::lang::insert::Insertion;
-use crate::{ClientRecordCollection, RecordCollection};
-use crate::storage::series::SeriesStorage;
+use crate::lang::retention::Retention;
+use crate::lang::show::ShowTarget;
+use crate::{ClientRecordCollection, DataValue, RecordCollection};
+use crate::storage::block_manager::{BLOCK_CACHE, CacheStats};
+use crate::storage::metadata::SeriesMetadata;
+use crate::storage::series::{read_series, SeriesStorage, SeriesSummary};
+use crate::storage::tag_index::{series_key, TagIndex};
+use crate::util::new_timestamp;
+use crate::wire_protocol::{DataType, FieldDescription};
 
-pub struct ExecutionEngine<'a> {
-    series_storages: Arc<Mutex<FnvHashMap<String, SeriesStorage<'a>>>>,
+pub mod aggregate;
+
+pub struct ExecutionEngine {
+    series_storages: Arc<Mutex<FnvHashMap<String, SeriesStorage>>>,
+    tag_index: Arc<Mutex<TagIndex>>,
+    /// The retention period of each series that has one, so that enforcing them doesn't read the
+    /// metadata of every series.
+    retention_periods: Arc<Mutex<FnvHashMap<String, i64>>>,
 }
 
 #[derive(Serialize)]
 pub enum ExecutionResult {
     Query(QueryResult),
     Insert(InsertionResult),
+    Command(CommandResult),
 }
 
 #[derive(Debug, Serialize)]
@@ -37,56 +56,958 @@ pub struct ClientQueryResult {
 #[derive(Serialize)]
 pub struct InsertionResult {
     pub success: bool,
-    // pub error: Option<String>,
-    // pub rows_inserted: u32,
+    pub error: Option<String>,
+    pub rows_inserted: u32,
+
+    /// Numbers of the rows that weren't inserted, counting from 1, in order.
+    pub failed_rows: Vec<u32>,
+}
+
+/// The result of a statement that changes how a series is stored, rather than reading or writing
+/// entries.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CommandResult {
+    pub message: String,
+}
+
+/// An error that prevented an action from being executed at all.
+#[derive(Debug, PartialEq)]
+pub enum ExecutionError {
+    SeriesNotFound(String),
+    FieldNotFound(String),
+    /// The action is well formed, but asks for something that isn't supported.
+    InvalidQuery(String),
+    /// Data couldn't be read from or written to disk.
+    Storage(String),
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::SeriesNotFound(series) => write!(f, "series '{}' not found", series),
+            ExecutionError::FieldNotFound(field) => write!(f, "field '{}' not found", field),
+            ExecutionError::InvalidQuery(message) => write!(f, "{}", message),
+            ExecutionError::Storage(message) => write!(f, "storage error: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for ExecutionError {
+    fn from(error: io::Error) -> Self {
+        ExecutionError::Storage(error.to_string())
+    }
 }
 
-impl ExecutionEngine<'_> {
-    pub fn new<'a>() -> ExecutionEngine<'a> {
-        ExecutionEngine { series_storages: Arc::new(Mutex::new(HashMap::default())) }
+impl Default for ExecutionEngine {
+    fn default() -> Self {
+        ExecutionEngine::new()
     }
+}
 
-    pub fn execute(&self, action: Action) -> ExecutionResult {
+impl ExecutionEngine {
+    pub fn new() -> ExecutionEngine {
+        ExecutionEngine {
+            series_storages: Arc::new(Mutex::new(HashMap::default())),
+            tag_index: Arc::new(Mutex::new(TagIndex::load())),
+            retention_periods: Arc::new(Mutex::new(load_retention_periods())),
+        }
+    }
+
+    pub fn execute(&self, action: Action) -> Result<ExecutionResult, ExecutionError> {
         match action {
             Action::Select(query) => self.execute_select(query),
-            Action::Insert(insertion) => self.execute_insert(insertion),
+            Action::Insert(insertions) => Ok(self.execute_insert(insertions)),
+            Action::CreateRetention(retention) => self.execute_create_retention(retention),
+            Action::CreateDuplicates(duplicates) => self.execute_create_duplicates(duplicates),
+            Action::Delete(deletion) => self.execute_delete(deletion),
+            Action::Drop(target) => self.execute_drop(target),
+            Action::Show(target) => self.execute_show(target),
         }
     }
 
-    fn execute_select(&self, query: SelectQuery) -> ExecutionResult {
-        // TODO: tmp
+    /// Purge deleted entries from disk, for each loaded series.
+    pub fn purge_deleted(&self) {
         let mut storages = self.series_storages.lock().unwrap();
-        match storages.get(&query.series.to_owned()) {
-            Some(storage) => {
-                let records = storage.read(query);
-                let count = records.len();
-                ExecutionResult::Query(QueryResult { records, count })
+        for storage in storages.values_mut() {
+            if let Err(err) = storage.purge_deleted() {
+                error!("failed to purge deleted entries of '{}': {}", storage.series_name, err);
             }
-            None => {
-                let series_name = (&query).series.to_owned();
+        }
+    }
 
-                let storage = SeriesStorage::load("test_series"); // TODO: BAD
-                let records = storage.read(query);
-                let count = &records.len();
-                let result = ExecutionResult::Query(QueryResult { records, count: *count });
+    /// Drop entries older than the retention period of each series that has one, relative to the
+    /// given time in nanoseconds since Unix epoch. Series that haven't been loaded yet are loaded
+    /// if they have a retention period.
+    pub fn enforce_retention(&self, now: i64) {
+        let retention_periods = self.retention_periods.lock().unwrap().clone();
+        let mut storages = self.series_storages.lock().unwrap();
+        for series in retention_periods.into_keys() {
+            // the series may have been dropped since, e.g. by another engine
+            if !storages.contains_key(&series) && !SeriesStorage::exists(&series) {
+                self.cache_retention(&series, None);
+                continue;
+            }
 
-                storages.insert(series_name, storage); // TODO: bad
+            if !storages.contains_key(&series) {
+                match SeriesStorage::load(&series) {
+                    Ok(storage) => storages.insert(series.clone(), storage),
+                    Err(err) => {
+                        error!("failed to load series '{}': {}", series, err);
+                        continue;
+                    }
+                };
+            }
 
-                result
+            if let Err(err) = storages.get_mut(&series).unwrap().enforce_retention(now) {
+                error!("failed to enforce the retention period of '{}': {}", series, err);
             }
         }
     }
 
-    fn execute_insert(&self, insertion: Insertion) -> ExecutionResult {
+    /// Remember the retention period of a series, or that it has none.
+    fn cache_retention(&self, series: &str, retention: Option<i64>) {
+        let mut retention_periods = self.retention_periods.lock().unwrap();
+        match retention {
+            Some(retention) => retention_periods.insert(series.to_owned(), retention),
+            None => retention_periods.remove(series),
+        };
+    }
+
+    /// Execute a query over all series of a measurement whose tags may match the query's
+    /// condition. Unlike insertions, queries never create a series, so querying a measurement that
+    /// doesn't exist is an error.
+    fn execute_select(&self, mut query: SelectQuery) -> Result<ExecutionResult, ExecutionError> {
         let mut storages = self.series_storages.lock().unwrap();
-        if !storages.contains_key(&insertion.series.to_owned()) {
-            println!("LOAD SERIES");
-            storages.insert(insertion.series.to_owned(), SeriesStorage::load("test_series")); // TODO: bad
+        let mut index = self.tag_index.lock().unwrap();
+        let keys = load_measurement(&mut storages, &mut index, query.series)?;
+        let measurement: Vec<&SeriesStorage> = keys.iter().map(|key| &storages[key]).collect();
+
+        // quoted values are compared to string fields of the measurement, rather than to tags of
+        // the same name, so those mustn't narrow down the series either
+        let is_field = |name: &str| measurement.iter().any(|storage| storage.has_field(name));
+        query.condition = query.condition.map(|condition| condition.resolve_fields(&is_field));
+
+        let keys = index.lookup(query.series, query.condition.as_ref());
+        let series: Vec<&SeriesStorage> = keys.iter().map(|key| &storages[key]).collect();
+        let records = read_series(&measurement, &series, query)?;
+        let count = records.len();
+        Ok(ExecutionResult::Query(QueryResult { records, count }))
+    }
+
+    /// Set the retention period of all series of a measurement, and drop any entries that are
+    /// already older than it.
+    fn execute_create_retention(&self, retention: Retention) -> Result<ExecutionResult, ExecutionError> {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        for key in load_measurement(&mut storages, &mut index, &retention.series)? {
+            let storage = storages.get_mut(&key).unwrap();
+            storage.set_retention(retention.duration)?;
+            self.cache_retention(&key, retention.duration);
+            storage.enforce_retention(new_timestamp())?;
+        }
+
+        let message = match retention.duration {
+            Some(duration) => format!("entries of '{}' are kept for {}ns", retention.series, duration),
+            None => format!("entries of '{}' are kept forever", retention.series),
+        };
+        Ok(ExecutionResult::Command(CommandResult { message }))
+    }
+
+    /// Set how all series of a measurement handle entries at the same time as an existing entry.
+    /// New series of the measurement inherit the policy when they're created.
+    fn execute_create_duplicates(&self, duplicates: Duplicates) -> Result<ExecutionResult, ExecutionError> {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        for key in load_measurement(&mut storages, &mut index, &duplicates.series)? {
+            storages.get_mut(&key).unwrap().set_duplicate_policy(duplicates.policy)?;
+        }
+
+        let message = format!("duplicate entries of '{}' are handled as {:?}", duplicates.series, duplicates.policy);
+        Ok(ExecutionResult::Command(CommandResult { message }))
+    }
+
+    /// Delete entries from all series of a measurement. Deleting from a measurement or field that
+    /// doesn't exist is an error, so that typos don't go unnoticed.
+    fn execute_delete(&self, deletion: Deletion) -> Result<ExecutionResult, ExecutionError> {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        let keys = load_measurement(&mut storages, &mut index, &deletion.series)?;
+        if let Some(field) = deletion.fields.iter().find(|field| !keys.iter().any(|key| storages[key].has_field(field))) {
+            return Err(ExecutionError::FieldNotFound(field.to_owned()));
+        }
+
+        for key in keys {
+            storages.get_mut(&key).unwrap().delete(&deletion.fields, deletion.start, deletion.end)?;
+        }
+
+        let message = format!("deleted entries of '{}'", deletion.series);
+        Ok(ExecutionResult::Command(CommandResult { message }))
+    }
+
+    /// Drop all series of a measurement or a field of them, along with all of its data.
+    fn execute_drop(&self, target: DropTarget) -> Result<ExecutionResult, ExecutionError> {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        let message = match target {
+            DropTarget::Series(series) => {
+                for key in load_measurement(&mut storages, &mut index, &series)? {
+                    // dropping the series' storage closes its files, and evicts its blocks from the cache
+                    storages.remove(&key);
+                    SeriesStorage::remove(&key)?;
+                    index.remove(&key);
+                    self.cache_retention(&key, None);
+                }
+                format!("dropped series '{}'", series)
+            }
+            DropTarget::Field { series, field } => {
+                let keys = load_measurement(&mut storages, &mut index, &series)?;
+                if !keys.iter().any(|key| storages[key].has_field(&field)) {
+                    return Err(ExecutionError::FieldNotFound(field));
+                }
+
+                for key in keys {
+                    let storage = storages.get_mut(&key).unwrap();
+                    if storage.has_field(&field) {
+                        storage.drop_field(&field)?;
+                    }
+                }
+                format!("dropped field '{}' of series '{}'", field, series)
+            }
+        };
+
+        Ok(ExecutionResult::Command(CommandResult { message }))
+    }
+
+    /// Describe all series, including each tagged series of a measurement, the fields of a
+    /// measurement across all of its series, or the usage of the block cache.
+    ///
+    /// Series are listed from the tag index and the open storages. Series that aren't open are only
+    /// opened long enough to be summarized, so SHOW SERIES doesn't keep the files of every series
+    /// open.
+    fn execute_show(&self, target: ShowTarget) -> Result<ExecutionResult, ExecutionError> {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        let summaries = match target {
+            ShowTarget::Series => {
+                let keys: BTreeSet<String> = index.all_series().into_iter().chain(storages.keys().cloned()).collect();
+                let mut summaries = vec![];
+                for key in keys {
+                    let summary = match storages.get(&key) {
+                        Some(storage) => storage.summarize()?,
+                        None if SeriesStorage::exists(&key) => SeriesStorage::load(&key)?.summarize()?,
+                        // or it may have been dropped since, e.g. by another engine
+                        None => continue,
+                    };
+                    summaries.push(summary);
+                }
+                summaries
+            }
+            ShowTarget::Fields(series) => {
+                let mut fields: BTreeMap<String, SeriesSummary> = BTreeMap::new();
+                for key in load_measurement(&mut storages, &mut index, &series)? {
+                    for summary in storages[&key].summarize_fields()? {
+                        match fields.get_mut(&summary.name) {
+                            Some(field) => {
+                                field.count += summary.count;
+                                field.first = field.first.into_iter().chain(summary.first).min();
+                                field.last = field.last.into_iter().chain(summary.last).max();
+                            }
+                            None => {
+                                fields.insert(summary.name.clone(), summary);
+                            }
+                        }
+                    }
+                }
+                fields.into_values().collect()
+            }
+            ShowTarget::Stats => return Ok(ExecutionResult::Query(stats_to_result(BLOCK_CACHE.lock().unwrap().stats()))),
+        };
+
+        Ok(ExecutionResult::Query(summaries_to_result(summaries)))
+    }
+
+    /// Execute insertions into the series of their measurements with the same tags, creating
+    /// series that don't exist yet. All insertions are applied while holding the lock on the
+    /// storages only once, in order.
+    ///
+    /// Batches aren't atomic: each row is inserted on its own, so rows that can't be inserted don't
+    /// prevent the others from being inserted, before or after them. The result is only successful
+    /// if all rows were inserted, and lists the rows that weren't, along with the error of the
+    /// first of them.
+    fn execute_insert(&self, insertions: Vec<Insertion>) -> ExecutionResult {
+        let mut storages = self.series_storages.lock().unwrap();
+        let mut index = self.tag_index.lock().unwrap();
+        let mut rows_inserted = 0;
+        let mut failed_rows = vec![];
+        let mut first_error = None;
+
+        for (i, insertion) in insertions.into_iter().enumerate() {
+            match self.insert_row(&mut storages, &mut index, insertion) {
+                Ok(()) => rows_inserted += 1,
+                Err(error) => {
+                    failed_rows.push(i as u32 + 1);
+                    first_error.get_or_insert_with(|| format!("row {}: {}", i + 1, error));
+                }
+            }
         }
 
-        let storage = storages.get_mut(&insertion.series.to_owned()).unwrap();
-        storage.insert(insertion.entry.into());
+        ExecutionResult::Insert(InsertionResult { success: first_error.is_none(), error: first_error, rows_inserted, failed_rows })
+    }
+
+    /// Insert a single row into the series of its measurement with the same tags, creating the
+    /// series if it doesn't exist yet.
+    fn insert_row(&self, storages: &mut FnvHashMap<String, SeriesStorage>, index: &mut TagIndex, insertion: Insertion) -> Result<(), String> {
+        let key = series_key(&insertion.series, &insertion.tags);
+        if !storages.contains_key(&key) {
+            let created = !SeriesStorage::exists(&key);
+            let mut storage = SeriesStorage::load(&key)
+                .map_err(|err| format!("failed to load series '{}': {}", key, err))?;
+
+            // a new set of tags inherits the settings of the measurement's existing series
+            if created {
+                if let Some(sibling) = index.series(&insertion.series).first() {
+                    let inherited = SeriesMetadata::load(sibling).and_then(|metadata| {
+                        storage.set_retention(metadata.retention)?;
+                        storage.set_duplicate_policy(metadata.duplicates)
+                    });
+                    inherited.map_err(|err| format!("failed to create series '{}': {}", key, err))?;
+                    self.cache_retention(&key, storage.retention());
+                }
+            }
+
+            index.add(&key);
+            storages.insert(key.clone(), storage);
+        }
+
+        validate_field_types(storages, index, &key, &insertion)?;
+        storages.get_mut(&key).unwrap().insert(insertion.entry)
+    }
+}
+
+/// Returns the keys of all series of a measurement, loading them if necessary. Unlike insertions,
+/// other actions never create a series, so a measurement that doesn't exist is an error.
+fn load_measurement(storages: &mut FnvHashMap<String, SeriesStorage>, index: &mut TagIndex, measurement: &str) -> Result<Vec<String>, ExecutionError> {
+    // the series may have been created since the index was built, e.g. by another engine
+    if !index.contains(measurement) && SeriesStorage::exists(measurement) {
+        index.add(measurement);
+    }
+
+    let mut keys = vec![];
+    for key in index.series(measurement) {
+        match storages.contains_key(&key) || SeriesStorage::exists(&key) {
+            true => keys.push(key),
+            // or they may have been dropped since
+            false => index.remove(&key),
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(ExecutionError::SeriesNotFound(measurement.to_owned()));
+    }
+
+    for key in &keys {
+        if !storages.contains_key(key) {
+            storages.insert(key.clone(), SeriesStorage::load(key)?);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Check that each value of an insertion into the series with the given key is of the same type as
+/// its field in the measurement's other series, so that each column of a query across them has a
+/// single type. Fields the series already has are checked by the series itself.
+fn validate_field_types(storages: &FnvHashMap<String, SeriesStorage>, index: &TagIndex, key: &str, insertion: &Insertion) -> Result<(), String> {
+    let entry = &insertion.entry;
+    for (field, value) in entry.fields.iter().zip(&entry.values) {
+        let data_type = match value.data_type() {
+            Some(data_type) => data_type,
+            None => continue,
+        };
+        if storages[key].has_field(field) {
+            continue;
+        }
+
+        // the series of a measurement all agree on the type of a field, so the first one with the
+        // field decides
+        let mut existing = None;
+        for sibling in index.series(&insertion.series).into_iter().filter(|sibling| sibling != key) {
+            existing = match storages.get(&sibling) {
+                Some(storage) => storage.field_type(field),
+                None => SeriesMetadata::load(&sibling)
+                    .map_err(|err| format!("failed to load series '{}': {}", sibling, err))?
+                    .fields.get(field).cloned(),
+            };
+            if existing.is_some() {
+                break;
+            }
+        }
+
+        if let Some(existing) = existing {
+            if existing != data_type {
+                return Err(format!("cannot insert a {:?} value into field '{}' of type {:?}", data_type, field, existing));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe series or fields as the rows of a query result, with the columns name, type, count,
+/// first and last. The type of a series is "series", since its fields may have different types.
+///
+/// Rows have no time of their own, so their time is 0. Results can't hold missing values yet, so
+/// the first and last entries of a series or field without any are reported as 0 as well.
+fn summaries_to_result(summaries: Vec<SeriesSummary>) -> QueryResult {
+    let fields = vec![
+        FieldDescription { name: String::from("name"), data_type: DataType::String },
+        FieldDescription { name: String::from("type"), data_type: DataType::String },
+        FieldDescription { name: String::from("count"), data_type: DataType::Integer },
+        FieldDescription { name: String::from("first"), data_type: DataType::Timestamp },
+        FieldDescription { name: String::from("last"), data_type: DataType::Timestamp },
+    ];
+
+    let count = summaries.len();
+    let mut elements = Vec::with_capacity(count * (fields.len() + 1));
+    for summary in summaries {
+        let data_type = summary.data_type.map_or(String::from("series"), |data_type| data_type.to_string());
+        elements.extend([
+            DataValue::Timestamp(0),
+            DataValue::from(summary.name),
+            DataValue::from(data_type),
+            DataValue::Integer(summary.count as i64),
+            DataValue::Timestamp(summary.first.unwrap_or(0)),
+            DataValue::Timestamp(summary.last.unwrap_or(0)),
+        ]);
+    }
+
+    QueryResult { count, records: RecordCollection { fields, elements } }
+}
+
+/// Describe the statistics of a block cache as a query result, with one row for each statistic.
+fn stats_to_result(stats: CacheStats) -> QueryResult {
+    let fields = vec![
+        FieldDescription { name: String::from("name"), data_type: DataType::String },
+        FieldDescription { name: String::from("value"), data_type: DataType::Integer },
+    ];
+
+    let stats = [
+        ("hits", stats.hits),
+        ("misses", stats.misses),
+        ("evictions", stats.evictions),
+        ("blocks", stats.blocks as u64),
+        ("size", stats.size as u64),
+        ("capacity", stats.capacity as u64),
+    ];
+    let mut elements = Vec::with_capacity(stats.len() * (fields.len() + 1));
+    for (name, value) in stats {
+        elements.extend([DataValue::Timestamp(0), DataValue::from(name), DataValue::Integer(value as i64)]);
+    }
+
+    QueryResult { count: stats.len(), records: RecordCollection { fields, elements } }
+}
+
+/// Read the retention period of each series that has one from its metadata. Series whose metadata
+/// can't be read are skipped.
+fn load_retention_periods() -> FnvHashMap<String, i64> {
+    SeriesStorage::list()
+        .into_iter()
+        .filter_map(|series| match SeriesMetadata::load(&series) {
+            Ok(metadata) => metadata.retention.map(|retention| (series, retention)),
+            Err(err) => {
+                error!("failed to load the retention period of '{}': {}", series, err);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::storage::data_dir;
+
+    use crate::DataValue;
+    use crate::execution::{ExecutionEngine, ExecutionError, ExecutionResult};
+    use crate::lang::Action;
+    use crate::lang::delete::parse_delete;
+    use crate::lang::drop::parse_drop;
+    use crate::lang::duplicates::parse_create_duplicates;
+    use crate::lang::show::parse_show;
+    use crate::wire_protocol::DataType;
+    use crate::lang::insert::parse_insert;
+    use crate::lang::query::parse_select;
+    use crate::lang::retention::parse_create_retention;
+    use crate::storage::metadata::{DuplicatePolicy, SeriesMetadata};
+
+    fn select(engine: &ExecutionEngine, query: &str) -> Result<ExecutionResult, ExecutionError> {
+        let mut query = String::from(query);
+        engine.execute(Action::Select(parse_select(&mut query).unwrap()))
+    }
+
+    #[test]
+    fn it_routes_to_series() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing1", data_dir()));
+        let _ = fs::remove_dir_all(format!("{}/test_routing2", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        for query in ["INSERT test_routing1,value=1.0 1", "INSERT test_routing2,value=2.0 2"] {
+            let mut query = String::from(query);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        match select(&engine, "SELECT test_routing1[value]") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(1), DataValue::from(1.0)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        // a new engine has to load the series from disk
+        let engine = ExecutionEngine::new();
+        match select(&engine, "SELECT test_routing2[value]") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(2), DataValue::from(2.0)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[test]
+    fn it_creates_retention() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_retention", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let create = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::CreateRetention(parse_create_retention(&mut query).unwrap()))
+        };
+
+        let result = create("CREATE RETENTION 1d ON test_routing_retention");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_retention"))));
+
+        let mut query = String::from("INSERT test_routing_retention,value=1.0 1");
+        engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        match create("CREATE RETENTION 1d ON test_routing_retention") {
+            Ok(ExecutionResult::Command(result)) => assert!(result.message.contains("test_routing_retention")),
+            _ => panic!("expected a command result"),
+        }
+
+        // the retention period is persisted, so it's picked up by engines that haven't loaded the
+        // series yet
+        assert_eq!(SeriesMetadata::load("test_routing_retention").unwrap().retention, Some(24 * 60 * 60 * 1_000_000_000));
+        let engine = ExecutionEngine::new();
+        assert_eq!(engine.retention_periods.lock().unwrap().get("test_routing_retention"), Some(&(24 * 60 * 60 * 1_000_000_000)));
+
+        let mut query = String::from("DROP SERIES test_routing_retention");
+        engine.execute(Action::Drop(parse_drop(&mut query).unwrap())).unwrap();
+        assert!(engine.retention_periods.lock().unwrap().get("test_routing_retention").is_none());
+    }
+
+    #[test]
+    fn it_creates_duplicates() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_duplicates", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let create = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::CreateDuplicates(parse_create_duplicates(&mut query).unwrap()))
+        };
+        let insert = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        };
+
+        let result = create("CREATE DUPLICATES KEEP_FIRST ON test_routing_duplicates");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_duplicates"))));
+
+        insert("INSERT test_routing_duplicates,value=1.0 1");
+        match create("CREATE DUPLICATES KEEP_FIRST ON test_routing_duplicates") {
+            Ok(ExecutionResult::Command(result)) => assert!(result.message.contains("test_routing_duplicates")),
+            _ => panic!("expected a command result"),
+        }
+        insert("INSERT test_routing_duplicates,value=2.0 1");
+
+        let mut query = String::from("SELECT test_routing_duplicates[value]");
+        match engine.execute(Action::Select(parse_select(&mut query).unwrap())) {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements, vec![
+                DataValue::Timestamp(1), DataValue::from(1.0),
+            ]),
+            _ => panic!("expected a query result"),
+        }
+
+        assert_eq!(SeriesMetadata::load("test_routing_duplicates").unwrap().duplicates, DuplicatePolicy::KeepFirst);
+    }
+
+    #[test]
+    fn it_deletes_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_delete", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let delete = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::Delete(parse_delete(&mut query).unwrap()))
+        };
+
+        let result = delete("DELETE FROM test_routing_delete");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_delete"))));
+
+        for query in ["INSERT test_routing_delete,value=1.0 1", "INSERT test_routing_delete,value=2.0 2"] {
+            let mut query = String::from(query);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        let result = delete("DELETE FROM test_routing_delete[missing]");
+        assert_eq!(result.err(), Some(ExecutionError::FieldNotFound(String::from("missing"))));
+
+        assert!(matches!(delete("DELETE FROM test_routing_delete[value] BEFORE 1"), Ok(ExecutionResult::Command(_))));
+        match select(&engine, "SELECT test_routing_delete[value]") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(2), DataValue::from(2.0)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[test]
+    fn it_drops_series_and_fields() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_drop", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let drop = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::Drop(parse_drop(&mut query).unwrap()))
+        };
+
+        let result = drop("DROP SERIES test_routing_drop");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_drop"))));
+
+        let mut query = String::from("INSERT test_routing_drop,value1=1.0,value2=2.0 1");
+        engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+
+        let result = drop("DROP FIELD test_routing_drop.missing");
+        assert_eq!(result.err(), Some(ExecutionError::FieldNotFound(String::from("missing"))));
+
+        assert!(matches!(drop("DROP FIELD test_routing_drop.value1"), Ok(ExecutionResult::Command(_))));
+        match select(&engine, "SELECT test_routing_drop") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(1), DataValue::from(2.0)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        assert!(matches!(drop("DROP SERIES test_routing_drop"), Ok(ExecutionResult::Command(_))));
+        assert!(!Path::new(&format!("{}/test_routing_drop", data_dir())).exists());
+        assert!(!Path::new(&format!("{}/_dropped_test_routing_drop", data_dir())).exists());
+        let result = select(&engine, "SELECT test_routing_drop");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_drop"))));
+    }
+
+    #[test]
+    fn it_rejects_mismatched_field_types() {
+        let keys = ["test_routing_types,host=a", "test_routing_types,host=b"];
+        for key in keys {
+            let _ = fs::remove_dir_all(format!("{}/{}", data_dir(), key));
+        }
+
+        let engine = ExecutionEngine::new();
+        let insert = |engine: &ExecutionEngine, query: &str| {
+            let mut query = String::from(query);
+            match engine.execute(Action::Insert(parse_insert(&mut query).unwrap())) {
+                Ok(ExecutionResult::Insert(result)) => result,
+                _ => panic!("expected an insertion result"),
+            }
+        };
+
+        assert!(insert(&engine, "INSERT test_routing_types,host=a usage=1.0 1").success);
+
+        // a new series of the measurement can't add a field of a different type
+        let result = insert(&engine, "INSERT test_routing_types,host=b usage=true 2");
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("'usage'"));
+
+        // including when the series that has the field isn't loaded
+        let engine = ExecutionEngine::new();
+        assert!(!insert(&engine, "INSERT test_routing_types,host=b usage=2i 2").success);
+        assert!(insert(&engine, "INSERT test_routing_types,host=b usage=2.0 2").success);
+    }
+
+    #[test]
+    fn it_shows_series_and_fields() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_show", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let show = |query: &str| {
+            let mut query = String::from(query);
+            engine.execute(Action::Show(parse_show(&mut query).unwrap()))
+        };
+
+        let result = show("SHOW FIELDS FROM test_routing_show");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_show"))));
+
+        for query in ["INSERT test_routing_show,value2=true 1", "INSERT test_routing_show,value1=1.0,value2=false 3"] {
+            let mut query = String::from(query);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        match show("SHOW FIELDS FROM test_routing_show") {
+            Ok(ExecutionResult::Query(result)) => {
+                let columns: Vec<_> = result.records.fields.iter().map(|field| (field.name.as_str(), field.data_type.clone())).collect();
+                assert_eq!(columns, vec![
+                    ("name", DataType::String),
+                    ("type", DataType::String),
+                    ("count", DataType::Integer),
+                    ("first", DataType::Timestamp),
+                    ("last", DataType::Timestamp),
+                ]);
+                assert_eq!(result.count, 2);
+                assert_eq!(result.records.elements, vec![
+                    DataValue::Timestamp(0), DataValue::from("value1"), DataValue::from("float"), DataValue::Integer(1), DataValue::Timestamp(3), DataValue::Timestamp(3),
+                    DataValue::Timestamp(0), DataValue::from("value2"), DataValue::from("bool"), DataValue::Integer(2), DataValue::Timestamp(1), DataValue::Timestamp(3),
+                ]);
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        // SHOW SERIES summarizes every series on disk, including those other tests are using, so
+        // it's covered by the tests of SeriesStorage::summarize instead
+    }
+
+    #[test]
+    fn it_shows_cache_stats() {
+        let engine = ExecutionEngine::new();
+        let mut query = String::from("SHOW STATS");
+        match engine.execute(Action::Show(parse_show(&mut query).unwrap())) {
+            Ok(ExecutionResult::Query(result)) => {
+                let columns: Vec<_> = result.records.fields.iter().map(|field| (field.name.as_str(), field.data_type.clone())).collect();
+                assert_eq!(columns, vec![("name", DataType::String), ("value", DataType::Integer)]);
+
+                // other tests use the same cache, so its values change as they run
+                let names: Vec<_> = result.records.elements.chunks(3).map(|row| row[1].clone()).collect();
+                assert_eq!(names, ["hits", "misses", "evictions", "blocks", "size", "capacity"].map(DataValue::from));
+                assert!(result.records.elements.chunks(3).all(|row| matches!(row[2], DataValue::Integer(value) if value >= 0)));
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[test]
+    fn it_queries_across_tags() {
+        let keys = ["test_routing_tags", "test_routing_tags,host=a,region=us", "test_routing_tags,host=b,region=us", "test_routing_tags,host=c,region=eu"];
+        for key in keys {
+            let _ = fs::remove_dir_all(format!("{}/{}", data_dir(), key));
+        }
+
+        let engine = ExecutionEngine::new();
+        for query in [
+            "INSERT test_routing_tags,host=a,region=us usage=1.0 1",
+            "INSERT test_routing_tags,region=us,host=b usage=3.0 2",
+            "INSERT test_routing_tags,host=c,region=eu usage=5.0 3",
+            "INSERT test_routing_tags,usage=7.0 4",
+        ] {
+            let mut query = String::from(query);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        // each set of tags is stored as a separate series, regardless of the order tags are given in
+        for key in keys {
+            assert!(Path::new(&format!("{}/{}", data_dir(), key)).is_dir(), "{}", key);
+        }
+
+        match select(&engine, "SELECT test_routing_tags[usage]") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements, vec![
+                DataValue::Timestamp(1), DataValue::from(1.0),
+                DataValue::Timestamp(2), DataValue::from(3.0),
+                DataValue::Timestamp(3), DataValue::from(5.0),
+                DataValue::Timestamp(4), DataValue::from(7.0),
+            ]),
+            _ => panic!("expected a query result"),
+        }
+
+        match select(&engine, "SELECT test_routing_tags[usage] WHERE region = 'us'") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements, vec![
+                DataValue::Timestamp(1), DataValue::from(1.0),
+                DataValue::Timestamp(2), DataValue::from(3.0),
+            ]),
+            _ => panic!("expected a query result"),
+        }
+
+        // series without a tag don't equal any value of it
+        match select(&engine, "SELECT test_routing_tags[usage] WHERE host != 'a' AND usage < 6") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements, vec![
+                DataValue::Timestamp(2), DataValue::from(3.0),
+                DataValue::Timestamp(3), DataValue::from(5.0),
+            ]),
+            _ => panic!("expected a query result"),
+        }
+
+        match select(&engine, "SELECT test_routing_tags[mean(usage)] WHERE region = 'us' OR region = 'eu'") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements, vec![
+                DataValue::Timestamp(1), DataValue::from(3.0),
+            ]),
+            _ => panic!("expected a query result"),
+        }
+
+        match select(&engine, "SELECT test_routing_tags[usage] WHERE host = 'd'") {
+            Ok(ExecutionResult::Query(result)) => assert!(result.records.elements.is_empty()),
+            _ => panic!("expected a query result"),
+        }
+
+        // a new engine finds the tagged series on disk
+        let engine = ExecutionEngine::new();
+        match select(&engine, "SELECT test_routing_tags[usage] WHERE host = 'c'") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(3), DataValue::from(5.0)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        let mut query = String::from("DROP SERIES test_routing_tags");
+        engine.execute(Action::Drop(parse_drop(&mut query).unwrap())).unwrap();
+        for key in keys {
+            assert!(!Path::new(&format!("{}/{}", data_dir(), key)).exists(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn it_stores_strings() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_strings", data_dir()));
+
+        // enough entries to flush a few blocks to disk
+        let engine = ExecutionEngine::new();
+        for i in 1..=250 {
+            let status = match i % 50 {
+                0 => r#""Error: \"timeout\"""#,
+                _ => "'OK'",
+            };
+            let mut query = format!("INSERT test_routing_strings status={},firmware='v1.{}',value={} {}", status, i / 100, i, i);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        let mut query = String::from("INSERT test_routing_strings status=1.0 251");
+        match engine.execute(Action::Insert(parse_insert(&mut query).unwrap())) {
+            Ok(ExecutionResult::Insert(result)) => assert!(!result.success),
+            _ => panic!("expected an insertion result"),
+        }
+
+        // a new engine has to read the strings back from disk
+        let engine = ExecutionEngine::new();
+        match select(&engine, "SELECT test_routing_strings[status, firmware] WHERE status != 'OK'") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.fields[0].data_type, DataType::String);
+                assert_eq!(result.records.elements, [50, 100, 150, 200, 250].into_iter()
+                    .flat_map(|i| [DataValue::Timestamp(i), DataValue::from("Error: \"timeout\""), DataValue::String(format!("v1.{}", i / 100))])
+                    .collect::<Vec<_>>());
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        match select(&engine, "SELECT test_routing_strings[max(firmware), min(status)]") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.elements, vec![DataValue::Timestamp(1), DataValue::from("v1.2"), DataValue::from("Error: \"timeout\"")]);
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[test]
+    fn it_stores_integers() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_integers", data_dir()));
+
+        // counters beyond 2^53 can't be represented exactly as floats
+        let base = 1i64 << 60;
+        let engine = ExecutionEngine::new();
+        for i in 1..=250 {
+            let mut query = format!("INSERT test_routing_integers count={}i,delta=-{}i {}", base + i, i % 7, i);
+            engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
+        }
+
+        // a new engine has to read the integers back from disk
+        let engine = ExecutionEngine::new();
+        match select(&engine, "SELECT test_routing_integers[count, delta] WHERE count > 1152921504606847220i") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.fields[0].data_type, DataType::Integer);
+                assert_eq!(result.records.elements, (245..=250)
+                    .flat_map(|i| [DataValue::Timestamp(i), DataValue::from(base + i), DataValue::from(-(i % 7))])
+                    .collect::<Vec<_>>());
+            }
+            _ => panic!("expected a query result"),
+        }
+
+        match select(&engine, "SELECT test_routing_integers[max(count), min(delta), mean(delta)]") {
+            Ok(ExecutionResult::Query(result)) => {
+                assert_eq!(result.records.fields[0].data_type, DataType::Integer);
+                assert_eq!(result.records.elements[1..3], [DataValue::from(base + 250), DataValue::from(-6i64)]);
+            }
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[test]
+    fn it_inserts_batches() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_batch", data_dir()));
+        let _ = fs::remove_dir_all(format!("{}/test_routing_batch,host=a", data_dir()));
+        let _ = fs::remove_dir_all(format!("{}/test_routing_batch,host=b", data_dir()));
+        let _ = fs::remove_dir_all(format!("{}/test_routing_batch_mem", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let mut query = (1..=100)
+            .map(|i| format!("test_routing_batch,host={} value={} {}\ntest_routing_batch_mem used={}i {}", ["a", "b"][i % 2], i, i, i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        query.insert_str(0, "INSERT ");
+
+        match engine.execute(Action::Insert(parse_insert(&mut query).unwrap())) {
+            Ok(ExecutionResult::Insert(result)) => {
+                assert!(result.success);
+                assert_eq!(result.rows_inserted, 200);
+                assert!(result.failed_rows.is_empty());
+            }
+            _ => panic!("expected an insertion result"),
+        }
+
+        match select(&engine, "SELECT test_routing_batch[value] WHERE host = 'a'") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.count, 50),
+            _ => panic!("expected a query result"),
+        }
+        match select(&engine, "SELECT test_routing_batch_mem[max(used)]") {
+            Ok(ExecutionResult::Query(result)) => assert_eq!(result.records.elements[1], DataValue::from(100i64)),
+            _ => panic!("expected a query result"),
+        }
+
+        // entries that can't be inserted don't prevent the others from being inserted
+        let mut query = String::from("INSERT test_routing_batch_mem used=1.0 101\ntest_routing_batch_mem used=102i 102\ntest_routing_batch_mem used=true 103");
+        match engine.execute(Action::Insert(parse_insert(&mut query).unwrap())) {
+            Ok(ExecutionResult::Insert(result)) => {
+                assert!(!result.success);
+                assert_eq!(result.rows_inserted, 1);
+                assert_eq!(result.failed_rows, vec![1, 3]);
+                assert!(result.error.unwrap().starts_with("row 1: "));
+            }
+            _ => panic!("expected an insertion result"),
+        }
+    }
+
+    #[test]
+    fn it_does_not_find_missing_series() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_missing", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let result = select(&engine, "SELECT test_routing_missing");
+        assert_eq!(result.err(), Some(ExecutionError::SeriesNotFound(String::from("test_routing_missing"))));
+        assert!(!Path::new(&format!("{}/test_routing_missing", data_dir())).exists());
+    }
+
+    #[test]
+    fn it_does_not_find_missing_fields() {
+        let _ = fs::remove_dir_all(format!("{}/test_routing_missing_field", data_dir()));
+
+        let engine = ExecutionEngine::new();
+        let mut query = String::from("INSERT test_routing_missing_field value=1.0 1");
+        engine.execute(Action::Insert(parse_insert(&mut query).unwrap())).unwrap();
 
-        ExecutionResult::Insert(InsertionResult { success: true })
+        let result = select(&engine, "SELECT test_routing_missing_field[value, valeu]");
+        assert_eq!(result.err(), Some(ExecutionError::FieldNotFound(String::from("valeu"))));
     }
 }
@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+
+use crate::{DataValue, RecordCollection};
+use crate::lang::Aggregation;
+use crate::wire_protocol::{DataType, FieldDescription};
+
+impl Aggregation {
+    /// The name of the aggregation, as written in a query.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Last => "last",
+            Aggregation::First => "first",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+        }
+    }
+
+    /// Whether values of the given type can be aggregated. Only numbers and bools have a mean.
+    pub fn supports(&self, input: &DataType) -> bool {
+        match self {
+            Aggregation::Mean => matches!(input, DataType::Float | DataType::Bool | DataType::Integer),
+            _ => true,
+        }
+    }
+
+    /// The data type of the result of aggregating values of the given type.
+    pub fn data_type(&self, input: &DataType) -> DataType {
+        match self {
+            Aggregation::Mean => DataType::Float,
+            _ => input.clone(),
+        }
+    }
+
+    /// Aggregate a sequence of values into a single value. Missing (None) values are ignored, and
+    /// if there are no values at all, the result is None as well.
+    ///
+    /// The mean of bools is the fraction of them that are true. Integers are summed exactly, so
+    /// that the mean of large integers isn't thrown off by rounding each of them to a float.
+    pub fn apply<'a, I>(&self, values: I) -> DataValue
+        where
+            I: Iterator<Item=&'a DataValue>
+    {
+        let mut values = values.filter(|v| **v != DataValue::None);
+
+        match self {
+            Aggregation::First => values.next().cloned().unwrap_or(DataValue::None),
+            Aggregation::Last => values.last().cloned().unwrap_or(DataValue::None),
+            Aggregation::Min => extreme(values, Ordering::Less),
+            Aggregation::Max => extreme(values, Ordering::Greater),
+            Aggregation::Mean => {
+                let mut count = 0;
+                let mut sum = 0.0;
+                let mut int_sum = 0i128;
+                for value in values {
+                    match value {
+                        DataValue::Float(f) => sum += f,
+                        DataValue::Bool(b) => sum += *b as u8 as f64,
+                        DataValue::Integer(i) => int_sum += *i as i128,
+                        _ => continue,
+                    }
+                    count += 1;
+                }
+
+                match count {
+                    0 => DataValue::None,
+                    _ => DataValue::Float((int_sum as f64 + sum) / count as f64),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the value that compares as `ordering` against all others, i.e. the minimum for
+/// Ordering::Less, and the maximum for Ordering::Greater. Values that can't be compared, such as
+/// NaN, are skipped.
+#[inline]
+fn extreme<'a, I>(values: I, ordering: Ordering) -> DataValue
+    where
+        I: Iterator<Item=&'a DataValue>
+{
+    let mut result = DataValue::None;
+    for value in values {
+        if result == DataValue::None {
+            if value.partial_cmp(value).is_some() {
+                result = value.clone();
+            }
+        } else if value.partial_cmp(&result) == Some(ordering) {
+            result = value.clone();
+        }
+    }
+
+    result
+}
+
+/// Aggregate the rows of a record collection, applying `aggregations[i]` to the i-th field.
+///
+/// If an interval is given, rows are grouped into buckets of that width, aligned to the Unix epoch,
+/// and one row is returned for each non-empty bucket, timestamped with the start of the bucket.
+/// Otherwise, all rows are aggregated into a single row, timestamped with `start`, if given, or the
+/// time of the first row.
+pub fn aggregate_records(records: &RecordCollection, aggregations: &[Aggregation], fields: Vec<FieldDescription>,
+                         interval: Option<i64>, start: Option<i64>) -> RecordCollection {
+    let row_len = records.fields.len() + 1;
+    let rows: Vec<_> = records.elements.chunks_exact(row_len).collect();
+    if rows.is_empty() {
+        return RecordCollection { fields, elements: vec![] };
+    }
+
+    let time = |row: &[DataValue]| match row[0] {
+        DataValue::Timestamp(t) => t,
+        _ => unreachable!("the first element of a row is always its timestamp"),
+    };
+
+    let bucket = |t: i64| match interval {
+        Some(interval) => t - t.rem_euclid(interval),
+        None => 0,
+    };
+
+    let mut elements = vec![];
+    let mut bucket_start = 0;
+    while bucket_start < rows.len() {
+        let key = bucket(time(rows[bucket_start]));
+        let mut bucket_end = bucket_start + 1;
+        while bucket_end < rows.len() && bucket(time(rows[bucket_end])) == key {
+            bucket_end += 1;
+        }
+
+        let timestamp = match interval {
+            Some(_) => key,
+            None => start.unwrap_or_else(|| time(rows[0])),
+        };
+        elements.push(DataValue::Timestamp(timestamp));
+
+        let bucket_rows = &rows[bucket_start..bucket_end];
+        for (i, aggregation) in aggregations.iter().enumerate() {
+            elements.push(aggregation.apply(bucket_rows.iter().map(|row| &row[i + 1])));
+        }
+
+        bucket_start = bucket_end;
+    }
+
+    RecordCollection { fields, elements }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DataValue, RecordCollection};
+    use crate::execution::aggregate::aggregate_records;
+    use crate::lang::Aggregation;
+    use crate::wire_protocol::{DataType, FieldDescription};
+
+    #[test]
+    fn applies_aggregations() {
+        let values = [DataValue::from(2.0), DataValue::None, DataValue::from(1.0), DataValue::from(6.0)];
+
+        assert_eq!(Aggregation::First.apply(values.iter()), DataValue::from(2.0));
+        assert_eq!(Aggregation::Last.apply(values.iter()), DataValue::from(6.0));
+        assert_eq!(Aggregation::Min.apply(values.iter()), DataValue::from(1.0));
+        assert_eq!(Aggregation::Max.apply(values.iter()), DataValue::from(6.0));
+        assert_eq!(Aggregation::Mean.apply(values.iter()), DataValue::from(3.0));
+
+        let bools = [DataValue::from(true), DataValue::from(false), DataValue::from(true), DataValue::from(true)];
+        assert_eq!(Aggregation::Mean.apply(bools.iter()), DataValue::from(0.75));
+        assert_eq!(Aggregation::Min.apply(bools.iter()), DataValue::from(false));
+
+        // integers keep their type, except for the mean, which is summed without losing precision
+        let ints = [DataValue::from(i64::MAX), DataValue::None, DataValue::from(i64::MAX - 2), DataValue::from(-5i64)];
+        assert_eq!(Aggregation::Max.apply(ints.iter()), DataValue::from(i64::MAX));
+        assert_eq!(Aggregation::Min.apply(ints.iter()), DataValue::from(-5i64));
+        assert_eq!(Aggregation::Last.apply(ints.iter()), DataValue::from(-5i64));
+        assert_eq!(Aggregation::Mean.apply(ints[..3].iter()), DataValue::from((i64::MAX - 1) as f64));
+
+        assert_eq!(Aggregation::Mean.apply([DataValue::None].iter()), DataValue::None);
+        assert_eq!(Aggregation::Max.apply([].iter()), DataValue::None);
+    }
+
+    #[test]
+    fn aggregates_buckets() {
+        let records = RecordCollection {
+            fields: vec![FieldDescription { name: String::from("value1"), data_type: DataType::Float }],
+            elements: vec![
+                DataValue::Timestamp(1), DataValue::from(1.0),
+                DataValue::Timestamp(5), DataValue::from(3.0),
+                DataValue::Timestamp(10), DataValue::from(4.0),
+                DataValue::Timestamp(35), DataValue::from(5.0),
+            ],
+        };
+        let fields = || vec![FieldDescription { name: String::from("mean(value1)"), data_type: DataType::Float }];
+
+        let result = aggregate_records(&records, &[Aggregation::Mean], fields(), Some(10), None);
+        assert_eq!(result.elements, vec![
+            DataValue::Timestamp(0), DataValue::from(2.0),
+            DataValue::Timestamp(10), DataValue::from(4.0),
+            DataValue::Timestamp(30), DataValue::from(5.0),
+        ]);
+
+        let result = aggregate_records(&records, &[Aggregation::Mean], fields(), None, None);
+        assert_eq!(result.elements, vec![DataValue::Timestamp(1), DataValue::from(3.25)]);
+
+        let result = aggregate_records(&records, &[Aggregation::Mean], fields(), None, Some(0));
+        assert_eq!(result.elements, vec![DataValue::Timestamp(0), DataValue::from(3.25)]);
+    }
+}
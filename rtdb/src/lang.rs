@@ -1,13 +1,31 @@
+use std::cmp::Ordering;
+
+use crate::DataValue;
+use crate::lang::delete::Deletion;
+use crate::lang::drop::DropTarget;
+use crate::lang::duplicates::Duplicates;
 use crate::lang::insert::Insertion;
+use crate::lang::retention::Retention;
+use crate::lang::show::ShowTarget;
 
-mod util;
+pub(crate) mod util;
 pub mod query;
 pub mod insert;
+pub mod retention;
+pub mod duplicates;
+pub mod delete;
+pub mod drop;
+pub mod show;
 
 #[derive(Debug, PartialEq)]
 pub enum Action<'a> {
     Select(SelectQuery<'a>),
-    Insert(Insertion),
+    Insert(Vec<Insertion>),
+    CreateRetention(Retention),
+    CreateDuplicates(Duplicates),
+    Delete(Deletion),
+    Drop(DropTarget),
+    Show(ShowTarget),
 }
 
 #[derive(Debug, PartialEq)]
@@ -18,10 +36,17 @@ pub struct SelectQuery<'a> {
     pub start: Option<i64>,
     pub end: Option<i64>,
 
-    // TODO: filters, group by, sort by, where
+    pub condition: Option<Condition<'a>>,
+
+    /// Width of the time buckets that rows are grouped into, in nanoseconds, as given by
+    /// `GROUP BY time(<interval>)`. Fields selected without an aggregation take the last value of
+    /// each bucket, as if they were selected with `last()`.
+    pub interval: Option<i64>,
+
+    // TODO: sort by
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Aggregation {
     Mean,
     Last,
@@ -42,6 +67,130 @@ pub struct SelectExpression<'a> {
     pub expression: Selection<'a>,
     pub aggregator: Aggregation,
 }
+
+/// A boolean expression over the values of a row, as given by a WHERE clause.
+#[derive(Debug, PartialEq)]
+pub enum Condition<'a> {
+    Comparison(Comparison<'a>),
+    Tag(TagPredicate<'a>),
+    And(Box<Condition<'a>>, Box<Condition<'a>>),
+    Or(Box<Condition<'a>>, Box<Condition<'a>>),
+    Not(Box<Condition<'a>>),
+}
+
+/// A comparison between the value of a field and a literal, e.g. `temperature > 80`.
+#[derive(Debug, PartialEq)]
+pub struct Comparison<'a> {
+    pub field: &'a str,
+    pub operator: Operator,
+    pub value: DataValue,
+}
+
+/// A comparison between the value of a tag and a quoted literal, e.g. `host = 'a'`. Tags can only
+/// be compared for (in)equality.
+#[derive(Debug, PartialEq)]
+pub struct TagPredicate<'a> {
+    pub key: &'a str,
+    pub operator: Operator,
+    pub value: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl<'a> Condition<'a> {
+    /// Returns the names of all fields referenced by this condition, without duplicates. Tags
+    /// aren't fields, so they're not included.
+    pub fn fields(&self) -> Vec<&'a str> {
+        let mut fields = vec![];
+        self.collect_fields(&mut fields);
+        fields
+    }
+
+    fn collect_fields(&self, fields: &mut Vec<&'a str>) {
+        match self {
+            Condition::Comparison(comparison) => {
+                if !fields.contains(&comparison.field) {
+                    fields.push(comparison.field);
+                }
+            }
+            Condition::Tag(_) => {}
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.collect_fields(fields);
+                b.collect_fields(fields);
+            }
+            Condition::Not(a) => a.collect_fields(fields),
+        }
+    }
+
+    /// Evaluate the condition against a single row, where `row[i]` holds the value of the field
+    /// named `columns[i]`, and `tags` are the tags of the series the row belongs to.
+    ///
+    /// A comparison against a field that has no value in the row, or whose value is of a different
+    /// type than the literal it's compared to, is false. A series without a given tag compares as
+    /// not equal to any value of it.
+    pub fn evaluate(&self, row: &[DataValue], columns: &[&str], tags: &[(String, String)]) -> bool {
+        match self {
+            Condition::Comparison(comparison) => {
+                let value = match columns.iter().position(|&c| c == comparison.field) {
+                    Some(i) => &row[i],
+                    None => return false,
+                };
+
+                match value.partial_cmp(&comparison.value) {
+                    None => false,
+                    Some(ordering) => match comparison.operator {
+                        Operator::Eq => ordering == Ordering::Equal,
+                        Operator::NotEq => ordering != Ordering::Equal,
+                        Operator::Lt => ordering == Ordering::Less,
+                        Operator::LtEq => ordering != Ordering::Greater,
+                        Operator::Gt => ordering == Ordering::Greater,
+                        Operator::GtEq => ordering != Ordering::Less,
+                    }
+                }
+            }
+            Condition::Tag(predicate) => predicate.matches(tags),
+            Condition::And(a, b) => a.evaluate(row, columns, tags) && b.evaluate(row, columns, tags),
+            Condition::Or(a, b) => a.evaluate(row, columns, tags) || b.evaluate(row, columns, tags),
+            Condition::Not(a) => !a.evaluate(row, columns, tags),
+        }
+    }
+
+    /// Turn tag predicates on names for which `is_field` holds into comparisons against the
+    /// fields of those names. A quoted literal may be compared to either a tag or a string field,
+    /// which is only known once the series is, so such comparisons are parsed as tag predicates.
+    pub fn resolve_fields(self, is_field: &impl Fn(&str) -> bool) -> Condition<'a> {
+        match self {
+            Condition::Tag(predicate) if is_field(predicate.key) => Condition::Comparison(Comparison {
+                field: predicate.key,
+                operator: predicate.operator,
+                value: DataValue::String(predicate.value),
+            }),
+            Condition::And(a, b) => Condition::And(Box::new(a.resolve_fields(is_field)), Box::new(b.resolve_fields(is_field))),
+            Condition::Or(a, b) => Condition::Or(Box::new(a.resolve_fields(is_field)), Box::new(b.resolve_fields(is_field))),
+            Condition::Not(a) => Condition::Not(Box::new(a.resolve_fields(is_field))),
+            condition => condition,
+        }
+    }
+}
+
+impl<'a> TagPredicate<'a> {
+    /// Whether a series with the given tags satisfies the predicate.
+    pub fn matches(&self, tags: &[(String, String)]) -> bool {
+        let equal = tags.iter().any(|(key, value)| key == self.key && *value == self.value);
+        match self.operator {
+            Operator::NotEq => !equal,
+            _ => equal,
+        }
+    }
+}
 //
 // #[derive(Debug, PartialEq)]
 // pub struct FieldSelection<'a> {
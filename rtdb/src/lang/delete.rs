@@ -0,0 +1,107 @@
+use crate::lang::Selection;
+use crate::lang::query::{parse_fields, parse_time_range};
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_identifier, parse_keyword};
+
+/// Entries to be deleted from a series, as given by a statement of the form:
+///
+/// ```markdown
+/// DELETE FROM <series>[<field>, ...] AFTER <timestamp> BEFORE <timestamp>
+/// ```
+///
+/// where the fields and either end of the time range may be omitted, to delete from all fields
+/// and without a bound, respectively.
+#[derive(Debug, PartialEq)]
+pub struct Deletion {
+    pub series: String,
+
+    /// The fields to delete entries from, or all fields if empty.
+    pub fields: Vec<String>,
+
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+/// Attempt to parse a DELETE statement.
+pub fn parse_delete(raw_query: &mut str) -> Result<Deletion, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index = 0;
+    if !parse_keyword("delete", input, &mut index) {
+        return Err(String::from("expected DELETE at pos: 0"));
+    }
+
+    advance_whitespace(input, &mut index);
+    if !parse_keyword("from", input, &mut index) {
+        return Err(format!("expected FROM at pos: {}", index));
+    }
+
+    advance_whitespace(input, &mut index);
+    let (ok, series) = parse_identifier(input, &mut index);
+    if !ok {
+        return Err(format!("expected a series name at pos: {}", index));
+    }
+    let series = series.to_owned();
+
+    advance_whitespace(input, &mut index);
+    let start = index;
+    let mut selections = vec![];
+    parse_fields(input, &mut index, &mut selections)?;
+    let mut fields = Vec::with_capacity(selections.len());
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => fields.push(field.to_owned()),
+            Selection::Expression(_) => return Err(format!("expected only field names at pos: {}", start)),
+        }
+    }
+
+    advance_whitespace(input, &mut index);
+    let (start, end) = parse_time_range(input, &mut index)?;
+
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(Deletion { series, fields, start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::delete::{Deletion, parse_delete};
+
+    #[test]
+    fn parses_delete() {
+        let mut query = String::from("DELETE FROM test_series[value1, value2] AFTER 10 BEFORE 20");
+        assert_eq!(parse_delete(&mut query), Ok(Deletion {
+            series: String::from("test_series"),
+            fields: vec![String::from("value1"), String::from("value2")],
+            start: Some(10),
+            end: Some(20),
+        }));
+
+        let mut query = String::from("delete from test_series before 20");
+        assert_eq!(parse_delete(&mut query), Ok(Deletion {
+            series: String::from("test_series"),
+            fields: vec![],
+            start: None,
+            end: Some(20),
+        }));
+    }
+
+    #[test]
+    fn rejects_invalid_delete() {
+        for query in [
+            "DELETE",
+            "DELETE test_series",
+            "DELETE FROM",
+            "DELETE FROM test_series[value1",
+            "DELETE FROM test_series[mean(value1)]",
+            "DELETE FROM test_series[value1] AFTER",
+            "DELETE FROM test_series AFTER 10 extra",
+        ] {
+            let mut query = String::from(query);
+            assert!(parse_delete(&mut query).is_err(), "{}", query);
+        }
+    }
+}
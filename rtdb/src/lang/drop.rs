@@ -0,0 +1,94 @@
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_ascii, parse_identifier, parse_keyword};
+
+/// What to remove, as given by a statement of either form:
+///
+/// ```markdown
+/// DROP SERIES <series>
+/// DROP FIELD <series>.<field>
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum DropTarget {
+    Series(String),
+    Field { series: String, field: String },
+}
+
+/// Attempt to parse a DROP statement.
+pub fn parse_drop(raw_query: &mut str) -> Result<DropTarget, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index = 0;
+    if !parse_keyword("drop", input, &mut index) {
+        return Err(String::from("expected DROP at pos: 0"));
+    }
+
+    advance_whitespace(input, &mut index);
+    let target = if parse_keyword("series", input, &mut index) {
+        advance_whitespace(input, &mut index);
+        let (ok, series) = parse_identifier(input, &mut index);
+        if !ok {
+            return Err(format!("expected a series name at pos: {}", index));
+        }
+
+        DropTarget::Series(series.to_owned())
+    } else if parse_keyword("field", input, &mut index) {
+        advance_whitespace(input, &mut index);
+        let (ok, series) = parse_identifier(input, &mut index);
+        if !ok {
+            return Err(format!("expected a series name at pos: {}", index));
+        }
+
+        if !parse_ascii(".", input, &mut index) {
+            return Err(format!("expected '.' at pos: {}", index));
+        }
+
+        let (ok, field) = parse_identifier(input, &mut index);
+        if !ok {
+            return Err(format!("expected a field name at pos: {}", index));
+        }
+
+        DropTarget::Field { series: series.to_owned(), field: field.to_owned() }
+    } else {
+        return Err(format!("expected SERIES or FIELD at pos: {}", index));
+    };
+
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::drop::{DropTarget, parse_drop};
+
+    #[test]
+    fn parses_drop() {
+        let mut query = String::from("DROP SERIES test_series");
+        assert_eq!(parse_drop(&mut query), Ok(DropTarget::Series(String::from("test_series"))));
+
+        let mut query = String::from("drop field test_series.value1");
+        assert_eq!(parse_drop(&mut query), Ok(DropTarget::Field {
+            series: String::from("test_series"),
+            field: String::from("value1"),
+        }));
+    }
+
+    #[test]
+    fn rejects_invalid_drop() {
+        for query in [
+            "DROP",
+            "DROP test_series",
+            "DROP SERIES",
+            "DROP SERIES test_series extra",
+            "DROP FIELD test_series",
+            "DROP FIELD test_series.",
+            "DROP FIELD test_series value1",
+        ] {
+            let mut query = String::from(query);
+            assert!(parse_drop(&mut query).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_identifier, parse_keyword};
+use crate::storage::metadata::DuplicatePolicy;
+
+/// How entries at the same time as an existing entry are handled by a series, as set by a
+/// statement of the form:
+///
+/// ```markdown
+/// CREATE DUPLICATES <policy> ON <series>
+/// ```
+///
+/// where the policy is one of LAST_WRITE_WINS, KEEP_FIRST or REJECT.
+#[derive(Debug, PartialEq)]
+pub struct Duplicates {
+    pub series: String,
+    pub policy: DuplicatePolicy,
+}
+
+/// Attempt to parse a CREATE DUPLICATES statement.
+pub fn parse_create_duplicates(raw_query: &mut str) -> Result<Duplicates, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index = 0;
+    if !parse_keyword("create", input, &mut index) {
+        return Err(String::from("expected CREATE at pos: 0"));
+    }
+
+    advance_whitespace(input, &mut index);
+    if !parse_keyword("duplicates", input, &mut index) {
+        return Err(format!("expected DUPLICATES at pos: {}", index));
+    }
+
+    advance_whitespace(input, &mut index);
+    let start = index;
+    let policy = match parse_identifier(input, &mut index) {
+        (true, "last_write_wins") => DuplicatePolicy::LastWriteWins,
+        (true, "keep_first") => DuplicatePolicy::KeepFirst,
+        (true, "reject") => DuplicatePolicy::Reject,
+        _ => return Err(format!("expected LAST_WRITE_WINS, KEEP_FIRST or REJECT at pos: {}", start)),
+    };
+
+    advance_whitespace(input, &mut index);
+    if !parse_keyword("on", input, &mut index) {
+        return Err(format!("expected ON at pos: {}", index));
+    }
+
+    advance_whitespace(input, &mut index);
+    let (ok, series) = parse_identifier(input, &mut index);
+    if !ok {
+        return Err(format!("expected a series name at pos: {}", index));
+    }
+    let series = series.to_owned();
+
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(Duplicates { series, policy })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::duplicates::{Duplicates, parse_create_duplicates};
+    use crate::storage::metadata::DuplicatePolicy;
+
+    #[test]
+    fn parses_create_duplicates() {
+        let mut query = String::from("CREATE DUPLICATES KEEP_FIRST ON test_series");
+        assert_eq!(parse_create_duplicates(&mut query), Ok(Duplicates {
+            series: String::from("test_series"),
+            policy: DuplicatePolicy::KeepFirst,
+        }));
+
+        let mut query = String::from("create duplicates reject on test_series");
+        assert_eq!(parse_create_duplicates(&mut query).unwrap().policy, DuplicatePolicy::Reject);
+
+        let mut query = String::from("create duplicates last_write_wins on test_series");
+        assert_eq!(parse_create_duplicates(&mut query).unwrap().policy, DuplicatePolicy::LastWriteWins);
+    }
+
+    #[test]
+    fn rejects_invalid_duplicates() {
+        for query in [
+            "CREATE",
+            "CREATE DUPLICATES ON test_series",
+            "CREATE DUPLICATES KEEP_LAST ON test_series",
+            "CREATE DUPLICATES REJECT test_series",
+            "CREATE DUPLICATES REJECT ON",
+            "CREATE DUPLICATES REJECT ON test_series extra",
+        ] {
+            let mut query = String::from(query);
+            assert!(parse_create_duplicates(&mut query).is_err(), "{}", query);
+        }
+    }
+}
@@ -1,7 +1,4 @@
-use std::str::from_utf8;
-use crate::DataValue;
-
-use crate::lang::util::{advance_whitespace, parse_ascii, parse_identifier, parse_timestamp};
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_ascii, parse_identifier, parse_keyword, parse_tag_value, parse_timestamp, parse_value};
 use crate::storage::series::SeriesEntry;
 use crate::util::new_timestamp;
 
@@ -9,35 +6,51 @@ use crate::util::new_timestamp;
 #[derive(Debug, PartialEq)]
 pub struct Insertion {
     pub series: String,
+
+    /// Tags identifying which of the series' sets of entries the entry belongs to, in the order
+    /// they were given.
+    pub tags: Vec<(String, String)>,
     pub entry: SeriesEntry,
 }
 
-// TODO: generalize to strings
-/// Attempt to parse a value, starting from s at the given index.
-///
-/// A value may be a bool, in the form of an unqouted "true" or "false", or a float.
-/// TODO: support parsing strings
-fn parse_value<'a>(s: &'a [u8], index: &'a mut usize) -> Result<DataValue, String> {
-    if s[*index..].starts_with(b"true") {
-        *index += 4;
-        return Ok(DataValue::Bool(true));
-    } else if s[*index..].starts_with(b"false") {
-        *index += 5;
-        return Ok(DataValue::Bool(false));
-    }
+/// Attempt to parse a list of tags, e.g. `host=a,region=us-east`, which must be followed by
+/// whitespace and a list of fields. Leaves index unchanged on failure, since the list may be a
+/// list of fields instead.
+#[inline]
+fn parse_tags(s: &[u8], index: &mut usize) -> Option<Vec<(String, String)>> {
+    let mut i = *index;
+    let mut tags: Vec<(String, String)> = vec![];
+    loop {
+        let (ok, key) = parse_identifier(s, &mut i);
+        if !ok || !parse_ascii("=", s, &mut i) {
+            return None;
+        }
+
+        let (ok, value) = parse_tag_value(s, &mut i);
+        if !ok || tags.iter().any(|(k, _)| k == key) {
+            return None;
+        }
+        tags.push((key.to_owned(), value.to_owned()));
 
+        if i >= s.len() || !parse_ascii(",", s, &mut i) {
+            break;
+        }
+    }
 
-    if let Ok((val, len)) = fast_float::parse_partial::<f64, _>(from_utf8(&s[*index..]).unwrap()) {
-        *index += len;
-        return Ok(DataValue::Float(val))
+    // tags are separated from fields by whitespace, and fields always start with `<name>=`
+    let end = i;
+    advance_whitespace(s, &mut i);
+    let (ok, _) = parse_identifier(s, &mut i);
+    if end == i || !ok || i >= s.len() || s[i] != b'=' {
+        return None;
     }
 
-    Err(format!("failed to parse a value at pos: {}", index))
+    *index = end;
+    Some(tags)
 }
 
-// TODO: return option
 #[inline]
-fn parse_fields<'a>(s: &'a [u8], index: &'a mut usize, entry: &mut SeriesEntry) {
+fn parse_fields<'a>(s: &'a [u8], index: &'a mut usize, entry: &mut SeriesEntry) -> Result<(), String> {
     while *index < s.len() {
         advance_whitespace(s, index);
         let (success, field) = parse_identifier(s, index);
@@ -48,48 +61,124 @@ fn parse_fields<'a>(s: &'a [u8], index: &'a mut usize, entry: &mut SeriesEntry)
         entry.fields.push(field.to_owned());
 
         advance_whitespace(s, index);
-        parse_ascii("=", s, index);
+        if !parse_ascii("=", s, index) {
+            return Err(format!("expected '=' at pos: {}", index));
+        }
         advance_whitespace(s, index);
 
-        match parse_value(s, index) {
-            Err(e) => panic!("{}", e),
-            Ok(value) => {
-                entry.values.push(value);
-                *index += 1;
-            }
+        let value = parse_value(s, index)?;
+        entry.values.push(value);
+
+        advance_whitespace(s, index);
+        if !parse_ascii(",", s, index) {
+            break;
         }
     }
-}
 
-/// Attempt to parse an insert statement.
-/// TODO: return Result, and handle invalid statements
-pub fn parse_insert(raw_query: &mut str) -> Insertion {
-    raw_query.make_ascii_lowercase();
+    match entry.fields.is_empty() {
+        true => Err(format!("expected a field at pos: {}", index)),
+        false => Ok(()),
+    }
+}
 
-    let mut entry = SeriesEntry { values: vec![], fields: vec![], time: 0 };
+/// Returns the index of the newline ending the line that starts at index, or the end of s if it's
+/// the last line. Newlines within quoted strings don't end a line.
+#[inline]
+fn line_end(s: &[u8], index: usize) -> usize {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, &c) in s.iter().enumerate().skip(index) {
+        match quote {
+            None if c == b'\n' => return i,
+            None if c == b'\'' || c == b'"' => quote = Some(c),
+            None => {}
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == b'\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+        }
+    }
 
-    let input = raw_query.as_bytes();
-    let mut index: usize = 7; // start after "INSERT "
+    s.len()
+}
 
-    advance_whitespace(input, &mut index);
+/// Attempt to parse a single entry, e.g. `cpu,host=a usage=0.5 10`, which must end at the end of s.
+/// Entries without a timestamp are timestamped with the current time.
+fn parse_entry(s: &[u8], index: &mut usize) -> Result<Insertion, String> {
+    let mut entry = SeriesEntry { values: vec![], fields: vec![], time: 0 };
 
-    let (_, series) = parse_identifier(input, &mut index);
+    let (ok, series) = parse_identifier(s, index);
+    if !ok {
+        return Err(format!("expected a series name at pos: {}", index));
+    }
     let series = series.to_owned();
-    advance_whitespace(input, &mut index);
-    parse_fields(input, &mut index, &mut entry);
-    advance_whitespace(input, &mut index);
+    advance_whitespace(s, index);
+
+    // tags are optional, and otherwise the series name may be followed by a comma and its fields
+    let mut tags = vec![];
+    if parse_ascii(",", s, index) {
+        if let Some(parsed) = parse_tags(s, index) {
+            tags = parsed;
+        }
+    }
+    parse_fields(s, index, &mut entry)?;
+    advance_whitespace(s, index);
 
-    if index >= input.len() {
+    if *index >= s.len() {
         entry.time = new_timestamp();
-        return Insertion { series, entry };
+        return Ok(Insertion { series, tags, entry });
     }
 
-    entry.time = match parse_timestamp(input, &mut index) {
+    entry.time = match parse_timestamp(s, index) {
         Some(t) => t,
-        None => new_timestamp(),
+        None => return Err(format!("expected a timestamp at pos: {}", index)),
     };
 
-    Insertion { series, entry }
+    advance_whitespace(s, index);
+    if *index < s.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(Insertion { series, tags, entry })
+}
+
+/// Attempt to parse an insert statement, which holds one or more newline separated entries, e.g.
+///
+/// ```text
+/// INSERT cpu,host=a usage=0.5 10
+/// cpu,host=b usage=0.7 10
+/// mem,host=a used=1024i 10
+/// ```
+///
+/// Entries may belong to any number of series, and blank lines are ignored. A statement with any
+/// malformed entry inserts nothing, but batches aren't atomic otherwise: each entry is inserted on
+/// its own, so entries that can't be inserted, e.g. due to a mismatched data type, don't prevent
+/// the others from being inserted.
+pub fn parse_insert(raw_query: &mut str) -> Result<Vec<Insertion>, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index: usize = 0;
+    if !parse_keyword("insert", input, &mut index) {
+        return Err(String::from("expected INSERT at pos: 0"));
+    }
+
+    let mut insertions = vec![];
+    while index < input.len() {
+        // each entry is parsed from its own line, but positions in errors are still relative to
+        // the start of the statement
+        let end = line_end(input, index);
+        advance_whitespace(&input[..end], &mut index);
+        if index < end {
+            insertions.push(parse_entry(&input[..end], &mut index)?);
+        }
+        index = end + 1;
+    }
+
+    match insertions.is_empty() {
+        true => Err(format!("expected a series name at pos: {}", input.len())),
+        false => Ok(insertions),
+    }
 }
 
 #[cfg(test)]
@@ -101,42 +190,116 @@ mod tests {
     #[test]
     fn parses_insert() {
         let mut query = String::from("INSERT test_series,field1=1.0");
-        let entry = parse_insert(&mut query);
+        let entry = parse_insert(&mut query).unwrap();
         dbg!(entry);
 
         let mut query = String::from("INSERT test_series,value1=0.5,value2=1 1663644227213092171");
-        let entry = parse_insert(&mut query);
+        let entry = parse_insert(&mut query).unwrap();
         dbg!(entry);
     }
 
+    #[test]
+    fn parses_tags() {
+        let mut query = String::from("INSERT cpu,host=a,region=us-east.1 usage=0.5,idle=true 10");
+        let insertion = parse_insert(&mut query).unwrap().remove(0);
+        assert_eq!(insertion.series, "cpu");
+        assert_eq!(insertion.tags, vec![
+            (String::from("host"), String::from("a")),
+            (String::from("region"), String::from("us-east.1")),
+        ]);
+        assert_eq!(insertion.entry, SeriesEntry {
+            fields: vec![String::from("usage"), String::from("idle")],
+            values: vec![DataValue::from(0.5), DataValue::from(true)],
+            time: 10,
+        });
+
+        // without whitespace before the fields, the list is a list of fields
+        let mut query = String::from("INSERT cpu,usage=0.5 10");
+        let insertion = parse_insert(&mut query).unwrap().remove(0);
+        assert!(insertion.tags.is_empty());
+        assert_eq!(insertion.entry.fields, vec![String::from("usage")]);
+        assert_eq!(insertion.entry.time, 10);
+
+        let mut query = String::from("INSERT cpu,host=a,host=b usage=0.5");
+        assert!(parse_insert(&mut query).is_err());
+    }
+
+    #[test]
+    fn parses_integers() {
+        let mut query = String::from("INSERT requests count=42i,errors=-3I,load=1.5 10");
+        let insertion = parse_insert(&mut query).unwrap().remove(0);
+        assert_eq!(insertion.entry.values, vec![DataValue::Integer(42), DataValue::Integer(-3), DataValue::from(1.5)]);
+
+        let mut query = String::from("INSERT requests count=99999999999999999999i 10");
+        assert!(parse_insert(&mut query).is_err());
+    }
+
+    #[test]
+    fn parses_batches() {
+        let mut query = String::from("INSERT cpu,host=a usage=0.5 10\n\ncpu,host=b usage=0.7 10\r\n  mem used=1024i,note='a\nb' 20\n");
+        let insertions = parse_insert(&mut query).unwrap();
+        assert_eq!(insertions.len(), 3);
+        assert_eq!(insertions[1].tags, vec![(String::from("host"), String::from("b"))]);
+        assert_eq!(insertions[2].series, "mem");
+        assert_eq!(insertions[2].entry, SeriesEntry {
+            fields: vec![String::from("used"), String::from("note")],
+            values: vec![DataValue::Integer(1024), DataValue::from("a\nb")],
+            time: 20,
+        });
+
+        let mut query = String::from("INSERT\ncpu usage=0.5 10\ncpu usage=0.7 20");
+        assert_eq!(parse_insert(&mut query).unwrap().len(), 2);
+
+        // positions in errors are relative to the start of the statement
+        let mut query = String::from("INSERT cpu usage=0.5 10\ncpu usage=abc 20");
+        assert_eq!(parse_insert(&mut query), Err(String::from("failed to parse a value at pos: 34")));
+
+        let mut query = String::from("INSERT \n \n");
+        assert!(parse_insert(&mut query).is_err());
+    }
+
     #[test]
     fn parses_fields() {
         let mut index = 0;
         let mut entry = SeriesEntry { fields: vec![], values: vec![], time: 0 };
-        parse_fields(b"field1=1", &mut index, &mut entry);
+        parse_fields(b"field1=1", &mut index, &mut entry).unwrap();
         assert_eq!(entry.fields[0], String::from("field1"));
         assert_eq!(entry.values[0], DataValue::from(1.0));
         assert_eq!(entry.time, 0);
 
         let mut index = 0;
         let mut entry = SeriesEntry { fields: vec![], values: vec![], time: 0 };
-        parse_fields(b"field1=1.0", &mut index, &mut entry);
+        parse_fields(b"field1=1.0", &mut index, &mut entry).unwrap();
         assert_eq!(entry.fields[0], String::from("field1"));
         assert_eq!(entry.values[0], DataValue::from(1.0));
         assert_eq!(entry.time, 0);
 
         let mut index = 0;
         let mut entry = SeriesEntry { fields: vec![], values: vec![], time: 0 };
-        parse_fields(b"field1=1.0,field2=3.01", &mut index, &mut entry);
+        parse_fields(b"field1=1.0,field2=3.01", &mut index, &mut entry).unwrap();
         assert_eq!(entry.fields, vec![String::from("field1"), String::from("field2")]);
         assert_eq!(entry.values, vec![DataValue::from(1.0), DataValue::from(3.01)]);
         assert_eq!(entry.time, 0);
 
         let mut index = 0;
         let mut entry = SeriesEntry { fields: vec![], values: vec![], time: 0 };
-        parse_fields(b"field1=1.0, field2=true", &mut index, &mut entry);
+        parse_fields(b"field1=1.0, field2=true", &mut index, &mut entry).unwrap();
         assert_eq!(entry.fields, vec![String::from("field1"), String::from("field2")]);
         assert_eq!(entry.values, vec![DataValue::from(1.0), DataValue::from(true)]);
         assert_eq!(entry.time, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_invalid_inserts() {
+        for query in [
+            "INSERT",
+            "INSERT 1series,field1=1.0",
+            "INSERT test_series",
+            "INSERT test_series,field1",
+            "INSERT test_series,field1=abc",
+            "INSERT test_series,field1=1.0 yesterday",
+        ] {
+            assert!(parse_insert(&mut String::from(query)).is_err(), "{}", query);
+        }
+    }
+}
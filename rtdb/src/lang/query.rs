@@ -1,81 +1,76 @@
-use std::str::from_utf8;
+use crate::DataValue;
+use crate::lang::{Aggregation, Comparison, Condition, Operator, SelectExpression, Selection, SelectQuery, TagPredicate};
 
-use crate::lang::{Aggregation, SelectExpression, Selection, SelectQuery};
-
-use crate::lang::util::{advance_whitespace, parse_ascii, parse_identifier, parse_timestamp};
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_ascii, parse_duration, parse_identifier, parse_keyword, parse_timestamp, parse_value};
 
 /// Parse a full SELECT query.
-pub fn parse_select(raw_query: &mut str) -> SelectQuery {
-    raw_query.make_ascii_lowercase();
+pub fn parse_select(raw_query: &mut str) -> Result<SelectQuery<'_>, String> {
+    make_ascii_lowercase_unquoted(raw_query);
 
-    let mut index: usize = 0;
     let input = raw_query.as_bytes();
-
-    index = 6;
+    let mut index = 0;
+    if !parse_keyword("select", input, &mut index) {
+        return Err(String::from("expected SELECT at pos: 0"));
+    }
     advance_whitespace(input, &mut index);
 
-    // parse series name
-    // let (success, a) = parse_identifier(&input, &mut index);
-    let start_index = index;
-    while index < input.len() && input[index] != b'[' && input[index] != b' ' {
-        index += 1;
+    // parse series name, which is also the name of its directory, so it must be a valid identifier
+    let (ok, series_name) = parse_identifier(input, &mut index);
+    if !ok {
+        return Err(format!("expected a series name at pos: {}", index));
     }
 
-    // I wonder if we could avoid this copy, since under the hood this is basically memcpy
-    //  TODO: actually, if we switch to parse_identifier, we probably can
-    let series_name = from_utf8(&input[start_index..index]).unwrap();
-
-    let mut query = SelectQuery { series: series_name, selections: vec![], start: None, end: None };
-    if index == input.len() {
-        return query;
-    }
+    let mut query = SelectQuery { series: series_name, selections: vec![], start: None, end: None, condition: None, interval: None };
 
     advance_whitespace(input, &mut index);
-    parse_fields(input, &mut index, &mut query.selections);
+    parse_fields(input, &mut index, &mut query.selections)?;
+    advance_whitespace(input, &mut index);
+    (query.start, query.end) = parse_time_range(input, &mut index)?;
     advance_whitespace(input, &mut index);
-    parse_time_range(input, &mut index, &mut query);
 
-    // TODO: parse WHERE
+    if parse_keyword("where", input, &mut index) {
+        query.condition = Some(parse_condition(input, &mut index, 0)?);
+    }
 
-    // TODO: parse GROUP BY
+    advance_whitespace(input, &mut index);
+    parse_group_by(input, &mut index, &mut query)?;
 
-    // dbg!(&query);
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
 
-    query
+    Ok(query)
 }
 
-fn parse_fields<'a>(s: &'a [u8], index: &mut usize, fields: &mut Vec<Selection<'a>>) {
+/// Parses an optional list of field selections, of the form `[<selection>, ...]`, where each
+/// selection is a field name, optionally wrapped in an aggregation.
+pub(crate) fn parse_fields<'a>(s: &'a [u8], index: &mut usize, fields: &mut Vec<Selection<'a>>) -> Result<(), String> {
     if !parse_ascii("[", s, index) {
-        return;
+        return Ok(());
     }
 
-    let mut i = *index;
-    while i < s.len() {
-        advance_whitespace(s, &mut i);
-        match s[i] {
-            b',' => {
-                i += 1;
-            }
-            b']' => {
-                i += 1;
-                break;
-            }
-            _ => {
-                if let Some(selection) = parse_field_selection(s, &mut i) {
-                    fields.push(selection);
-                }
+    loop {
+        advance_whitespace(s, index);
+        match s.get(*index) {
+            None => return Err(String::from("expected ']', found end of query")),
+            Some(b',') => *index += 1,
+            Some(b']') => {
+                *index += 1;
+                return Ok(());
             }
+            Some(_) => fields.push(parse_field_selection(s, index)?),
         }
     }
-
-    *index = i;
 }
 
 #[inline]
-fn parse_field_selection<'a>(s: &'a [u8], index: &mut usize) -> Option<Selection<'a>> {
+fn parse_field_selection<'a>(s: &'a [u8], index: &mut usize) -> Result<Selection<'a>, String> {
     let aggregator =
         if parse_ascii("last(", s, index) {
             Some(Aggregation::Last)
+        } else if parse_ascii("first(", s, index) {
+            Some(Aggregation::First)
         } else if parse_ascii("mean(", s, index) {
             Some(Aggregation::Mean)
         } else if parse_ascii("max(", s, index) {
@@ -87,20 +82,26 @@ fn parse_field_selection<'a>(s: &'a [u8], index: &mut usize) -> Option<Selection
         };
 
 
+    let start = *index;
     let (ok, ident) = parse_identifier(s, index);
     if !ok {
-        return None;
+        return Err(format!("expected a field name at pos: {}", index));
     }
 
     let selection = match aggregator {
         Some(aggregator) => {
-            parse_ascii(")", s, index);
+            if s.get(*index) == Some(&b'(') {
+                return Err(format!("nested aggregations are not supported at pos: {}", start));
+            }
+            if !parse_ascii(")", s, index) {
+                return Err(format!("expected ')' at pos: {}", index));
+            }
             Selection::Expression(Box::new(SelectExpression { expression: Selection::Field(ident), aggregator }))
         }
         None => Selection::Field(ident)
     };
 
-    Some(selection)
+    Ok(selection)
 }
 
 /// Parses a time range from one of the following formats:
@@ -110,74 +111,234 @@ fn parse_field_selection<'a>(s: &'a [u8], index: &mut usize) -> Option<Selection
 /// BEFORE <timestamp>
 /// AFTER <timestamp> BEFORE <timestamp>
 /// ```
-/// and updates the given query.
-fn parse_time_range<'a>(s: &'a [u8], mut index: &mut usize, query: &mut SelectQuery<'a>) {
-    if parse_ascii("after", &s, &mut index) {
-        advance_whitespace(s, &mut index);
-        query.start = parse_timestamp(s, &mut index);
+/// and returns its start and end, either of which may be open.
+pub(crate) fn parse_time_range(s: &[u8], index: &mut usize) -> Result<(Option<i64>, Option<i64>), String> {
+    let mut range = (None, None);
+    if parse_keyword("after", s, index) {
+        advance_whitespace(s, index);
+        match parse_timestamp(s, index) {
+            Some(start) => range.0 = Some(start),
+            None => return Err(format!("expected a timestamp at pos: {}", index)),
+        }
+    }
+
+    advance_whitespace(s, index);
+    if parse_keyword("before", s, index) {
+        advance_whitespace(s, index);
+        match parse_timestamp(s, index) {
+            Some(end) => range.1 = Some(end),
+            None => return Err(format!("expected a timestamp at pos: {}", index)),
+        }
+    }
+
+    Ok(range)
+}
+
+/// Parses a GROUP BY clause, of the form:
+///
+/// ```markdown
+/// GROUP BY time(<duration>)
+/// ```
+/// and updates the given query. Does nothing if there is no GROUP BY clause.
+fn parse_group_by(s: &[u8], index: &mut usize, query: &mut SelectQuery) -> Result<(), String> {
+    if !parse_keyword("group", s, index) {
+        return Ok(());
+    }
+
+    advance_whitespace(s, index);
+    if !parse_keyword("by", s, index) {
+        return Err(format!("expected BY at pos: {}", index));
+    }
+
+    advance_whitespace(s, index);
+    if !parse_ascii("time(", s, index) {
+        return Err(format!("expected time(<interval>) at pos: {}", index));
+    }
+
+    advance_whitespace(s, index);
+    let interval = match parse_duration(s, index) {
+        Some(interval) if interval > 0 => interval,
+        _ => return Err(format!("expected a positive duration at pos: {}", index)),
+    };
+
+    advance_whitespace(s, index);
+    if !parse_ascii(")", s, index) {
+        return Err(format!("expected ')' at pos: {}", index));
+    }
+
+    query.interval = Some(interval);
+    Ok(())
+}
+
+/// The max number of parentheses and NOTs a condition may be nested in.
+const MAX_CONDITION_DEPTH: usize = 64;
+
+/// Parses the condition of a WHERE clause, which may be any combination of the following:
+///
+/// ```markdown
+/// <field> <operator> <value>
+/// NOT <condition>
+/// <condition> AND <condition>
+/// <condition> OR <condition>
+/// (<condition>)
+/// ```
+/// where operator is one of `=`, `!=`, `<`, `<=`, `>` or `>=`. AND binds tighter than OR. A quoted
+/// value compared with `=` or `!=`, e.g. `host = 'a'`, compares a tag rather than a field, unless
+/// the series has a field of that name, see [Condition::resolve_fields].
+///
+/// Conditions are parsed recursively, so `depth` counts the parentheses and NOTs the condition is
+/// nested in, and conditions nested deeper than [MAX_CONDITION_DEPTH] are rejected rather than
+/// overflowing the stack.
+fn parse_condition<'a>(s: &'a [u8], index: &mut usize, depth: usize) -> Result<Condition<'a>, String> {
+    let mut condition = parse_conjunction(s, index, depth)?;
+
+    advance_whitespace(s, index);
+    while parse_keyword("or", s, index) {
+        let rhs = parse_conjunction(s, index, depth)?;
+        condition = Condition::Or(Box::new(condition), Box::new(rhs));
+        advance_whitespace(s, index);
+    }
+
+    Ok(condition)
+}
+
+fn parse_conjunction<'a>(s: &'a [u8], index: &mut usize, depth: usize) -> Result<Condition<'a>, String> {
+    let mut condition = parse_unary_condition(s, index, depth)?;
+
+    advance_whitespace(s, index);
+    while parse_keyword("and", s, index) {
+        let rhs = parse_unary_condition(s, index, depth)?;
+        condition = Condition::And(Box::new(condition), Box::new(rhs));
+        advance_whitespace(s, index);
+    }
+
+    Ok(condition)
+}
+
+fn parse_unary_condition<'a>(s: &'a [u8], index: &mut usize, depth: usize) -> Result<Condition<'a>, String> {
+    advance_whitespace(s, index);
+    if depth > MAX_CONDITION_DEPTH {
+        return Err(format!("WHERE clause nested too deeply at pos: {}", index));
+    }
+
+    if parse_keyword("not", s, index) {
+        let condition = parse_unary_condition(s, index, depth + 1)?;
+        return Ok(Condition::Not(Box::new(condition)));
+    }
+
+    if parse_ascii("(", s, index) {
+        let condition = parse_condition(s, index, depth + 1)?;
+        advance_whitespace(s, index);
+        if !parse_ascii(")", s, index) {
+            return Err(format!("expected ')' at pos: {}", index));
+        }
+        return Ok(condition);
+    }
+
+    parse_comparison(s, index)
+}
+
+/// Parses a comparison of a field to a value, or of a tag to a quoted value.
+fn parse_comparison<'a>(s: &'a [u8], index: &mut usize) -> Result<Condition<'a>, String> {
+    if *index >= s.len() {
+        return Err(String::from("expected a condition, found end of query"));
+    }
+
+    let (ok, field) = parse_identifier(s, index);
+    if !ok {
+        return Err(format!("expected a field name at pos: {}", index));
     }
 
-    advance_whitespace(s, &mut index);
-    if parse_ascii("before", &s, &mut index) {
-        advance_whitespace(s, &mut index);
-        query.end = parse_timestamp(s, &mut index);
+    advance_whitespace(s, index);
+    let operator =
+        if parse_ascii(">=", s, index) {
+            Operator::GtEq
+        } else if parse_ascii("<=", s, index) {
+            Operator::LtEq
+        } else if parse_ascii("!=", s, index) || parse_ascii("<>", s, index) {
+            Operator::NotEq
+        } else if parse_ascii("==", s, index) || parse_ascii("=", s, index) {
+            Operator::Eq
+        } else if parse_ascii(">", s, index) {
+            Operator::Gt
+        } else if parse_ascii("<", s, index) {
+            Operator::Lt
+        } else {
+            return Err(format!("expected a comparison operator at pos: {}", index));
+        };
+
+    advance_whitespace(s, index);
+    let value = parse_value(s, index)?;
+
+    match value {
+        DataValue::String(value) if matches!(operator, Operator::Eq | Operator::NotEq) => {
+            Ok(Condition::Tag(TagPredicate { key: field, operator, value }))
+        }
+        value => Ok(Condition::Comparison(Comparison { field, operator, value })),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lang::query::{parse_select, parse_time_range};
-    use crate::lang::{Aggregation, SelectExpression, Selection, SelectQuery};
+    use crate::DataValue;
+    use crate::lang::query::{parse_condition, parse_select, parse_time_range};
+    use crate::lang::{Aggregation, Comparison, Condition, Operator, SelectExpression, Selection, SelectQuery, TagPredicate};
 
     #[test]
     fn time_range() {
-        let mut input = "after 1663226470079106890".as_bytes();
-        let mut query = SelectQuery { series: "test_series", selections: vec![], start: None, end: None };
+        let input = "after 1663226470079106890".as_bytes();
         let mut index = 0;
 
-        parse_time_range(&mut input, &mut index, &mut query)
+        let (start, _) = parse_time_range(input, &mut index).unwrap();
+        assert_eq!(start, Some(1663226470079106890));
     }
 
     #[test]
     fn select_query_simple() {
         let mut input = String::from("SELECT test_series");
-        let query = parse_select(&mut input);
-        assert_eq!(query, SelectQuery { series: "test_series", selections: vec![], start: None, end: None });
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query, SelectQuery { series: "test_series", selections: vec![], start: None, end: None, condition: None, interval: None });
 
         let mut input = String::from("SELECT test_series[value1, value2]");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Field("value1"),
                              Selection::Field("value2")],
             start: None,
             end: None,
+            condition: None,
+            interval: None,
         });
     }
 
     #[test]
     fn select_query_timestamps() {
         let mut input = String::from("SELECT test_series AFTER 1663226470079106890");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![],
             start: Some(1663226470079106890),
             end: None,
+            condition: None,
+            interval: None,
         });
 
         let mut input = String::from("SELECT test_series[value1, value2] AFTER 1663226470079106890");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Field("value1"),
                              Selection::Field("value2")],
             start: Some(1663226470079106890),
             end: None,
+            condition: None,
+            interval: None,
         });
 
         let mut input = String::from("SELECT test_series[value1, value2] BEFORE 1663226470079106895");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Field("value1"),
@@ -185,28 +346,32 @@ mod tests {
             start: None,
             end: Some(
                 1663226470079106895),
+            condition: None,
+            interval: None,
         });
 
         let mut input = String::from("SELECT test_series[value1, value2] AFTER 1663226470079106890 BEFORE 1663226470079106895");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Field("value1"),
                              Selection::Field("value2")],
             start: Some(1663226470079106890),
             end: Some(1663226470079106895),
+            condition: None,
+            interval: None,
         });
     }
 
     #[test]
     fn select_query_aggregator() {
         let mut input = String::from("SELECT test_series");
-        let query = parse_select(&mut input);
-        assert_eq!(query, SelectQuery { series: "test_series", selections: vec![], start: None, end: None });
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query, SelectQuery { series: "test_series", selections: vec![], start: None, end: None, condition: None, interval: None });
 
 
         let mut input = String::from("SELECT test_series[last(value1), value2]");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Expression(Box::new(SelectExpression {
@@ -215,11 +380,13 @@ mod tests {
             })), Selection::Field("value2")],
             start: None,
             end: None,
+            condition: None,
+            interval: None,
         });
 
 
         let mut input = String::from("SELECT test_series[ value1 ,  mean(value2) ] ");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Field("value1"),
@@ -229,11 +396,21 @@ mod tests {
                              }))],
             start: None,
             end: None,
+            condition: None,
+            interval: None,
         });
 
 
+        let mut input = String::from("SELECT test_series[first(value1), value2]");
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query.selections, vec![Selection::Expression(Box::new(SelectExpression {
+            expression: Selection::Field("value1"),
+            aggregator: Aggregation::First,
+        })), Selection::Field("value2")]);
+
+
         let mut input = String::from("SELECT test_series[min(value1), max(value2), mean(value3)]");
-        let query = parse_select(&mut input);
+        let query = parse_select(&mut input).unwrap();
         assert_eq!(query, SelectQuery {
             series: "test_series",
             selections: vec![Selection::Expression(Box::new(SelectExpression {
@@ -250,6 +427,155 @@ mod tests {
                              }))],
             start: None,
             end: None,
+            condition: None,
+            interval: None,
+        });
+    }
+
+    #[test]
+    fn rejects_nested_aggregations() {
+        let mut input = String::from("SELECT test_series[max(mean(value1))]");
+        assert_eq!(parse_select(&mut input), Err(String::from("nested aggregations are not supported at pos: 23")));
+    }
+
+    #[test]
+    fn select_query_where() {
+        let mut input = String::from("SELECT test_series[value1] WHERE value1 > 80");
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query, SelectQuery {
+            series: "test_series",
+            selections: vec![Selection::Field("value1")],
+            start: None,
+            end: None,
+            condition: Some(Condition::Comparison(Comparison {
+                field: "value1",
+                operator: Operator::Gt,
+                value: DataValue::from(80.0),
+            })),
+            interval: None,
+        });
+
+        let mut input = String::from("SELECT test_series AFTER 1663226470079106890 WHERE value1 >= 80 AND value2 = true");
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query, SelectQuery {
+            series: "test_series",
+            selections: vec![],
+            start: Some(1663226470079106890),
+            end: None,
+            condition: Some(Condition::And(
+                Box::new(Condition::Comparison(Comparison { field: "value1", operator: Operator::GtEq, value: DataValue::from(80.0) })),
+                Box::new(Condition::Comparison(Comparison { field: "value2", operator: Operator::Eq, value: DataValue::from(true) })),
+            )),
+            interval: None,
+        });
+    }
+
+    #[test]
+    fn select_query_group_by() {
+        let mut input = String::from("SELECT test_series[mean(value1)] AFTER 0 BEFORE 3600000000000 GROUP BY time(1m)");
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query, SelectQuery {
+            series: "test_series",
+            selections: vec![Selection::Expression(Box::new(SelectExpression {
+                expression: Selection::Field("value1"),
+                aggregator: Aggregation::Mean,
+            }))],
+            start: Some(0),
+            end: Some(3600000000000),
+            condition: None,
+            interval: Some(60_000_000_000),
         });
+
+        let mut input = String::from("SELECT test_series[max(value1)] WHERE value2 = true GROUP BY time( 10s )");
+        let query = parse_select(&mut input).unwrap();
+        assert_eq!(query.interval, Some(10_000_000_000));
+        assert!(query.condition.is_some());
+    }
+
+    #[test]
+    fn conditions() {
+        let mut index = 0;
+        let condition = parse_condition(b"a < 1 or b != 2 and not c <= 3", &mut index, 0).unwrap();
+        assert_eq!(condition, Condition::Or(
+            Box::new(Condition::Comparison(Comparison { field: "a", operator: Operator::Lt, value: DataValue::from(1.0) })),
+            Box::new(Condition::And(
+                Box::new(Condition::Comparison(Comparison { field: "b", operator: Operator::NotEq, value: DataValue::from(2.0) })),
+                Box::new(Condition::Not(Box::new(
+                    Condition::Comparison(Comparison { field: "c", operator: Operator::LtEq, value: DataValue::from(3.0) })
+                ))),
+            )),
+        ));
+
+        let mut index = 0;
+        let condition = parse_condition(b"(a < 1 or b>2) and order=false", &mut index, 0).unwrap();
+        assert_eq!(condition, Condition::And(
+            Box::new(Condition::Or(
+                Box::new(Condition::Comparison(Comparison { field: "a", operator: Operator::Lt, value: DataValue::from(1.0) })),
+                Box::new(Condition::Comparison(Comparison { field: "b", operator: Operator::Gt, value: DataValue::from(2.0) })),
+            )),
+            Box::new(Condition::Comparison(Comparison { field: "order", operator: Operator::Eq, value: DataValue::from(false) })),
+        ));
+
+        let mut index = 0;
+        let condition = parse_condition(b"host = 'a' and region != \"us\"", &mut index, 0).unwrap();
+        assert_eq!(condition, Condition::And(
+            Box::new(Condition::Tag(TagPredicate { key: "host", operator: Operator::Eq, value: String::from("a") })),
+            Box::new(Condition::Tag(TagPredicate { key: "region", operator: Operator::NotEq, value: String::from("us") })),
+        ));
+
+        // quoted values can only be ordered against string fields
+        let mut index = 0;
+        let condition = parse_condition(b"version >= '1.2'", &mut index, 0).unwrap();
+        assert_eq!(condition, Condition::Comparison(Comparison { field: "version", operator: Operator::GtEq, value: DataValue::from("1.2") }));
+
+        // names that turn out to be fields are compared as such
+        let mut index = 0;
+        let condition = parse_condition(b"host = 'a' or not status = 'ok'", &mut index, 0).unwrap();
+        assert_eq!(condition.resolve_fields(&|name| name == "status"), Condition::Or(
+            Box::new(Condition::Tag(TagPredicate { key: "host", operator: Operator::Eq, value: String::from("a") })),
+            Box::new(Condition::Not(Box::new(Condition::Comparison(Comparison { field: "status", operator: Operator::Eq, value: DataValue::from("ok") })))),
+        ));
+
+        let mut index = 0;
+        assert!(parse_condition(b"host = 'a", &mut index, 0).is_err());
+
+        let mut index = 0;
+        assert!(parse_condition(b"(a < 1", &mut index, 0).is_err());
+
+        let mut index = 0;
+        assert!(parse_condition(b"a ~ 1", &mut index, 0).is_err());
+
+        let mut index = 0;
+        assert!(parse_condition(b"a >", &mut index, 0).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_deeply_nested_conditions() {
+        let nested = |depth: usize| format!("SELECT s WHERE {}a = 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse_select(&mut nested(64)).is_ok());
+        assert!(parse_select(&mut format!("SELECT s WHERE {}a = 1", "not ".repeat(64))).is_ok());
+
+        // deep enough to overflow the stack, if nesting weren't limited
+        let error = parse_select(&mut nested(1_000_000)).unwrap_err();
+        assert!(error.starts_with("WHERE clause nested too deeply"), "{}", error);
+        let error = parse_select(&mut format!("SELECT s WHERE {}a = 1", "not ".repeat(1_000_000))).unwrap_err();
+        assert!(error.starts_with("WHERE clause nested too deeply"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        for query in [
+            "SELECT",
+            "SELECT [value1]",
+            "SELECT test_series[value1",
+            "SELECT test_series[1value]",
+            "SELECT test_series[mean(value1]",
+            "SELECT test_series AFTER yesterday",
+            "SELECT test_series WHERE value1 >",
+            "SELECT test_series GROUP BY time(0s)",
+            "SELECT test_series LIMIT 10",
+        ] {
+            assert!(parse_select(&mut String::from(query)).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_duration, parse_identifier, parse_keyword};
+
+/// How long the entries of a series are kept, as set by a statement of the form:
+///
+/// ```markdown
+/// CREATE RETENTION <duration> ON <series>
+/// ```
+///
+/// where the duration may be INF, to keep entries forever.
+#[derive(Debug, PartialEq)]
+pub struct Retention {
+    pub series: String,
+
+    /// The retention period in nanoseconds, or None to keep entries forever.
+    pub duration: Option<i64>,
+}
+
+/// Attempt to parse a CREATE RETENTION statement.
+pub fn parse_create_retention(raw_query: &mut str) -> Result<Retention, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index = 0;
+    if !parse_keyword("create", input, &mut index) {
+        return Err(String::from("expected CREATE at pos: 0"));
+    }
+
+    advance_whitespace(input, &mut index);
+    if !parse_keyword("retention", input, &mut index) {
+        return Err(format!("expected RETENTION at pos: {}", index));
+    }
+
+    advance_whitespace(input, &mut index);
+    let duration = if parse_keyword("inf", input, &mut index) {
+        None
+    } else {
+        match parse_duration(input, &mut index) {
+            Some(duration) if duration > 0 => Some(duration),
+            _ => return Err(format!("expected a duration at pos: {}", index)),
+        }
+    };
+
+    advance_whitespace(input, &mut index);
+    if !parse_keyword("on", input, &mut index) {
+        return Err(format!("expected ON at pos: {}", index));
+    }
+
+    advance_whitespace(input, &mut index);
+    let (ok, series) = parse_identifier(input, &mut index);
+    if !ok {
+        return Err(format!("expected a series name at pos: {}", index));
+    }
+    let series = series.to_owned();
+
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(Retention { series, duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::retention::{parse_create_retention, Retention};
+
+    #[test]
+    fn parses_create_retention() {
+        let mut query = String::from("CREATE RETENTION 30d ON test_series");
+        assert_eq!(parse_create_retention(&mut query), Ok(Retention {
+            series: String::from("test_series"),
+            duration: Some(30 * 24 * 60 * 60 * 1_000_000_000),
+        }));
+
+        let mut query = String::from("create retention inf on test_series");
+        assert_eq!(parse_create_retention(&mut query), Ok(Retention { series: String::from("test_series"), duration: None }));
+    }
+
+    #[test]
+    fn rejects_invalid_retention() {
+        for query in [
+            "CREATE",
+            "CREATE RETENTION ON test_series",
+            "CREATE RETENTION 0d ON test_series",
+            "CREATE RETENTION 30 ON test_series",
+            "CREATE RETENTION 30d test_series",
+            "CREATE RETENTION 30d ON",
+            "CREATE RETENTION 30d ON test_series extra",
+        ] {
+            let mut query = String::from(query);
+            assert!(parse_create_retention(&mut query).is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_identifier, parse_keyword};
+
+/// What to describe, as given by a statement of either form:
+///
+/// ```markdown
+/// SHOW SERIES
+/// SHOW FIELDS FROM <series>
+/// SHOW STATS
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum ShowTarget {
+    Series,
+    Fields(String),
+    /// Statistics about the usage of the block cache.
+    Stats,
+}
+
+/// Attempt to parse a SHOW statement.
+pub fn parse_show(raw_query: &mut str) -> Result<ShowTarget, String> {
+    make_ascii_lowercase_unquoted(raw_query);
+
+    let input = raw_query.as_bytes();
+    let mut index = 0;
+    if !parse_keyword("show", input, &mut index) {
+        return Err(String::from("expected SHOW at pos: 0"));
+    }
+
+    advance_whitespace(input, &mut index);
+    let target = if parse_keyword("series", input, &mut index) {
+        ShowTarget::Series
+    } else if parse_keyword("fields", input, &mut index) {
+        advance_whitespace(input, &mut index);
+        if !parse_keyword("from", input, &mut index) {
+            return Err(format!("expected FROM at pos: {}", index));
+        }
+
+        advance_whitespace(input, &mut index);
+        let (ok, series) = parse_identifier(input, &mut index);
+        if !ok {
+            return Err(format!("expected a series name at pos: {}", index));
+        }
+
+        ShowTarget::Fields(series.to_owned())
+    } else if parse_keyword("stats", input, &mut index) {
+        ShowTarget::Stats
+    } else {
+        return Err(format!("expected SERIES, FIELDS or STATS at pos: {}", index));
+    };
+
+    advance_whitespace(input, &mut index);
+    if index < input.len() {
+        return Err(format!("unexpected input at pos: {}", index));
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::show::{parse_show, ShowTarget};
+
+    #[test]
+    fn parses_show() {
+        let mut query = String::from("SHOW SERIES");
+        assert_eq!(parse_show(&mut query), Ok(ShowTarget::Series));
+
+        let mut query = String::from("show fields from test_series");
+        assert_eq!(parse_show(&mut query), Ok(ShowTarget::Fields(String::from("test_series"))));
+
+        let mut query = String::from("SHOW STATS");
+        assert_eq!(parse_show(&mut query), Ok(ShowTarget::Stats));
+    }
+
+    #[test]
+    fn rejects_invalid_show() {
+        for query in ["SHOW", "SHOW TABLES", "SHOW SERIES extra", "SHOW FIELDS", "SHOW FIELDS test_series", "SHOW FIELDS FROM", "SHOW STATS extra"] {
+            let mut query = String::from(query);
+            assert!(parse_show(&mut query).is_err(), "{}", query);
+        }
+    }
+}
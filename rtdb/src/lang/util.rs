@@ -1,4 +1,5 @@
 use std::str::{from_utf8, from_utf8_unchecked};
+use crate::DataValue;
 use crate::util::new_timestamp;
 
 #[inline]
@@ -23,6 +24,25 @@ pub fn parse_ascii(tag: &'static str, s: &[u8], index: &mut usize) -> bool {
     }
 }
 
+/// Attempt to parse a keyword, and advance the index accordingly if successful.
+///
+/// Unlike [parse_ascii], a keyword only matches if it isn't immediately followed by a character
+/// that could continue an identifier, so that e.g. "or" doesn't match the start of "order".
+#[inline]
+pub fn parse_keyword(tag: &'static str, s: &[u8], index: &mut usize) -> bool {
+    if !s[*index..].starts_with(tag.as_bytes()) {
+        return false;
+    }
+
+    match s.get(*index + tag.len()) {
+        Some(&c) if c.is_ascii_alphanumeric() || c == b'_' || c == b'-' => false,
+        _ => {
+            *index += tag.len();
+            true
+        }
+    }
+}
+
 /// Attempts to parse an identifier. Identifiers must begin with an alphabetic character, and
 /// afterwards may only container alphanumeric characters, '-', or '_'. On success, increases index
 /// by the length of the parsed identifier.
@@ -32,8 +52,11 @@ pub fn parse_ascii(tag: &'static str, s: &[u8], index: &mut usize) -> bool {
 pub fn parse_identifier<'a>(s: &'a [u8], index: &mut usize) -> (bool, &'a str) {
     let mut i = 0;
 
-    let first_char = s[*index];
-    if first_char < 0x61 || first_char > 0x7A { // test a-z
+    let first_char = match s.get(*index) {
+        Some(&c) => c,
+        None => return (false, ""),
+    };
+    if !(0x61..=0x7A).contains(&first_char) { // test a-z
         return (false, "");
     }
     // i += 1;
@@ -49,6 +72,88 @@ pub fn parse_identifier<'a>(s: &'a [u8], index: &mut usize) -> (bool, &'a str) {
     unsafe { (i > 0, from_utf8_unchecked(&s[*index - i..*index])) }
 }
 
+/// Attempts to parse an unquoted tag value, which may only contain alphanumeric characters, '-',
+/// '_', or '.'. On success, increases index by the length of the parsed value.
+#[inline]
+pub fn parse_tag_value<'a>(s: &'a [u8], index: &mut usize) -> (bool, &'a str) {
+    let mut i = *index;
+    while i < s.len() && (s[i].is_ascii_alphanumeric() || s[i] == b'_' || s[i] == b'-' || s[i] == b'.') {
+        i += 1;
+    }
+
+    let value = unsafe { from_utf8_unchecked(&s[*index..i]) };
+    *index = i;
+    (!value.is_empty(), value)
+}
+
+/// Converts a query to lowercase in place, except for quoted strings, which are kept as is.
+pub fn make_ascii_lowercase_unquoted(s: &mut str) {
+    let mut unquoted = vec![];
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in s.bytes().enumerate() {
+        match quote {
+            None if c == b'\'' || c == b'"' => {
+                unquoted.push(start..i);
+                quote = Some(c);
+            }
+            None => {}
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == b'\\' => escaped = true,
+            Some(q) if c == q => {
+                quote = None;
+                start = i + 1;
+            }
+            Some(_) => {}
+        }
+    }
+
+    if quote.is_none() {
+        unquoted.push(start..s.len());
+    }
+
+    // quotes are ascii, so the unquoted parts always start and end at character boundaries
+    for range in unquoted {
+        s[range].make_ascii_lowercase();
+    }
+}
+
+/// Attempts to parse a string enclosed in single or double quotes, and returns its contents
+/// without the quotes. On success, increases index past the closing quote.
+///
+/// Within the string, a backslash escapes the character following it, so `\'`, `\"` and `\\`
+/// stand for the character itself, while `\n` and `\t` stand for a newline and a tab.
+pub fn parse_quoted(s: &[u8], index: &mut usize) -> Option<String> {
+    let quote = match s.get(*index) {
+        Some(&c) if c == b'\'' || c == b'"' => c,
+        _ => return None,
+    };
+
+    let mut bytes = vec![];
+    let mut i = *index + 1;
+    while i < s.len() {
+        match s[i] {
+            c if c == quote => {
+                *index = i + 1;
+                return String::from_utf8(bytes).ok();
+            }
+            b'\\' => {
+                i += 1;
+                match s.get(i)? {
+                    b'n' => bytes.push(b'\n'),
+                    b't' => bytes.push(b'\t'),
+                    &c => bytes.push(c),
+                }
+            }
+            c => bytes.push(c),
+        }
+        i += 1;
+    }
+
+    None
+}
+
 /// Attempts to parse a timestamp starting from index.
 ///
 /// Currently accepts the following formats:
@@ -80,9 +185,103 @@ pub fn parse_timestamp(s: &[u8], index: &mut usize) -> Option<i64> {
     }
 }
 
+/// Attempts to parse a duration, such as "5m", starting from index, and returns it in nanoseconds.
+///
+/// A duration is an integer followed by one of the following units:
+/// - Nanoseconds: "ns"
+/// - Microseconds: "us"
+/// - Milliseconds: "ms"
+/// - Seconds: "s"
+/// - Minutes: "m"
+/// - Hours: "h"
+/// - Days: "d"
+/// - Weeks: "w"
+#[inline]
+pub fn parse_duration(s: &[u8], index: &mut usize) -> Option<i64> {
+    let mut i = *index;
+    while i < s.len() && s[i].is_ascii_digit() {
+        i += 1;
+    }
+
+    let amount: i64 = match from_utf8(&s[*index..i]).unwrap().parse() {
+        Ok(amount) => amount,
+        Err(_) => return None,
+    };
+
+    let unit: i64 =
+        if parse_ascii("ns", s, &mut i) {
+            1
+        } else if parse_ascii("us", s, &mut i) {
+            1_000
+        } else if parse_ascii("ms", s, &mut i) {
+            1_000_000
+        } else if parse_ascii("s", s, &mut i) {
+            1_000_000_000
+        } else if parse_ascii("m", s, &mut i) {
+            60 * 1_000_000_000
+        } else if parse_ascii("h", s, &mut i) {
+            60 * 60 * 1_000_000_000
+        } else if parse_ascii("d", s, &mut i) {
+            24 * 60 * 60 * 1_000_000_000
+        } else if parse_ascii("w", s, &mut i) {
+            7 * 24 * 60 * 60 * 1_000_000_000
+        } else {
+            return None;
+        };
+
+    *index = i;
+    amount.checked_mul(unit)
+}
+
+/// Attempt to parse a value, starting from s at the given index.
+///
+/// A value may be a bool, in the form of an unqouted "true" or "false", an integer, in the form of
+/// digits followed by an "i" such as "42i", a float, or a quoted string, see [parse_quoted].
+pub fn parse_value<'a>(s: &'a [u8], index: &'a mut usize) -> Result<DataValue, String> {
+    if *index >= s.len() {
+        return Err(String::from("expected a value, found end of query"));
+    }
+
+    if s[*index..].starts_with(b"true") {
+        *index += 4;
+        return Ok(DataValue::Bool(true));
+    } else if s[*index..].starts_with(b"false") {
+        *index += 5;
+        return Ok(DataValue::Bool(false));
+    }
+
+    if s[*index] == b'\'' || s[*index] == b'"' {
+        return match parse_quoted(s, index) {
+            Some(string) => Ok(DataValue::String(string)),
+            None => Err(format!("unterminated string at pos: {}", index)),
+        };
+    }
+
+    // integers are marked by a suffix, so that a value such as "42" stays a float
+    let start = *index + (s[*index] == b'-') as usize;
+    let digits = s[start..].iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && s.get(start + digits) == Some(&b'i') {
+        let end = start + digits + 1;
+        if !s.get(end).is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'.') {
+            let value = from_utf8(&s[*index..end - 1]).unwrap().parse::<i64>()
+                .map_err(|_| format!("integer out of range at pos: {}", index))?;
+            *index = end;
+            return Ok(DataValue::Integer(value));
+        }
+    }
+
+    if let Ok((val, len)) = fast_float::parse_partial::<f64, _>(&s[*index..]) {
+        *index += len;
+        return Ok(DataValue::Float(val))
+    }
+
+    Err(format!("failed to parse a value at pos: {}", index))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::lang::util::{advance_whitespace, parse_ascii, parse_identifier, parse_timestamp};
+    use crate::lang::util::{advance_whitespace, make_ascii_lowercase_unquoted, parse_ascii, parse_duration, parse_identifier, parse_keyword, parse_quoted, parse_tag_value, parse_timestamp, parse_value};
+    use crate::DataValue;
     use crate::util::new_timestamp;
 
     #[test]
@@ -112,16 +311,35 @@ mod tests {
     fn parses_ascii() {
         let mut index = 0;
 
-        assert_eq!(parse_ascii("test", b"a test", &mut index), false);
+        assert!(!parse_ascii("test", b"a test", &mut index));
         assert_eq!(index, 0);
 
-        assert_eq!(parse_ascii("a", b"a test", &mut index), true);
+        assert!(parse_ascii("a", b"a test", &mut index));
         assert_eq!(index, 1);
 
-        assert_eq!(parse_ascii(" test ", b"a test", &mut index), false);
+        assert!(!parse_ascii(" test ", b"a test", &mut index));
         assert_eq!(index, 1);
     }
 
+    #[test]
+    fn parses_keywords() {
+        let mut index = 0;
+
+        assert!(!parse_keyword("or", b"order > 1", &mut index));
+        assert_eq!(index, 0);
+
+        assert!(parse_keyword("or", b"or value1 > 1", &mut index));
+        assert_eq!(index, 2);
+
+        let mut index = 0;
+        assert!(parse_keyword("not", b"not(value1 > 1)", &mut index));
+        assert_eq!(index, 3);
+
+        let mut index = 0;
+        assert!(parse_keyword("and", b"and", &mut index));
+        assert_eq!(index, 3);
+    }
+
     #[test]
     fn parses_timestamps() {
         let mut index = 0;
@@ -134,36 +352,120 @@ mod tests {
         assert!(parse_timestamp(b"now()", &mut index).unwrap() > new_timestamp() - 1_000_000);
     }
 
+    #[test]
+    fn parses_durations() {
+        let mut index = 0;
+        assert_eq!(parse_duration(b"", &mut index), None);
+
+        let mut index = 0;
+        assert_eq!(parse_duration(b"5", &mut index), None);
+        assert_eq!(index, 0);
+
+        let mut index = 0;
+        assert_eq!(parse_duration(b"1m)", &mut index), Some(60_000_000_000));
+        assert_eq!(index, 2);
+
+        let mut index = 0;
+        assert_eq!(parse_duration(b"250ms", &mut index), Some(250_000_000));
+        assert_eq!(index, 5);
+
+        let mut index = 0;
+        assert_eq!(parse_duration(b"2w", &mut index), Some(1_209_600_000_000_000));
+    }
+
     #[test]
     fn parses_identifier() {
         let mut index = 0;
 
         let (parsed, ident) = parse_identifier(b"test_series,value1=1", &mut index);
-        assert_eq!(parsed, true);
+        assert!(parsed);
         assert_eq!(ident, "test_series");
         assert_eq!(index, 11);
 
         index += 1; // step past comma
         let (parsed, ident) = parse_identifier(b"test_series,value1=1", &mut index);
-        assert_eq!(parsed, true);
+        assert!(parsed);
         assert_eq!(ident, "value1");
         assert_eq!(index, 18);
 
         let (parsed, _ident) = parse_identifier(b"test_series,value1=1 12345", &mut index);
-        assert_eq!(parsed, false);
+        assert!(!parsed);
         assert_eq!(index, 18);
 
         let mut index = 0;
         let (parsed, _ident) = parse_identifier(b"1identifiers_cannot_start_with_number", &mut index);
-        assert_eq!(parsed, false);
+        assert!(!parsed);
         assert_eq!(index, 0);
 
         let mut index = 0;
         let (parsed, ident) = parse_identifier(b"name-with_ch4rs", &mut index);
-        assert_eq!(parsed, true);
+        assert!(parsed);
         assert_eq!(ident, "name-with_ch4rs");
         assert_eq!(index, 15);
 
         // TODO: try with non utf8
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parses_tag_values() {
+        let mut index = 0;
+        assert_eq!(parse_tag_value(b"us-east.1,host=a", &mut index), (true, "us-east.1"));
+        assert_eq!(index, 9);
+
+        let mut index = 0;
+        assert_eq!(parse_tag_value(b" a", &mut index), (false, ""));
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn parses_quoted() {
+        let mut index = 0;
+        assert_eq!(parse_quoted(b"'us east' and", &mut index).as_deref(), Some("us east"));
+        assert_eq!(index, 9);
+
+        let mut index = 0;
+        assert_eq!(parse_quoted(b"\"a\"", &mut index).as_deref(), Some("a"));
+        assert_eq!(index, 3);
+
+        let mut index = 0;
+        assert_eq!(parse_quoted(br#"'it\'s "v1.2"\\\n'"#, &mut index).as_deref(), Some("it's \"v1.2\"\\\n"));
+        assert_eq!(index, 18);
+
+        let mut index = 0;
+        assert_eq!(parse_quoted(b"'unterminated", &mut index), None);
+        assert_eq!(parse_quoted(b"unquoted", &mut index), None);
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn parses_values() {
+        let mut index = 0;
+        assert_eq!(parse_value(b"42i,", &mut index), Ok(DataValue::Integer(42)));
+        assert_eq!(index, 3);
+
+        let mut index = 0;
+        assert_eq!(parse_value(b"-9223372036854775808i", &mut index), Ok(DataValue::Integer(i64::MIN)));
+
+        let mut index = 0;
+        assert_eq!(parse_value(b"42 ", &mut index), Ok(DataValue::Float(42.0)));
+        assert_eq!(index, 2);
+
+        let mut index = 0;
+        assert_eq!(parse_value(b"-1.5", &mut index), Ok(DataValue::Float(-1.5)));
+
+        let mut index = 0;
+        assert!(parse_value(b"9223372036854775808i", &mut index).is_err());
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn lowercases_unquoted() {
+        let mut query = String::from(r#"INSERT Sensors Status='OK',Version="V1.2 \"Beta\"" 10"#);
+        make_ascii_lowercase_unquoted(&mut query);
+        assert_eq!(query, r#"insert sensors status='OK',version="V1.2 \"Beta\"" 10"#);
+
+        let mut query = String::from("SELECT A WHERE B = 'It\\'S' AND C = 'D'");
+        make_ascii_lowercase_unquoted(&mut query);
+        assert_eq!(query, "select a where b = 'It\\'S' and c = 'D'");
+    }
+}
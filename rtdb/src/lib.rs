@@ -1,11 +1,12 @@
 extern crate core;
 
+use std::cmp::Ordering;
 use std::fmt::{Formatter};
 
 
 use bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
-use crate::wire_protocol::FieldDescription;
+use crate::wire_protocol::{DataType, FieldDescription};
 
 pub mod storage;
 pub mod execution;
@@ -37,6 +38,12 @@ impl RecordCollection {
     pub fn len(&self) -> usize {
         self.elements.len() / (self.fields.len() + 1)
     }
+
+    /// Return whether this record collection has no rows.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
 }
 
 // TODO: move to client side and rename
@@ -60,7 +67,7 @@ impl DataRow {
     }
 }
 
-#[derive(Archive, Copy, Clone, Deserialize, Serialize, Debug, PartialEq, serde::Serialize)]
+#[derive(Archive, Clone, Deserialize, Serialize, Debug, PartialEq, serde::Serialize)]
 #[archive(compare(PartialEq))]
 #[archive_attr(derive(CheckBytes, Debug))]
 pub enum DataValue {
@@ -68,6 +75,8 @@ pub enum DataValue {
     Timestamp(i64),
     Bool(bool),
     Float(f64),
+    String(String),
+    Integer(i64),
 }
 
 impl From<bool> for DataValue {
@@ -82,14 +91,53 @@ impl From<f64> for DataValue {
     }
 }
 
+impl From<i64> for DataValue {
+    fn from(i: i64) -> Self {
+        DataValue::Integer(i)
+    }
+}
+
+impl From<&str> for DataValue {
+    fn from(s: &str) -> Self {
+        DataValue::String(s.to_owned())
+    }
+}
+
+impl From<String> for DataValue {
+    fn from(s: String) -> Self {
+        DataValue::String(s)
+    }
+}
+
 impl DataValue {
+    /// The type of the value, or None if there is no value.
+    #[inline]
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            DataValue::None => None,
+            DataValue::Timestamp(_) => Some(DataType::Timestamp),
+            DataValue::Bool(_) => Some(DataType::Bool),
+            DataValue::Float(_) => Some(DataType::Float),
+            DataValue::String(_) => Some(DataType::String),
+            DataValue::Integer(_) => Some(DataType::Integer),
+        }
+    }
+
+    /// Strings are prefixed with their length in bytes, as a u32.
     #[inline]
-    pub fn to_be_bytes(self) -> Vec<u8> {
+    pub fn to_be_bytes(&self) -> Vec<u8> {
         match self {
             DataValue::None => vec![],
             DataValue::Timestamp(t) => t.to_be_bytes().to_vec(),
-            DataValue::Bool(b) => vec![b as u8],
-            DataValue::Float(f) => f.to_be_bytes().to_vec()
+            DataValue::Bool(b) => vec![*b as u8],
+            DataValue::Float(f) => f.to_be_bytes().to_vec(),
+            DataValue::Integer(i) => i.to_be_bytes().to_vec(),
+            DataValue::String(s) => {
+                let mut bytes = Vec::with_capacity(4 + s.len());
+                bytes.extend((s.len() as u32).to_be_bytes());
+                bytes.extend(s.as_bytes());
+                bytes
+            }
         }
     }
 }
@@ -103,13 +151,85 @@ impl PartialEq<f64> for DataValue {
     }
 }
 
+/// Values are only ordered against values of the same type, so that e.g. comparing a float to a
+/// bool yields None rather than an arbitrary ordering. The exception are integers and floats,
+/// which are ordered by their numeric value, so that integer fields can be compared to literals
+/// without an `i` suffix.
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (DataValue::Timestamp(a), DataValue::Timestamp(b)) => a.partial_cmp(b),
+            (DataValue::Bool(a), DataValue::Bool(b)) => a.partial_cmp(b),
+            (DataValue::Float(a), DataValue::Float(b)) => a.partial_cmp(b),
+            (DataValue::String(a), DataValue::String(b)) => a.partial_cmp(b),
+            (DataValue::Integer(a), DataValue::Integer(b)) => a.partial_cmp(b),
+            (DataValue::Integer(a), DataValue::Float(b)) => compare_integer_to_float(*a, *b),
+            (DataValue::Float(a), DataValue::Integer(b)) => compare_integer_to_float(*b, *a).map(Ordering::reverse),
+            _ => None,
+        }
+    }
+}
+
+/// Compare an integer to a float exactly. Converting either to the other's type isn't exact, since
+/// floats can't represent all integers above 2^53, and integers can't represent fractions.
+fn compare_integer_to_float(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+
+    // -2^63 and 2^63 are exactly representable as floats, so integral floats in between fit in an i64
+    if b.fract() == 0.0 && b >= i64::MIN as f64 && b < i64::MAX as f64 {
+        return Some(a.cmp(&(b as i64)));
+    }
+
+    // otherwise there's no integer equal to the float, and an i128 holds both the integer and the
+    // float's neighbouring integers, saturating for floats that are too large even for an i128
+    match (a as i128) <= b.floor() as i128 {
+        true => Some(Ordering::Less),
+        false => Some(Ordering::Greater),
+    }
+}
+
 impl std::fmt::Display for DataValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             DataValue::None => write!(f, "NONE"),
             DataValue::Timestamp(t) => write!(f, "{}", t), // TODO: could format more nicely?
             DataValue::Bool(bool) => write!(f, "{}", bool),
-            DataValue::Float(float) => write!(f, "{}", float)
+            DataValue::Float(float) => write!(f, "{}", float),
+            DataValue::String(string) => write!(f, "{}", string),
+            DataValue::Integer(integer) => write!(f, "{}", integer),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::DataValue;
+
+    #[test]
+    fn compares_integers_to_floats_exactly() {
+        let cmp = |a: i64, b: f64| DataValue::Integer(a).partial_cmp(&DataValue::Float(b));
+
+        // 2^53 + 1 is the first integer a float can't represent, and rounds to 2^53 as a float
+        let max_exact = 1i64 << 53;
+        assert_eq!(cmp(max_exact, max_exact as f64), Some(Ordering::Equal));
+        assert_eq!(cmp(max_exact + 1, max_exact as f64), Some(Ordering::Greater));
+        assert_eq!(cmp(-max_exact - 1, -max_exact as f64), Some(Ordering::Less));
+        assert_eq!(DataValue::Float(max_exact as f64).partial_cmp(&DataValue::Integer(max_exact + 1)), Some(Ordering::Less));
+
+        // i64::MAX rounds up to 2^63 as a float
+        assert_eq!(cmp(i64::MAX, i64::MAX as f64), Some(Ordering::Less));
+        assert_eq!(cmp(i64::MIN, i64::MIN as f64), Some(Ordering::Equal));
+
+        assert_eq!(cmp(1, 1.5), Some(Ordering::Less));
+        assert_eq!(cmp(2, 1.5), Some(Ordering::Greater));
+        assert_eq!(cmp(-2, -1.5), Some(Ordering::Less));
+        assert_eq!(cmp(-1, -1.5), Some(Ordering::Greater));
+        assert_eq!(cmp(i64::MAX, f64::INFINITY), Some(Ordering::Less));
+        assert_eq!(cmp(i64::MIN, f64::NEG_INFINITY), Some(Ordering::Greater));
+        assert_eq!(cmp(0, f64::NAN), None);
+    }
+}
@@ -1,9 +1,16 @@
-use std::str::from_utf8;
+use std::io;
+use std::time::Duration;
 
+use log::{debug, error, warn};
 use tokio::io::AsyncReadExt;
 use tokio::net::TcpStream;
 
 use crate::network::connection::ConnectionPool;
+use crate::network::server::ENGINE;
+use crate::storage::block_manager::{BLOCK_CACHE, DEFAULT_CACHE_CAPACITY};
+use crate::storage::series::SeriesStorage;
+use crate::storage::set_data_dir;
+use crate::util::new_timestamp;
 
 pub mod connection;
 pub mod server;
@@ -16,35 +23,105 @@ pub const ACTION_AUTHENTICATE: u8 = 0x00;
 pub const ACTION_QUERY: u8 = 0x01;
 pub const ACTION_INSERT: u8 = 0x02;
 
+/// The maximum length of a statement, or of any other string sent by a client, in bytes. Longer
+/// strings are rejected with a protocol error, after which the connection is closed.
+pub const MAX_STRING_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// Options for the TCP server.
+#[derive(Clone, Debug)]
+pub struct ServerOptions {
+    /// The address to listen on, e.g. "127.0.0.1:2345".
+    pub address: String,
+
+    /// Whether clients must authenticate as one of the database's users, before they can issue
+    /// queries or inserts.
+    pub require_auth: bool,
+
+    /// Memory budget of the block cache shared by all series, in bytes.
+    pub block_cache_capacity: usize,
+
+    /// How often background maintenance runs, which drops entries older than their series'
+    /// retention period, and purges deleted entries from disk.
+    pub maintenance_interval: Duration,
+
+    /// Directory to store series in, or None for the default. It can't be changed once series
+    /// were stored, e.g. by another server in the same process.
+    pub data_dir: Option<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            address: format!("{}:{}", "127.0.0.1", PORT),
+            require_auth: false,
+            block_cache_capacity: DEFAULT_CACHE_CAPACITY,
+            maintenance_interval: Duration::from_secs(60),
+            data_dir: None,
+        }
+    }
+}
+
 pub async fn start_tcp_listener() {
-    let address = format!("{}:{}", "127.0.0.1", PORT);
-    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+    start_tcp_listener_with(ServerOptions::default()).await;
+}
 
-    let mut pool = ConnectionPool::new();
+pub async fn start_tcp_listener_with(options: ServerOptions) {
+    if let Some(dir) = &options.data_dir {
+        if let Err(err) = set_data_dir(dir) {
+            error!("failed to use data directory '{}': {}", dir, err);
+            return;
+        }
+    }
+    BLOCK_CACHE.lock().unwrap().set_capacity(options.block_cache_capacity);
+    SeriesStorage::remove_dropped();
+
+    let listener = tokio::net::TcpListener::bind(&options.address).await.unwrap();
+
+    let mut maintenance_interval = tokio::time::interval(options.maintenance_interval);
+    tokio::spawn(async move {
+        loop {
+            maintenance_interval.tick().await;
+            // maintenance reads and rewrites blocks on disk, which mustn't hold up the runtime
+            let maintenance = tokio::task::spawn_blocking(|| {
+                let engine = ENGINE.blocking_read();
+                engine.enforce_retention(new_timestamp());
+                engine.purge_deleted();
+            });
+            if let Err(err) = maintenance.await {
+                error!("maintenance failed: {}", err);
+            }
+        }
+    });
+
+    let mut pool = ConnectionPool::new(options.require_auth);
 
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
-                println!("new connection: {:?}", addr);
+                debug!("new connection: {:?}", addr);
 
                 pool.add(stream);
             }
-            Err(e) => println!("couldn't get cli: {:?}", e),
+            Err(e) => warn!("couldn't accept connection: {}", e),
         };
     }
 }
 
-/// Consumes a UCSD string, with a length specified as a u16.
+/// Consumes a UCSD string, with a length specified as a u32.
+///
+/// Fails with [io::ErrorKind::InvalidData] if the string is longer than [MAX_STRING_LENGTH], or
+/// isn't valid UTF-8, in which case the rest of the stream can't be trusted to be in sync.
 #[inline]
-async fn read_string(stream: &mut TcpStream) -> Option<String> {
-    let len = match stream.read_u16().await {
-        Ok(len) => len,
-        Err(_) => return None,
-    };
-    let mut buffer = vec![0; len as usize];
+async fn read_string(stream: &mut TcpStream) -> io::Result<String> {
+    let len = stream.read_u32().await?;
+    if len > MAX_STRING_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("string is {} bytes long, but may be at most {} bytes", len, MAX_STRING_LENGTH)));
+    }
 
-    stream.read_exact(&mut buffer).await;
-    Some(from_utf8(&buffer).unwrap().to_owned())
+    let mut buffer = vec![0; len as usize];
+    stream.read_exact(&mut buffer).await?;
+    String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
 #[cfg(test)]
@@ -52,9 +129,12 @@ mod tests {
     use std::time::Duration;
 
     use nom::AsBytes;
-    use tokio::io::AsyncWriteExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
 
-    use crate::network::{ACTION_QUERY, start_tcp_listener};
+    use crate::network::{ACTION_AUTHENTICATE, ACTION_INSERT, ACTION_QUERY, MAX_STRING_LENGTH, ServerOptions, start_tcp_listener, start_tcp_listener_with};
+    use crate::users::{hash_sha256, User};
+    use crate::wire_protocol::error::ErrorCode;
 
     #[tokio::test]
     async fn accepts_connection() {
@@ -66,14 +146,120 @@ mod tests {
         tokio::time::sleep(Duration::new(0, 1e6 as u32)).await;
 
         let msg = b"SELECT test_series";
-        let len = msg.len() as u16;
+        let len = msg.len() as u32;
 
         let mut c = tokio::net::TcpStream::connect("127.0.0.1:2345").await.unwrap();
-        c.write_all(&[ACTION_QUERY]).await;
-        c.write_all(len.to_be_bytes().as_bytes()).await;
-        c.write_all(msg).await;
-        c.flush().await;
+        c.write_all(&[ACTION_QUERY]).await.unwrap();
+        c.write_all(len.to_be_bytes().as_bytes()).await.unwrap();
+        c.write_all(msg).await.unwrap();
+        c.flush().await.unwrap();
 
         tokio::time::sleep(Duration::new(1, 1e7 as u32)).await;
     }
+
+    async fn send(c: &mut TcpStream, action: u8, strings: &[&str]) {
+        let mut buffer = vec![action];
+        for str in strings {
+            buffer.extend((str.len() as u32).to_be_bytes());
+            buffer.extend(str.as_bytes());
+        }
+        c.write_all(&buffer).await.unwrap();
+    }
+
+    /// Read a response, returning its tag, and its error code if it's an error.
+    async fn read_response(c: &mut TcpStream) -> (u8, Option<ErrorCode>) {
+        c.read_u64().await.unwrap();
+        match c.read_u8().await.unwrap() {
+            3 => {
+                let code = ErrorCode::from(c.read_u16().await.unwrap());
+                let mut message = vec![0; c.read_u16().await.unwrap() as usize];
+                c.read_exact(&mut message).await.unwrap();
+                (3, Some(code))
+            }
+            2 => {
+                c.read_u8().await.unwrap();
+                (2, None)
+            }
+            tag => (tag, None),
+        }
+    }
+
+    #[tokio::test]
+    async fn requires_authentication() {
+        User::create("test_auth_user", Some("password")).unwrap();
+
+        let options = ServerOptions {
+            address: String::from("127.0.0.1:2346"),
+            require_auth: true,
+            ..ServerOptions::default()
+        };
+        tokio::spawn(async {
+            start_tcp_listener_with(options).await;
+        });
+
+        // give listener time to bind
+        tokio::time::sleep(Duration::new(0, 1e7 as u32)).await;
+
+        let mut c = TcpStream::connect("127.0.0.1:2346").await.unwrap();
+        let insert = "INSERT test_auth,value=1.0 1";
+
+        send(&mut c, ACTION_INSERT, &[insert]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::Unauthenticated)));
+
+        // statements aren't parsed before authenticating
+        send(&mut c, ACTION_QUERY, &["SELEKT nonsense"]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::Unauthenticated)));
+
+        send(&mut c, ACTION_AUTHENTICATE, &["test_auth_user", &hash_sha256("wrong")]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::AuthenticationFailed)));
+
+        send(&mut c, ACTION_AUTHENTICATE, &["test_auth_user", &hash_sha256("password")]).await;
+        assert_eq!(read_response(&mut c).await, (4, None));
+
+        send(&mut c, ACTION_INSERT, &[insert]).await;
+        assert_eq!(read_response(&mut c).await, (2, None));
+    }
+
+    #[tokio::test]
+    async fn closes_connections_on_protocol_errors() {
+        let options = ServerOptions { address: String::from("127.0.0.1:2347"), ..ServerOptions::default() };
+        tokio::spawn(async {
+            start_tcp_listener_with(options).await;
+        });
+
+        // give listener time to bind
+        tokio::time::sleep(Duration::new(0, 1e7 as u32)).await;
+
+        let mut c = TcpStream::connect("127.0.0.1:2347").await.unwrap();
+        send(&mut c, 42, &[]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::ProtocolError)));
+        assert_eq!(c.read_u8().await.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // a statement longer than the server accepts isn't read, so the connection can't continue
+        let mut c = TcpStream::connect("127.0.0.1:2347").await.unwrap();
+        c.write_all(&[ACTION_QUERY]).await.unwrap();
+        c.write_all(&(MAX_STRING_LENGTH + 1).to_be_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::ProtocolError)));
+        assert_eq!(c.read_u8().await.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn reports_results_too_large_to_send() {
+        let options = ServerOptions { address: String::from("127.0.0.1:2348"), ..ServerOptions::default() };
+        tokio::spawn(async {
+            start_tcp_listener_with(options).await;
+        });
+
+        // give listener time to bind
+        tokio::time::sleep(Duration::new(0, 1e7 as u32)).await;
+
+        // the error names the series, which makes its message too long for a u16 length prefix
+        let mut c = TcpStream::connect("127.0.0.1:2348").await.unwrap();
+        send(&mut c, ACTION_QUERY, &[&format!("SELECT {}", "a".repeat(u16::MAX as usize))]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::ResultTooLarge)));
+
+        // the connection is still in sync
+        send(&mut c, ACTION_QUERY, &["SELECT test_too_large"]).await;
+        assert_eq!(read_response(&mut c).await, (3, Some(ErrorCode::SeriesNotFound)));
+    }
 }
@@ -1,14 +1,26 @@
+use std::io;
 use std::time;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use log::{debug, error, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
+use crate::execution::ExecutionResult;
 use crate::lang::Action;
+use crate::lang::util::make_ascii_lowercase_unquoted;
+use crate::lang::delete::parse_delete;
+use crate::lang::drop::parse_drop;
+use crate::lang::duplicates::parse_create_duplicates;
 use crate::lang::insert::parse_insert;
 use crate::lang::query::parse_select;
-use crate::network::read_string;
+use crate::lang::retention::parse_create_retention;
+use crate::lang::show::parse_show;
+use crate::network::{ACTION_AUTHENTICATE, ACTION_INSERT, ACTION_QUERY, read_string};
 use crate::network::server::ENGINE;
+use crate::users::User;
+use crate::wire_protocol::auth::build_auth_result;
 use crate::wire_protocol::build_response;
+use crate::wire_protocol::error::{build_error_result, ErrorCode, ErrorResult};
 
 /// A database connection.
 ///
@@ -35,77 +47,185 @@ impl Connection {
     /// 1. listening for and parsing a command.
     /// 2. executing a corresponding action.
     /// 3. serializing and writing back the results.
+    ///
+    /// Responses are serialized into a buffer before being sent, so that they can be prefixed by
+    /// their length as a u64, which the client needs to know how much to read.
     pub async fn start_handle_loop(&mut self) {
-        loop {
-            let mut msg = match read_string(&mut self.stream).await {
-                None => break,
-                Some(msg) => msg
+        let mut response = Vec::with_capacity(1024);
+        while let Ok(action) = self.stream.read_u8().await {
+            // reserve space for the length, which is only known once the response is built
+            response.clear();
+            response.extend(0u64.to_be_bytes());
+
+            // the connection can't be kept in sync after a protocol error, so it's closed once the
+            // error is reported
+            let mut close = false;
+            let built = match action {
+                ACTION_AUTHENTICATE => match self.authenticate().await {
+                    Ok(Ok(())) => build_auth_result(&mut response).await,
+                    Ok(Err(error)) => build_error_result(&error, &mut response).await,
+                    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                        close = true;
+                        build_error_result(&protocol_error(err.to_string()), &mut response).await
+                    }
+                    Err(_) => break,
+                },
+                ACTION_QUERY | ACTION_INSERT => {
+                    let start = time::Instant::now();
+                    let built = match self.execute_statement().await {
+                        Ok(Ok(result)) => build_response(&result, &mut response).await,
+                        Ok(Err(error)) => build_error_result(&error, &mut response).await,
+                        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                            close = true;
+                            build_error_result(&protocol_error(err.to_string()), &mut response).await
+                        }
+                        Err(_) => break,
+                    };
+                    debug!("exec: {}us", start.elapsed().as_micros());
+                    built
+                }
+                _ => {
+                    close = true;
+                    build_error_result(&protocol_error(format!("unknown action: {}", action)), &mut response).await
+                }
             };
+            // a result that can't be encoded is replaced by an error, rather than sending part of it
+            let built = match built {
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                    warn!("failed to build response: {}", err);
+                    response.truncate(8);
+                    let error = ErrorResult { code: ErrorCode::ResultTooLarge, message: err.to_string() };
+                    build_error_result(&error, &mut response).await
+                }
+                built => built,
+            };
+            if let Err(err) = built {
+                error!("failed to build response: {}", err);
+                break;
+            }
+
+            let len = (response.len() - 8) as u64;
+            response[..8].copy_from_slice(&len.to_be_bytes());
+            if let Err(err) = self.stream.write_all(&response).await {
+                error!("failed to write response: {}", err);
+                break;
+            }
+
+            if close {
+                break;
+            }
+        }
+    }
 
-            let start = time::Instant::now();
-
-
-            msg.make_ascii_lowercase();
-            let action =
-                if msg.starts_with("select") {
-                    Action::Select(parse_select(&mut msg))
-                } else if msg.starts_with("insert") {
-                    Action::Insert(parse_insert(&mut msg))
-                } else {
-                    panic!("Could not parse!") // tODO: error handling
-                };
-
-            let engine = ENGINE.read().await;
-            let result = engine.execute(action);
-
-            let elapsed1 = start.elapsed();
-
-
-            // --
-            // --
-            // --
-            // -- HEY! So, the issue that we left off at, is that we don't actually
-            //      know the length of the response, which we were previously sending as the very
-            //      first part of the response...
-            // --
-            // --
-
-
-
-            let mut buf = BufWriter::new(&mut self.stream);
-            build_response(&result, &mut buf).await;
-            buf.flush().await;
-
-            let elapsed = start.elapsed();
-            println!("exec: {}us", elapsed1.as_micros());
+    /// Read a statement from the stream, and execute it.
+    ///
+    /// Fails if the stream was closed before the statement was read, or if the statement is
+    /// malformed, see [read_string], or if executing it panicked.
+    async fn execute_statement(&mut self) -> io::Result<Result<ExecutionResult, ErrorResult>> {
+        let mut msg = read_string(&mut self.stream).await?;
+
+        // statements from unauthenticated connections aren't even parsed, so they can't learn
+        // anything from parse errors
+        if !self.authenticated {
+            return Ok(Err(ErrorResult {
+                code: ErrorCode::Unauthenticated,
+                message: String::from("authentication is required"),
+            }));
+        }
 
-            // let len = response.len();
-            self.stream.write(&len.to_be_bytes()).await;
-            self.stream.write_all(&response).await;
-            self.stream.flush().await;
+        // executing reads and writes blocks on disk, which mustn't hold up the runtime. Actions
+        // borrow from the statement, so it's parsed on the blocking thread as well.
+        tokio::task::spawn_blocking(move || execute(&mut msg))
+            .await
+            .map_err(|err| io::Error::other(format!("statement execution failed: {}", err)))
+    }
 
-            println!("post_serialization: {}us", elapsed.as_micros());
+    /// Read a username and password from the stream, and attempt to authenticate as that user.
+    /// The password is expected to already be hashed with SHA-256, and hex encoded, or empty for
+    /// users without a password.
+    ///
+    /// Fails if the stream was closed before the credentials were read, or if they're malformed,
+    /// see [read_string].
+    async fn authenticate(&mut self) -> io::Result<Result<(), ErrorResult>> {
+        let username = read_string(&mut self.stream).await?;
+        let password = read_string(&mut self.stream).await?;
+        let password = match password.is_empty() {
+            true => None,
+            false => Some(password.as_str()),
+        };
+
+        match User::authenticate(&username, password) {
+            Ok(_) => {
+                self.authenticated = true;
+                Ok(Ok(()))
+            }
+            Err(message) => Ok(Err(ErrorResult {
+                code: ErrorCode::AuthenticationFailed,
+                message: message.to_owned(),
+            })),
         }
     }
 }
 
+/// Parse a statement and execute it on the engine. Blocks on the engine's lock and on disk I/O, so
+/// it must be run on a blocking thread.
+fn execute(msg: &mut str) -> Result<ExecutionResult, ErrorResult> {
+    make_ascii_lowercase_unquoted(msg);
+    let action =
+        if msg.starts_with("select") {
+            parse_select(msg).map(Action::Select)
+        } else if msg.starts_with("insert") {
+            parse_insert(msg).map(Action::Insert)
+        } else if msg.starts_with("create") && msg["create".len()..].trim_start().starts_with("duplicates") {
+            parse_create_duplicates(msg).map(Action::CreateDuplicates)
+        } else if msg.starts_with("create") {
+            parse_create_retention(msg).map(Action::CreateRetention)
+        } else if msg.starts_with("delete") {
+            parse_delete(msg).map(Action::Delete)
+        } else if msg.starts_with("drop") {
+            parse_drop(msg).map(Action::Drop)
+        } else if msg.starts_with("show") {
+            parse_show(msg).map(Action::Show)
+        } else {
+            Err(String::from("unknown command, expected SELECT, INSERT, CREATE, DELETE, DROP or SHOW"))
+        };
+
+    match action {
+        Ok(action) => ENGINE.blocking_read().execute(action).map_err(ErrorResult::from),
+        Err(message) => Err(ErrorResult { code: ErrorCode::InvalidQuery, message }),
+    }
+}
+
+/// The error reported to a client whose request doesn't follow the protocol, before its
+/// connection is closed.
+fn protocol_error(message: String) -> ErrorResult {
+    warn!("protocol error: {}", message);
+    ErrorResult { code: ErrorCode::ProtocolError, message }
+}
+
 /// ConnectionPool manages a fixed size pool of live TCP connections from clients.
 pub struct ConnectionPool {
     active_connections: u16,
+
+    /// Whether connections must authenticate before issuing queries and inserts.
+    require_auth: bool,
 }
 
 impl ConnectionPool {
-    pub fn new() -> ConnectionPool {
+    pub fn new(require_auth: bool) -> ConnectionPool {
         ConnectionPool {
             active_connections: 0,
+            require_auth,
         }
     }
 
     pub fn add(&mut self, stream: TcpStream) {
         self.active_connections += 1;
 
+        let authenticated = !self.require_auth;
         tokio::spawn(async move {
             let mut connection = Connection::from(stream);
+            connection.authenticated = authenticated;
             connection.start_handle_loop().await;
         });
     }
@@ -12,14 +12,17 @@ use axum::extract::Query;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
-use log::info;
+use log::{debug, info};
 use once_cell::sync::Lazy;
 use tokio::time;
 
-use crate::execution::{ExecutionEngine};
+use crate::execution::{ExecutionEngine, ExecutionError};
 use crate::lang::Action;
+use crate::lang::util::make_ascii_lowercase_unquoted;
 use crate::lang::insert::parse_insert;
 use crate::lang::query::parse_select;
+use crate::lang::show::parse_show;
+use crate::storage::block_manager::BLOCK_CACHE;
 
 pub struct HttpServer {}
 
@@ -28,6 +31,7 @@ impl HttpServer {
         let app = Router::new()
             .route("/insert", post(insert))
             .route("/query", get(query))
+            .route("/stats", get(stats))
             .route("/", get(root));
 
         let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
@@ -47,33 +51,59 @@ async fn root() -> &'static str {
 
 async fn query(Query(mut params): Query<HashMap<String, String>>) -> impl IntoResponse {
     let start = time::Instant::now();
-    let mut query = params.get_mut("query").unwrap();
+    let query = params.get_mut("query").unwrap();
 
     // TOOD: move parsing into execution engine?
-    let select = parse_select(&mut query);
+    // schema discovery goes through the same endpoint as queries
+    make_ascii_lowercase_unquoted(query);
+    let action = match query.trim_start().starts_with("show") {
+        true => parse_show(query).map(Action::Show),
+        false => parse_select(query).map(Action::Select),
+    };
+    let action = match action {
+        Ok(action) => action,
+        Err(err) => return (StatusCode::BAD_REQUEST, err),
+    };
 
     let engine = ENGINE.read().await;
-    let result = engine.execute(Action::Select(select));
+    let result = engine.execute(action);
 
-    let elapsed = start.elapsed();
-    println!("{}us", elapsed.as_micros());
+    debug!("exec: {}us", start.elapsed().as_micros());
 
-    (StatusCode::OK, serde_json::to_string(&result).unwrap())
+    match result {
+        Ok(result) => (StatusCode::OK, serde_json::to_string(&result).unwrap()),
+        Err(err @ (ExecutionError::SeriesNotFound(_) | ExecutionError::FieldNotFound(_))) => (StatusCode::NOT_FOUND, err.to_string()),
+        Err(err @ ExecutionError::InvalidQuery(_)) => (StatusCode::BAD_REQUEST, err.to_string()),
+        Err(err @ ExecutionError::Storage(_)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+/// Statistics about the block cache, as JSON.
+async fn stats() -> impl IntoResponse {
+    let stats = BLOCK_CACHE.lock().unwrap().stats();
+    (StatusCode::OK, serde_json::to_string(&stats).unwrap())
 }
 
 
 async fn insert(Query(mut params): Query<HashMap<String, String>>) -> impl IntoResponse {
     let start = time::Instant::now();
-    let mut query = params.get_mut("query").unwrap();
-    let insertion = parse_insert(&mut query);
+    let query = params.get_mut("query").unwrap();
+    let insertions = match parse_insert(query) {
+        Ok(insertions) => insertions,
+        Err(err) => return (StatusCode::BAD_REQUEST, err),
+    };
 
     let engine = ENGINE.write().await;
-    let result = engine.execute(Action::Insert(insertion));
+    let result = engine.execute(Action::Insert(insertions));
 
-    let elapsed = start.elapsed();
-    println!("{}us", elapsed.as_micros());
+    debug!("exec: {}us", start.elapsed().as_micros());
 
-    (StatusCode::OK, serde_json::to_string(&result).unwrap())
+    match result {
+        Ok(result) => (StatusCode::OK, serde_json::to_string(&result).unwrap()),
+        Err(err @ (ExecutionError::SeriesNotFound(_) | ExecutionError::FieldNotFound(_))) => (StatusCode::NOT_FOUND, err.to_string()),
+        Err(err @ ExecutionError::InvalidQuery(_)) => (StatusCode::BAD_REQUEST, err.to_string()),
+        Err(err @ ExecutionError::Storage(_)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
 }
 
 // TODO: move
@@ -3,9 +3,52 @@ pub mod field;
 pub mod field_block;
 pub mod field_index;
 pub mod block_bool;
+pub mod block_float;
+pub mod block_int;
+pub mod block_string;
+pub mod block_time;
 pub mod storage_block;
 pub mod block_manager;
+pub mod wal;
+pub mod metadata;
+pub mod tombstone;
+pub mod tag_index;
 
+use once_cell::sync::OnceCell;
 
 // TODO: use a default path, e.g. /var/lib/rtdb/data
-const DEFAULT_DATA_DIR: &str = "data";
\ No newline at end of file
+#[cfg(not(test))]
+const DEFAULT_DATA_DIR: &str = "data";
+
+/// The directory that series are stored in, which is fixed once it's first used.
+static DATA_DIR: OnceCell<String> = OnceCell::new();
+
+#[cfg(not(test))]
+fn default_data_dir() -> String {
+    String::from(DEFAULT_DATA_DIR)
+}
+
+/// Tests store their series in a temporary directory of their own, so that test runs don't write
+/// into the working tree.
+#[cfg(test)]
+fn default_data_dir() -> String {
+    let dir = std::env::temp_dir().join(format!("rtdb-test-data-{}", std::process::id()));
+    dir.to_string_lossy().into_owned()
+}
+
+/// Return the directory that series are stored in.
+pub(crate) fn data_dir() -> &'static str {
+    DATA_DIR.get_or_init(default_data_dir)
+}
+
+/// Store series in the given directory, rather than the default one. Open series keep referring
+/// to their files by path, so the directory can't be changed once it was used.
+///
+/// Fails if a different directory is already in use.
+pub fn set_data_dir(dir: &str) -> Result<(), String> {
+    let current = DATA_DIR.get_or_init(|| String::from(dir));
+    match current == dir {
+        true => Ok(()),
+        false => Err(format!("series are already stored in '{}'", current)),
+    }
+}
@@ -1,11 +1,14 @@
 /// Serialize values into a type-specific, compressed format.
-pub fn serialize_bools(values: &Vec<Option<bool>>) -> Vec<u8> {
-    let mut buf = vec![0; values.len() / 4];
+///
+/// Values are packed 4 to a byte, so if the number of values isn't a multiple of 4, the last byte
+/// is padded with None values.
+pub fn serialize_bools(values: &[Option<bool>]) -> Vec<u8> {
+    let mut buf = vec![0; values.len().div_ceil(4)];
 
     for i in (0..values.len()).step_by(4) {
         let mut byte = 0;
         for j in 0..3 {
-            let val = values[i + j];
+            let val = values.get(i + j).copied().flatten();
             match val {
                 Some(true) => byte |= 0x3,
                 Some(false) => byte |= 0x2,
@@ -17,7 +20,7 @@ pub fn serialize_bools(values: &Vec<Option<bool>>) -> Vec<u8> {
 
         // manually unroll the last iteration of the above loop to avoid needing a conditional on the
         // above bitshift.
-        let val = values[i + 3];
+        let val = values.get(i + 3).copied().flatten();
         match val {
             Some(true) => byte |= 0x3,
             Some(false) => byte |= 0x2,
@@ -30,15 +33,11 @@ pub fn serialize_bools(values: &Vec<Option<bool>>) -> Vec<u8> {
     buf
 }
 
-pub fn serialize_floats(_values: &Vec<Option<f64>>) -> Vec<u8> {
-    todo!()
-}
-
 type BoolSet = [Option<bool>; 4];
 
 const BOOLS: [BoolSet; 256] = {
     const fn opt(val: u8, i: usize) -> Option<bool> {
-        match (val << i * 2) & 0b1100_0000 {
+        match (val << (i * 2)) & 0b1100_0000 {
             0b1100_0000 => Some(true),
             0b1000_0000 => Some(false),
             _ => None,
@@ -53,9 +52,9 @@ const BOOLS: [BoolSet; 256] = {
         ]
     }
     let mut v = [[None, None, None, None]; 256];
-    let mut i = 0u8;
-    while i < u8::MAX {
-        v[i as usize] = bool_set(i);
+    let mut i = 0;
+    while i < 256 {
+        v[i] = bool_set(i as u8);
         i += 1;
     }
     v
@@ -66,7 +65,7 @@ pub fn deserialize_1(raw: &[u8]) -> Vec<Option<bool>> {
 
     for &b in raw {
         for i in 0..4 {
-            let val = match (b << i * 2) & 0b1100_0000 {
+            let val = match (b << (i * 2)) & 0b1100_0000 {
                 0b1100_0000 => Some(true),
                 0b1000_0000 => Some(false),
                 _ => None,
@@ -80,7 +79,32 @@ pub fn deserialize_1(raw: &[u8]) -> Vec<Option<bool>> {
 
 /// Deserialize booleans from a custom compressed format.
 pub fn deserialize_bools(raw: &[u8]) -> Vec<Option<bool>> {
-    raw.iter().flat_map(|v| BOOLS[*v as usize]).collect()
+    BoolIter::new(raw).collect()
+}
+
+/// Iterates over booleans in the compressed format, decoding them as they're read. Like
+/// [deserialize_bools], this includes any padding in the last byte.
+pub struct BoolIter<'a> {
+    raw: &'a [u8],
+    index: usize,
+}
+
+impl<'a> BoolIter<'a> {
+    pub fn new(raw: &'a [u8]) -> BoolIter<'a> {
+        BoolIter { raw, index: 0 }
+    }
+}
+
+impl<'a> Iterator for BoolIter<'a> {
+    type Item = Option<bool>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let byte = *self.raw.get(self.index / 4)?;
+        let value = BOOLS[byte as usize][self.index % 4];
+        self.index += 1;
+        Some(value)
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +119,14 @@ mod tests {
 
         assert_eq!(deserialized, vals);
     }
+
+    #[test]
+    fn pads_partial_bytes() {
+        let vals = vec![Some(true), Some(true), Some(true), Some(true), Some(false), None];
+        let serialized = serialize_bools(&vals);
+        assert_eq!(serialized.len(), 2);
+
+        let deserialized = deserialize_bools(&serialized);
+        assert_eq!(deserialized, vec![Some(true), Some(true), Some(true), Some(true), Some(false), None, None, None]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,234 @@
+//! Compression for blocks of floats, based on the XOR encoding described in Facebook's Gorilla
+//! paper: <https://www.vldb.org/pvldb/vol8/p1816-teller.pdf>
+//!
+//! Consecutive values in a time series tend to be equal or close to each other, in which case XORing
+//! them yields mostly zero bits. Rather than storing each value in full, we store the XOR with the
+//! previous value, and only its "meaningful" bits, between its leading and trailing zeros.
+//!
+//! Since values may be missing, a block is laid out as:
+//!
+//! [COUNT] [PRESENCE] [VALUES]
+//! u32     bitmap     XOR encoded bit stream, of only the present values
+//!
+//! where the presence bitmap has one bit for each value, set if the value is present.
+
+use bitreader::BitReader;
+
+/// Writes values of arbitrary bit widths into a byte buffer, most significant bit first.
+struct BitWriter {
+    buffer: Vec<u8>,
+
+    /// Number of bits still free in the last byte of the buffer.
+    free: u32,
+}
+
+impl BitWriter {
+    fn new(buffer: Vec<u8>) -> BitWriter {
+        BitWriter { buffer, free: 0 }
+    }
+
+    /// Write the lowest `bits` bits of value.
+    #[inline]
+    fn write(&mut self, value: u64, bits: u32) {
+        let mut remaining = bits;
+        while remaining > 0 {
+            if self.free == 0 {
+                self.buffer.push(0);
+                self.free = 8;
+            }
+
+            let take = remaining.min(self.free);
+            let chunk = (value >> (remaining - take)) & ((1 << take) - 1);
+            *self.buffer.last_mut().unwrap() |= (chunk as u8) << (self.free - take);
+
+            self.free -= take;
+            remaining -= take;
+        }
+    }
+
+    #[inline]
+    fn write_bit(&mut self, bit: bool) {
+        self.write(bit as u64, 1);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Leading zeros are stored in 5 bits, so any more than this are treated as meaningful bits.
+const MAX_LEADING_ZEROS: u32 = 31;
+
+/// Serialize floats into a compressed format.
+pub fn serialize_floats(values: &Vec<Option<f64>>) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(4 + values.len() / 8 + values.len());
+    buffer.extend((values.len() as u32).to_le_bytes());
+
+    let mut presence = BitWriter::new(buffer);
+    for value in values {
+        presence.write_bit(value.is_some());
+    }
+
+    let mut out = BitWriter::new(presence.finish());
+    let mut present = values.iter().flatten().map(|v| v.to_bits());
+
+    let mut prev = match present.next() {
+        Some(first) => first,
+        None => return out.finish(),
+    };
+    out.write(prev, 64);
+
+    // the window of meaningful bits used by the previous value, if any
+    let mut window: Option<(u32, u32)> = None;
+
+    for value in present {
+        let xor = value ^ prev;
+        prev = value;
+
+        if xor == 0 {
+            out.write_bit(false);
+            continue;
+        }
+        out.write_bit(true);
+
+        let leading = xor.leading_zeros().min(MAX_LEADING_ZEROS);
+        let trailing = xor.trailing_zeros();
+
+        match window {
+            // the meaningful bits fit in the previous window, so we can reuse it
+            Some((prev_leading, prev_trailing)) if leading >= prev_leading && trailing >= prev_trailing => {
+                out.write_bit(false);
+                out.write(xor >> prev_trailing, 64 - prev_leading - prev_trailing);
+            }
+            _ => {
+                let meaningful = 64 - leading - trailing;
+
+                out.write_bit(true);
+                out.write(leading as u64, 5);
+                // 64 meaningful bits doesn't fit in 6 bits, but 0 can never occur, so it stands in
+                out.write((meaningful % 64) as u64, 6);
+                out.write(xor >> trailing, meaningful);
+
+                window = Some((leading, trailing));
+            }
+        }
+    }
+
+    out.finish()
+}
+
+/// Deserialize floats from the compressed format produced by [serialize_floats].
+pub fn deserialize_floats(raw: &[u8]) -> Vec<Option<f64>> {
+    FloatIter::new(raw).collect()
+}
+
+/// Iterates over floats in the compressed format, decoding them as they're read. Iteration stops
+/// early if the bit stream is truncated or corrupt.
+pub struct FloatIter<'a> {
+    presence: &'a [u8],
+    reader: BitReader<'a>,
+
+    index: usize,
+    count: usize,
+
+    prev: Option<u64>,
+    /// The leading and trailing zeros of the current window of meaningful bits.
+    window: (u32, u32),
+}
+
+impl<'a> FloatIter<'a> {
+    pub fn new(raw: &'a [u8]) -> FloatIter<'a> {
+        let count = u32::from_le_bytes(raw[..4].try_into().unwrap()) as usize;
+        let presence = &raw[4..4 + count.div_ceil(8)];
+        let reader = BitReader::new(&raw[4 + presence.len()..]);
+
+        FloatIter { presence, reader, index: 0, count, prev: None, window: (0, 0) }
+    }
+}
+
+impl<'a> Iterator for FloatIter<'a> {
+    type Item = Option<f64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let i = self.index;
+        self.index += 1;
+        if self.presence[i / 8] & (0x80 >> (i % 8)) == 0 {
+            return Some(None);
+        }
+
+        let reader = &mut self.reader;
+        let value = match self.prev {
+            None => reader.read_u64(64).ok()?,
+            Some(prev) => {
+                if !reader.read_bool().ok()? {
+                    prev
+                } else {
+                    if reader.read_bool().ok()? {
+                        let leading = reader.read_u8(5).ok()? as u32;
+                        let meaningful = match reader.read_u8(6).ok()? as u32 {
+                            0 => 64,
+                            meaningful => meaningful,
+                        };
+                        self.window = (leading, 64u32.checked_sub(leading + meaningful)?);
+                    }
+
+                    let (leading, trailing) = self.window;
+                    let xor = reader.read_u64((64 - leading - trailing) as u8).ok()? << trailing;
+                    prev ^ xor
+                }
+            }
+        };
+
+        self.prev = Some(value);
+        Some(Some(f64::from_bits(value)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.count - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::block_float::{deserialize_floats, serialize_floats};
+
+    #[test]
+    fn full_loop() {
+        let values = vec![Some(1.0), Some(1.0), None, Some(1.5), Some(-20.25), Some(f64::MAX), None,
+                          Some(0.0), Some(f64::MIN_POSITIVE), Some(f64::NAN), Some(-0.0), Some(1.5)];
+        let deserialized = deserialize_floats(&serialize_floats(&values));
+
+        assert_eq!(deserialized.len(), values.len());
+        for (a, b) in deserialized.iter().zip(&values) {
+            assert_eq!(a.map(f64::to_bits), b.map(f64::to_bits));
+        }
+
+        assert!(deserialize_floats(&serialize_floats(&vec![])).is_empty());
+        assert_eq!(deserialize_floats(&serialize_floats(&vec![None, None])), vec![None, None]);
+    }
+
+    #[test]
+    fn stops_at_truncated_values() {
+        let values = vec![Some(1.0), Some(2.5), Some(-3.75)];
+        let serialized = serialize_floats(&values);
+
+        let deserialized = deserialize_floats(&serialized[..serialized.len() - 2]);
+        assert!(deserialized.len() < values.len());
+        assert_eq!(deserialized[0], Some(1.0));
+    }
+
+    #[test]
+    fn compresses_slowly_changing_values() {
+        // a sensor reading that only changes occasionally, in small steps
+        let values: Vec<_> = (0..1000).map(|i| Some(20.0 + (i / 50) as f64 * 0.5)).collect();
+        let serialized = serialize_floats(&values);
+
+        assert!(serialized.len() * 10 < values.len() * 8, "{} bytes", serialized.len());
+        assert_eq!(deserialize_floats(&serialized), values);
+    }
+}
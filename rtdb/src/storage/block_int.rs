@@ -0,0 +1,302 @@
+//! Compression for blocks of integers, using delta, zig-zag and simple8b encoding.
+//!
+//! Counters and most other integer fields change by small amounts between consecutive entries, so
+//! rather than storing each value in full, we store the difference to the previous value. Zig-zag
+//! encoding maps these deltas to unsigned integers, such that deltas close to zero, whether
+//! positive or negative, become small integers. Simple8b then packs as many of them as fit into
+//! each 64 bit word, using the same bit width for all values of a word.
+//!
+//! Since values may be missing, a block is laid out as:
+//!
+//! [COUNT] [PRESENCE] [MODE] [VALUES]
+//! u32     bitmap     u8     simple8b words, or raw values, of only the present values
+//!
+//! where the presence bitmap has one bit for each value, set if the value is present, most
+//! significant bit first. Deltas of at least 2^60 don't fit in simple8b, so if there are any, the
+//! mode is [MODE_RAW] and values are stored as they are. All integers are little endian.
+
+/// Values are stored as simple8b words of zig-zag encoded deltas.
+const MODE_SIMPLE8B: u8 = 0;
+
+/// Values are stored as they are, 8 bytes each.
+const MODE_RAW: u8 = 1;
+
+/// The number of values packed into a simple8b word, and their width in bits, for each selector.
+/// Selectors 0 and 1 are runs of zeros, i.e. of unchanged values, which take no bits at all.
+const SELECTORS: [(usize, u32); 16] = [
+    (240, 0), (120, 0), (60, 1), (30, 2), (20, 3), (15, 4), (12, 5), (10, 6),
+    (8, 7), (7, 8), (6, 10), (5, 12), (4, 15), (3, 20), (2, 30), (1, 60),
+];
+
+/// The largest value simple8b can pack.
+const MAX_SIMPLE8B: u64 = (1 << 60) - 1;
+
+#[inline]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[inline]
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Pack values into simple8b words, or return None if any value is too large. The last word may
+/// be padded with zeros, so the number of values has to be known when unpacking.
+fn pack_simple8b(values: &[u64]) -> Option<Vec<u64>> {
+    if values.iter().any(|&v| v > MAX_SIMPLE8B) {
+        return None;
+    }
+
+    let mut words = vec![];
+    let mut i = 0;
+    while i < values.len() {
+        let remaining = &values[i..];
+        for (selector, &(n, bits)) in SELECTORS.iter().enumerate() {
+            let take = n.min(remaining.len());
+            if remaining[..take].iter().any(|&v| v >> bits != 0) {
+                continue;
+            }
+
+            let mut word = (selector as u64) << 60;
+            for (j, &value) in remaining[..take].iter().enumerate() {
+                word |= value << (j as u32 * bits);
+            }
+            words.push(word);
+            i += take;
+            break;
+        }
+    }
+
+    Some(words)
+}
+
+/// Unpack all values of a simple8b word, including any padding, onto the end of a buffer.
+#[inline]
+fn unpack_simple8b(word: u64, out: &mut Vec<u64>) {
+    let (n, bits) = SELECTORS[(word >> 60) as usize];
+    match bits {
+        0 => out.extend(std::iter::repeat_n(0, n)),
+        _ => {
+            let mask = (1 << bits) - 1;
+            out.extend((0..n).map(|j| (word >> (j as u32 * bits)) & mask));
+        }
+    }
+}
+
+/// Serialize integers into a compressed format.
+pub fn serialize_ints(values: &[Option<i64>]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(5 + values.len().div_ceil(8) + values.len());
+    buffer.extend((values.len() as u32).to_le_bytes());
+
+    let mut presence = vec![0; values.len().div_ceil(8)];
+    for (i, value) in values.iter().enumerate() {
+        if value.is_some() {
+            presence[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    buffer.extend(presence);
+
+    let present: Vec<i64> = values.iter().flatten().copied().collect();
+    let mut prev = 0i64;
+    let deltas: Vec<u64> = present.iter()
+        .map(|&value| {
+            let delta = value.wrapping_sub(prev);
+            prev = value;
+            zigzag(delta)
+        })
+        .collect();
+
+    match pack_simple8b(&deltas) {
+        Some(words) => {
+            buffer.push(MODE_SIMPLE8B);
+            for word in words {
+                buffer.extend(word.to_le_bytes());
+            }
+        }
+        None => {
+            buffer.push(MODE_RAW);
+            for value in present {
+                buffer.extend(value.to_le_bytes());
+            }
+        }
+    }
+
+    buffer
+}
+
+/// Deserialize integers from the compressed format produced by [serialize_ints].
+pub fn deserialize_ints(raw: &[u8]) -> Vec<Option<i64>> {
+    IntIter::new(raw).collect()
+}
+
+/// Check that a serialized block of integers is well formed, and holds exactly `count` values.
+pub fn validate_ints(raw: &[u8], count: usize) -> bool {
+    let presence_len = count.div_ceil(8);
+    if raw.len() < 5 + presence_len || u32::from_le_bytes(raw[..4].try_into().unwrap()) as usize != count {
+        return false;
+    }
+
+    let present: usize = raw[4..4 + presence_len].iter().map(|b| b.count_ones() as usize).sum();
+    let payload = &raw[5 + presence_len..];
+    match raw[4 + presence_len] {
+        MODE_SIMPLE8B => {
+            if !payload.len().is_multiple_of(8) {
+                return false;
+            }
+
+            // every word but the last has to be full
+            let capacities: Vec<usize> = payload.chunks_exact(8)
+                .map(|word| SELECTORS[(u64::from_le_bytes(word.try_into().unwrap()) >> 60) as usize].0)
+                .collect();
+            let total: usize = capacities.iter().sum();
+            total >= present && total - capacities.last().copied().unwrap_or(0) < present.max(1)
+        }
+        MODE_RAW => payload.len() == present * 8,
+        _ => false,
+    }
+}
+
+/// Iterates over integers in the compressed format, decoding them as they're read.
+pub struct IntIter<'a> {
+    count: usize,
+    index: usize,
+    presence: &'a [u8],
+    raw: bool,
+    payload: &'a [u8],
+
+    /// Values unpacked from the current simple8b word, and the position of the next one.
+    unpacked: Vec<u64>,
+    position: usize,
+    prev: i64,
+}
+
+impl<'a> IntIter<'a> {
+    pub fn new(raw: &'a [u8]) -> IntIter<'a> {
+        let count = match raw.get(..4) {
+            Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()) as usize,
+            None => 0,
+        };
+
+        let presence_len = count.div_ceil(8);
+        match raw.get(4 + presence_len) {
+            Some(&mode) => IntIter {
+                count,
+                index: 0,
+                presence: &raw[4..4 + presence_len],
+                raw: mode == MODE_RAW,
+                payload: &raw[5 + presence_len..],
+                unpacked: Vec::with_capacity(SELECTORS[0].0),
+                position: 0,
+                prev: 0,
+            },
+            None => IntIter {
+                count: 0,
+                index: 0,
+                presence: &[],
+                raw: true,
+                payload: &[],
+                unpacked: vec![],
+                position: 0,
+                prev: 0,
+            },
+        }
+    }
+
+    /// Read the next present value.
+    #[inline]
+    fn next_value(&mut self) -> Option<i64> {
+        if self.raw {
+            let (value, rest) = self.payload.split_at_checked(8)?;
+            self.payload = rest;
+            return Some(i64::from_le_bytes(value.try_into().unwrap()));
+        }
+
+        if self.position == self.unpacked.len() {
+            let (word, rest) = self.payload.split_at_checked(8)?;
+            self.payload = rest;
+            self.unpacked.clear();
+            unpack_simple8b(u64::from_le_bytes(word.try_into().unwrap()), &mut self.unpacked);
+            self.position = 0;
+        }
+
+        let delta = unzigzag(self.unpacked[self.position]);
+        self.position += 1;
+        self.prev = self.prev.wrapping_add(delta);
+        Some(self.prev)
+    }
+}
+
+impl<'a> Iterator for IntIter<'a> {
+    type Item = Option<i64>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let present = self.presence[self.index / 8] & (0x80 >> (self.index % 8)) != 0;
+        self.index += 1;
+        match present {
+            true => self.next_value().map(Some),
+            false => Some(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::block_int::{deserialize_ints, pack_simple8b, serialize_ints, unzigzag, validate_ints, zigzag};
+
+    #[test]
+    fn zigzags() {
+        for (value, encoded) in [(0, 0), (-1, 1), (1, 2), (-2, 3), (i64::MAX, u64::MAX - 1), (i64::MIN, u64::MAX)] {
+            assert_eq!(zigzag(value), encoded);
+            assert_eq!(unzigzag(encoded), value);
+        }
+    }
+
+    #[test]
+    fn packs_simple8b() {
+        // a run of unchanged values takes a single word
+        assert_eq!(pack_simple8b(&[0; 240]).unwrap().len(), 1);
+        assert_eq!(pack_simple8b(&[1; 60]).unwrap().len(), 1);
+        assert_eq!(pack_simple8b(&[1 << 59, 1]).unwrap().len(), 2);
+        assert_eq!(pack_simple8b(&[1 << 60]), None);
+    }
+
+    #[test]
+    fn full_loop() {
+        let vals = vec![Some(1), Some(2), None, Some(2), Some(-40), Some(9_007_199_254_740_993), None, Some(0)];
+        let serialized = serialize_ints(&vals);
+        assert!(validate_ints(&serialized, vals.len()));
+        assert_eq!(deserialize_ints(&serialized), vals);
+
+        // a counter that increases steadily packs many values into each word
+        let vals: Vec<_> = (0..1000).map(|i| Some(1_000_000 + i * 3)).collect();
+        let serialized = serialize_ints(&vals);
+        assert!(serialized.len() < 1000);
+        assert!(validate_ints(&serialized, vals.len()));
+        assert_eq!(deserialize_ints(&serialized), vals);
+    }
+
+    #[test]
+    fn falls_back_to_raw_values() {
+        let vals = vec![Some(i64::MIN), None, Some(i64::MAX), Some(0)];
+        let serialized = serialize_ints(&vals);
+        assert_eq!(serialized.len(), 4 + 1 + 1 + 3 * 8);
+        assert!(validate_ints(&serialized, vals.len()));
+        assert_eq!(deserialize_ints(&serialized), vals);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let vals = vec![Some(1), None, Some(3)];
+        let serialized = serialize_ints(&vals);
+        assert!(!validate_ints(&serialized, 4));
+        assert!(!validate_ints(&serialized[..serialized.len() - 1], 3));
+        assert!(validate_ints(&serialize_ints(&[None, None]), 2));
+        assert!(validate_ints(&serialize_ints(&[]), 0));
+    }
+}
@@ -1,31 +1,491 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use crate::storage::field_block::FieldStorageBlock;
+use std::io;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-/// BlockManager is responsible for intelligently caching field storage blocks in memory, loading
-/// them from disk when necessary. TODO: that's not actually what it does right now, pretty far
+use fnv::FnvHashMap;
+use memmap2::Mmap;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::DataValue;
+use crate::storage::field::FieldEntry;
+use crate::storage::field_block::{BlockView, corrupt_block, FieldStorageBlock};
+use crate::storage::field_index::FieldStorageBlockSummary;
+use crate::storage::storage_block::BlockHeader;
+
+/// The default memory budget of the block cache, in bytes.
+pub const DEFAULT_CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// Reads that span more blocks than this are treated as scans, see [BlockManager::scan].
+pub const SCAN_THRESHOLD: usize = 8;
+
+/// The block cache shared by all fields, unless they're given their own.
+pub static BLOCK_CACHE: Lazy<Arc<Mutex<BlockCache>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(BlockCache::new(DEFAULT_CACHE_CAPACITY)))
+});
+
+/// Used to give each block manager a unique id, so that blocks of different fields never collide
+/// in a shared cache.
+static NEXT_MANAGER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Blocks are identified by their block manager, and their offset in its data file. Blocks are
+/// never modified once written, only replaced by blocks at new offsets, so cached blocks never
+/// go stale.
+type BlockKey = (u64, u64);
+
+/// Statistics about the usage of a block cache.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+
+    /// Number of blocks currently in the cache.
+    pub blocks: usize,
+    /// Estimated memory used by the blocks in the cache, in bytes.
+    pub size: usize,
+    /// Memory budget of the cache, in bytes.
+    pub capacity: usize,
+}
+
+#[derive(Debug)]
+struct CachedBlock {
+    block: Arc<FieldStorageBlock>,
+    size: usize,
+    last_used: u64,
+}
+
+/// A cache of decoded blocks, bounded by an estimate of the memory they use. When the cache is
+/// over budget, the least recently used blocks are evicted first.
+#[derive(Debug)]
+pub struct BlockCache {
+    capacity: usize,
+    size: usize,
+
+    blocks: FnvHashMap<BlockKey, CachedBlock>,
+
+    /// Keys of cached blocks, ordered from least to most recently used. Every access is given a new
+    /// tick, so ticks are unique.
+    lru: BTreeMap<u64, BlockKey>,
+    tick: u64,
+
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity,
+            size: 0,
+            blocks: FnvHashMap::default(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Change the memory budget of the cache, evicting blocks if it's now over budget.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            blocks: self.blocks.len(),
+            size: self.size,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Returns a cached block, and marks it as the most recently used.
+    fn get(&mut self, key: BlockKey) -> Option<Arc<FieldStorageBlock>> {
+        self.tick += 1;
+        match self.blocks.get_mut(&key) {
+            Some(cached) => {
+                self.lru.remove(&cached.last_used);
+                self.lru.insert(self.tick, key);
+                cached.last_used = self.tick;
+
+                self.hits += 1;
+                Some(cached.block.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: BlockKey, block: Arc<FieldStorageBlock>) {
+        self.remove(key);
+
+        self.tick += 1;
+        let size = BlockCache::estimate_size(&block);
+        self.blocks.insert(key, CachedBlock { block, size, last_used: self.tick });
+        self.lru.insert(self.tick, key);
+        self.size += size;
+
+        self.evict();
+    }
+
+    fn remove(&mut self, key: BlockKey) {
+        if let Some(cached) = self.blocks.remove(&key) {
+            self.lru.remove(&cached.last_used);
+            self.size -= cached.size;
+        }
+    }
+
+    /// Number of blocks of a block manager in the cache.
+    fn count(&self, manager_id: u64) -> usize {
+        self.blocks.keys().filter(|(id, _)| *id == manager_id).count()
+    }
+
+    /// Remove all blocks of a block manager.
+    fn remove_all(&mut self, manager_id: u64) {
+        let keys: Vec<_> = self.blocks.keys().filter(|(id, _)| *id == manager_id).copied().collect();
+        for key in keys {
+            self.remove(key);
+        }
+    }
+
+    /// Evict the least recently used blocks until the cache is within its budget.
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let key = match self.lru.first_key_value() {
+                Some((_, &key)) => key,
+                None => break,
+            };
+
+            self.remove(key);
+            self.evictions += 1;
+        }
+    }
+
+    /// Strings are stored on the heap, outside of the block's entries, so they're counted
+    /// separately.
+    #[inline]
+    fn estimate_size(block: &FieldStorageBlock) -> usize {
+        let strings: usize = block.entries.iter()
+            .map(|entry| match &entry.value {
+                DataValue::String(s) => s.capacity(),
+                _ => 0,
+            })
+            .sum();
+
+        size_of::<CachedBlock>() + size_of::<FieldStorageBlock>() + block.entries.capacity() * size_of::<FieldEntry>() + strings
+    }
+}
+
+/// BlockManager is responsible for loading the blocks of a single field from disk, caching them
+/// in memory in a [BlockCache], which is usually shared with all other fields.
+///
+/// Blocks are read from a memory mapping of the field's data file, so they can be decoded in
+/// place, without first copying them into a buffer.
 #[derive(Debug)]
 pub struct BlockManager {
-    pub data_file: File,
+    id: u64,
+    data_file: File,
+    cache: Arc<Mutex<BlockCache>>,
 
-    // key is the block index
-    pub blocks: Vec<FieldStorageBlock>,
+    /// The data file, memory mapped. It grows as blocks are flushed, so it's mapped again whenever
+    /// a block past the end of the current mapping is read.
+    mmap: Mutex<Option<Arc<Mmap>>>,
 }
 
 impl BlockManager {
+    /// Create a block manager for the given data file, which caches blocks in the global cache.
     pub fn new(data_file: File) -> BlockManager {
+        BlockManager::with_cache(data_file, BLOCK_CACHE.clone())
+    }
+
+    pub fn with_cache(data_file: File, cache: Arc<Mutex<BlockCache>>) -> BlockManager {
         BlockManager {
+            id: NEXT_MANAGER_ID.fetch_add(1, Ordering::Relaxed),
             data_file,
-            blocks: vec![],
+            cache,
+            mmap: Mutex::new(None),
+        }
+    }
+
+    /// Returns the block described by the given summary, loading it from disk if it isn't cached.
+    ///
+    /// Fails if the block can't be read, or is corrupt.
+    pub fn load(&self, summary: &FieldStorageBlockSummary) -> io::Result<Arc<FieldStorageBlock>> {
+        let key = (self.id, summary.offset);
+        if let Some(block) = self.cache.lock().unwrap().get(key) {
+            return Ok(block);
+        }
+
+        // the cache isn't locked while reading from disk, so other fields aren't blocked by it
+        let block = match self.mapped(summary) {
+            Some(mmap) => FieldStorageBlock::deserialize(BlockManager::block_bytes(&mmap, summary))
+                .map_err(|error| corrupt_block(summary, error))?,
+            None => FieldStorageBlock::load(&self.data_file, summary)?,
+        };
+
+        let block = Arc::new(block);
+        self.cache.lock().unwrap().insert(key, block.clone());
+        Ok(block)
+    }
+
+    /// Append the entries of a block with a timestamp within `[from, until]` to out.
+    ///
+    /// Unlike [BlockManager::load], blocks that aren't cached are decoded straight from the memory
+    /// mapped data file into out, and aren't added to the cache. This way, scans over large time
+    /// ranges don't allocate for each block, and don't evict the blocks other queries are using.
+    ///
+    /// Fails if the block can't be read, or is corrupt.
+    pub fn scan(&self, summary: &FieldStorageBlockSummary, from: Option<i64>, until: Option<i64>, out: &mut Vec<FieldEntry>) -> io::Result<()> {
+        if let Some(block) = self.cache.lock().unwrap().get((self.id, summary.offset)) {
+            out.extend_from_slice(block.range(from, until));
+            return Ok(());
         }
+
+        match self.mapped(summary) {
+            Some(mmap) => {
+                BlockView::parse(BlockManager::block_bytes(&mmap, summary))
+                    .and_then(|view| view.read_into(from, until, out))
+                    .map_err(|error| corrupt_block(summary, error))?;
+            }
+            None => out.extend_from_slice(FieldStorageBlock::load(&self.data_file, summary)?.range(from, until)),
+        }
+        Ok(())
+    }
+
+    /// Returns the number of entries in a block, which for blocks that aren't cached is read from
+    /// the block's header, without decoding it.
+    pub fn count(&self, summary: &FieldStorageBlockSummary) -> io::Result<usize> {
+        if let Some(block) = self.cache.lock().unwrap().get((self.id, summary.offset)) {
+            return Ok(block.entries.len());
+        }
+
+        match self.mapped(summary) {
+            Some(mmap) => match BlockHeader::deserialize(BlockManager::block_bytes(&mmap, summary)) {
+                Ok(header) => Ok(header.count as usize),
+                Err(error) => Err(corrupt_block(summary, error)),
+            },
+            None => Ok(FieldStorageBlock::load(&self.data_file, summary)?.entries.len()),
+        }
+    }
+
+    /// Returns a memory mapping of the data file that includes the given block, or None if the
+    /// file couldn't be mapped.
+    fn mapped(&self, summary: &FieldStorageBlockSummary) -> Option<Arc<Mmap>> {
+        let end = summary.offset + summary.length;
+
+        let mut mmap = self.mmap.lock().unwrap();
+        if !matches!(&*mmap, Some(mmap) if mmap.len() as u64 >= end) {
+            // SAFETY: the data file is only ever shrunk by [BlockManager::truncate], which holds
+            // this lock, and only does so once no mapping of the file is left, so the file never
+            // shrinks under a live mapping. Blocks are never modified once written, and space is
+            // only reclaimed by punching holes where the index no longer has any blocks, so while
+            // other parts of the mapping may change, the bytes of the blocks we read don't
+            *mmap = unsafe { Mmap::map(&self.data_file) }.ok().map(Arc::new);
+        }
+
+        mmap.clone().filter(|mmap| mmap.len() as u64 >= end)
     }
 
-    pub fn load(&mut self, block_offset: usize) -> &FieldStorageBlock {
-        if block_offset < self.blocks.len() {
-            return &self.blocks[block_offset];
+    /// Truncate the data file to the given length, e.g. to cut off a partially written block.
+    ///
+    /// Accessing a mapping past the end of its file is a bus error, so the cached mapping is
+    /// dropped first, and the file is left as is if a read is still using a mapping of it.
+    pub fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut mmap = self.mmap.lock().unwrap();
+        if matches!(&*mmap, Some(mmap) if Arc::strong_count(mmap) > 1) {
+            return Err(io::Error::other("data file is still mapped by a read"));
         }
 
-        let block = FieldStorageBlock::load(&self.data_file, block_offset);
-        self.blocks.push(block);
-        &self.blocks[block_offset]
+        *mmap = None;
+        self.data_file.set_len(len)
+    }
+
+    #[inline]
+    fn block_bytes<'a>(mmap: &'a Mmap, summary: &FieldStorageBlockSummary) -> &'a [u8] {
+        &mmap[summary.offset as usize..(summary.offset + summary.length) as usize]
+    }
+
+    /// Cache a block that was just written to disk, since recent data is the most likely to be
+    /// queried.
+    pub fn insert(&self, summary: &FieldStorageBlockSummary, block: FieldStorageBlock) {
+        self.cache.lock().unwrap().insert((self.id, summary.offset), Arc::new(block));
+    }
+
+    /// Number of this manager's blocks that are currently cached.
+    pub fn cached_blocks(&self) -> usize {
+        self.cache.lock().unwrap().count(self.id)
+    }
+
+    /// Remove a block from the cache, typically because it was replaced.
+    pub fn evict(&self, summary: &FieldStorageBlockSummary) {
+        self.cache.lock().unwrap().remove((self.id, summary.offset));
+    }
+}
+
+impl Drop for BlockManager {
+    fn drop(&mut self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.remove_all(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::{File, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use std::sync::{Arc, Mutex};
+    use crate::storage::data_dir;
+
+    use crate::DataValue;
+    use crate::storage::block_manager::{BlockCache, BlockManager};
+    use crate::storage::field::FieldEntry;
+    use crate::storage::field_block::FieldStorageBlock;
+    use crate::storage::field_index::FieldStorageBlockSummary;
+    use crate::wire_protocol::DataType;
+
+    /// Serialize blocks of 10 entries each, starting at the given block, and return them along
+    /// with their summaries, as if they were written at the given offset.
+    fn write_blocks(blocks: std::ops::Range<i64>, offset: usize) -> (Vec<u8>, Vec<FieldStorageBlockSummary>) {
+        let mut data = vec![];
+        let mut summaries = vec![];
+        for i in blocks {
+            let mut block = FieldStorageBlock::new();
+            block.entries = (0..10).map(|j| FieldEntry { time: i * 10 + j, value: DataValue::from(i as f64) }).collect();
+
+            let bytes = block.serialize(&DataType::Float).unwrap();
+            summaries.push(FieldStorageBlockSummary::from_entries(&block.entries, (offset + data.len()) as u64, bytes.len() as u64));
+            data.extend(bytes);
+        }
+
+        (data, summaries)
+    }
+
+    #[test]
+    fn it_scans_mapped_blocks() {
+        let _ = fs::remove_dir_all(format!("{}/test_block_scan", data_dir()));
+        fs::create_dir_all(format!("{}/test_block_scan", data_dir())).unwrap();
+
+        let (data, summaries) = write_blocks(0..3, 0);
+        fs::write(format!("{}/test_block_scan/field1", data_dir()), &data).unwrap();
+
+        let cache = Arc::new(Mutex::new(BlockCache::new(1024 * 1024)));
+        let manager = BlockManager::with_cache(File::open(format!("{}/test_block_scan/field1", data_dir())).unwrap(), cache.clone());
+
+        let mut records = vec![];
+        for summary in &summaries {
+            manager.scan(summary, Some(5), Some(24), &mut records).unwrap();
+        }
+        assert_eq!(records.iter().map(|r| r.time).collect::<Vec<_>>(), (5..=24).collect::<Vec<_>>());
+        assert_eq!(cache.lock().unwrap().stats().blocks, 0);
+
+        // blocks appended after the file was mapped are read from a new mapping
+        let (more, more_summaries) = write_blocks(3..4, data.len());
+        OpenOptions::new().append(true).open(format!("{}/test_block_scan/field1", data_dir())).unwrap().write_all(&more).unwrap();
+
+        let mut records = vec![];
+        manager.scan(&more_summaries[0], None, None, &mut records).unwrap();
+        assert_eq!(records.iter().map(|r| r.time).collect::<Vec<_>>(), (30..40).collect::<Vec<_>>());
+
+        // cached blocks are scanned from the cache
+        assert_eq!(manager.load(&summaries[1]).unwrap().entries[0].time, 10);
+        let mut records = vec![];
+        manager.scan(&summaries[1], Some(12), Some(13), &mut records).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(cache.lock().unwrap().stats().hits, 1);
+    }
+
+    #[test]
+    fn it_fails_on_corrupt_blocks() {
+        let _ = fs::remove_dir_all(format!("{}/test_block_corrupt", data_dir()));
+        fs::create_dir_all(format!("{}/test_block_corrupt", data_dir())).unwrap();
+
+        let (mut data, summaries) = write_blocks(0..2, 0);
+        // an unknown block format version
+        data[summaries[1].offset as usize] += 1;
+        fs::write(format!("{}/test_block_corrupt/field1", data_dir()), &data).unwrap();
+
+        let cache = Arc::new(Mutex::new(BlockCache::new(1024 * 1024)));
+        let manager = BlockManager::with_cache(File::open(format!("{}/test_block_corrupt/field1", data_dir())).unwrap(), cache.clone());
+
+        assert_eq!(manager.load(&summaries[0]).unwrap().entries.len(), 10);
+        assert_eq!(manager.load(&summaries[1]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(manager.scan(&summaries[1], None, None, &mut vec![]).is_err());
+        assert!(manager.count(&summaries[1]).is_err());
+        assert_eq!(cache.lock().unwrap().stats().blocks, 1);
+    }
+
+    #[test]
+    fn it_truncates_unmapped_files() {
+        let _ = fs::remove_dir_all(format!("{}/test_block_truncate", data_dir()));
+        fs::create_dir_all(format!("{}/test_block_truncate", data_dir())).unwrap();
+
+        let (data, summaries) = write_blocks(0..2, 0);
+        fs::write(format!("{}/test_block_truncate/field1", data_dir()), &data).unwrap();
+
+        let file = OpenOptions::new().read(true).write(true).open(format!("{}/test_block_truncate/field1", data_dir())).unwrap();
+        let manager = BlockManager::with_cache(file, Arc::new(Mutex::new(BlockCache::new(0))));
+        assert_eq!(manager.count(&summaries[1]).unwrap(), 10);
+
+        // a mapping that's still in use keeps the file from being truncated
+        let mmap = manager.mapped(&summaries[1]).unwrap();
+        assert!(manager.truncate(summaries[1].offset).is_err());
+        drop(mmap);
+
+        // the cached mapping is dropped along with the truncated block, so it's never read again
+        manager.truncate(summaries[1].offset).unwrap();
+        assert_eq!(fs::metadata(format!("{}/test_block_truncate/field1", data_dir())).unwrap().len(), summaries[1].offset);
+        assert_eq!(manager.count(&summaries[0]).unwrap(), 10);
+        assert!(manager.mapped(&summaries[1]).is_none());
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used_blocks() {
+        let _ = fs::remove_dir_all(format!("{}/test_block_cache", data_dir()));
+        fs::create_dir_all(format!("{}/test_block_cache", data_dir())).unwrap();
+
+        let (data, summaries) = write_blocks(0..4, 0);
+        fs::write(format!("{}/test_block_cache/field1", data_dir()), data).unwrap();
+
+        let block_size = BlockCache::estimate_size(&FieldStorageBlock::load(&File::open(format!("{}/test_block_cache/field1", data_dir())).unwrap(), &summaries[0]).unwrap());
+        let cache = Arc::new(Mutex::new(BlockCache::new(block_size * 2)));
+        let manager = BlockManager::with_cache(File::open(format!("{}/test_block_cache/field1", data_dir())).unwrap(), cache.clone());
+
+        // blocks are loaded by offset, in any order, from a memory mapping of the file
+        assert_eq!(manager.load(&summaries[2]).unwrap().entries[0].time, 20);
+        assert_eq!(manager.load(&summaries[0]).unwrap().entries[0].time, 0);
+        assert_eq!(manager.load(&summaries[2]).unwrap().entries[0].time, 20);
+
+        // block 0 is the least recently used, so it's evicted first
+        assert_eq!(manager.load(&summaries[3]).unwrap().entries[0].time, 30);
+        let stats = cache.lock().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions, stats.blocks), (1, 3, 1, 2));
+        assert!(stats.size <= stats.capacity);
+
+        manager.load(&summaries[2]).unwrap();
+        manager.load(&summaries[0]).unwrap();
+        let stats = cache.lock().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 4, 2));
+
+        cache.lock().unwrap().set_capacity(0);
+        assert_eq!(cache.lock().unwrap().stats().blocks, 0);
+
+        // blocks of a manager are dropped along with it
+        cache.lock().unwrap().set_capacity(block_size * 2);
+        manager.load(&summaries[1]).unwrap();
+        drop(manager);
+        assert_eq!(cache.lock().unwrap().stats().size, 0);
     }
 }
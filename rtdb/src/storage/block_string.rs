@@ -0,0 +1,179 @@
+//! Dictionary encoding for blocks of strings.
+//!
+//! Fields of strings, such as status codes or firmware versions, tend to take few distinct values,
+//! so each distinct value is only stored once, in a dictionary, and values are stored as indices
+//! into it. A block is laid out as:
+//!
+//! [COUNT] [DICT_COUNT] [DICTIONARY] [INDICES]
+//! u32     u32          strings      COUNT indices
+//!
+//! where each string of the dictionary is prefixed by its length in bytes as a u32, and indices
+//! are 1, 2 or 4 bytes wide, whichever is the smallest that fits DICT_COUNT + 1 values. The largest
+//! index of that width marks a missing value. All integers are little endian.
+
+use std::collections::HashMap;
+
+/// The width of each index in bytes, for a dictionary of the given size.
+#[inline]
+fn index_width(dict_count: usize) -> usize {
+    if dict_count < u8::MAX as usize {
+        1
+    } else if dict_count < u16::MAX as usize {
+        2
+    } else {
+        4
+    }
+}
+
+/// Serialize strings into a dictionary encoded format.
+pub fn serialize_strings(values: &[Option<String>]) -> Vec<u8> {
+    let mut dictionary: Vec<&str> = vec![];
+    let mut lookup: HashMap<&str, u32> = HashMap::new();
+    let indices: Vec<Option<u32>> = values.iter()
+        .map(|value| value.as_deref().map(|value| *lookup.entry(value).or_insert_with(|| {
+            dictionary.push(value);
+            dictionary.len() as u32 - 1
+        })))
+        .collect();
+
+    let width = index_width(dictionary.len());
+    let dict_len: usize = dictionary.iter().map(|s| 4 + s.len()).sum();
+    let mut buffer = Vec::with_capacity(8 + dict_len + width * values.len());
+    buffer.extend((values.len() as u32).to_le_bytes());
+    buffer.extend((dictionary.len() as u32).to_le_bytes());
+
+    for value in &dictionary {
+        buffer.extend((value.len() as u32).to_le_bytes());
+        buffer.extend(value.as_bytes());
+    }
+
+    for index in indices {
+        match width {
+            1 => buffer.push(index.map_or(u8::MAX, |i| i as u8)),
+            2 => buffer.extend(index.map_or(u16::MAX, |i| i as u16).to_le_bytes()),
+            _ => buffer.extend(index.unwrap_or(u32::MAX).to_le_bytes()),
+        }
+    }
+
+    buffer
+}
+
+/// Deserialize strings from the format produced by [serialize_strings].
+pub fn deserialize_strings(raw: &[u8]) -> Vec<Option<String>> {
+    StringIter::new(raw).collect()
+}
+
+/// Check that a serialized block of strings is well formed, and holds exactly `count` values.
+pub fn validate_strings(raw: &[u8], count: usize) -> bool {
+    match BlockParts::parse(raw) {
+        Some(parts) => parts.count == count
+            && parts.indices.len() == count * index_width(parts.dictionary.len())
+            && parts.dictionary.iter().all(|value| std::str::from_utf8(value).is_ok()),
+        None => false,
+    }
+}
+
+/// A serialized block, split into its parts.
+struct BlockParts<'a> {
+    count: usize,
+    dictionary: Vec<&'a [u8]>,
+    indices: &'a [u8],
+}
+
+impl<'a> BlockParts<'a> {
+    /// Split a serialized block into its parts, or return None if it's too short to hold them.
+    fn parse(raw: &'a [u8]) -> Option<BlockParts<'a>> {
+        let read_u32 = |offset: usize| raw.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize);
+
+        let count = read_u32(0)?;
+        let dict_count = read_u32(4)?;
+
+        let mut offset = 8;
+        let mut dictionary = Vec::with_capacity(dict_count.min(raw.len()));
+        for _ in 0..dict_count {
+            let len = read_u32(offset)?;
+            dictionary.push(raw.get(offset + 4..offset + 4 + len)?);
+            offset += 4 + len;
+        }
+
+        Some(BlockParts { count, dictionary, indices: &raw[offset..] })
+    }
+}
+
+/// Iterates over strings in the dictionary encoded format, decoding them as they're read. A block
+/// that isn't well formed yields no values.
+pub struct StringIter<'a> {
+    dictionary: Vec<&'a [u8]>,
+    indices: &'a [u8],
+    width: usize,
+}
+
+impl<'a> StringIter<'a> {
+    pub fn new(raw: &'a [u8]) -> StringIter<'a> {
+        match BlockParts::parse(raw) {
+            Some(parts) => {
+                let width = index_width(parts.dictionary.len());
+                StringIter { dictionary: parts.dictionary, indices: parts.indices, width }
+            }
+            None => StringIter { dictionary: vec![], indices: &[], width: 1 },
+        }
+    }
+}
+
+impl<'a> Iterator for StringIter<'a> {
+    type Item = Option<String>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.indices.len() < self.width {
+            return None;
+        }
+
+        let (index, rest) = self.indices.split_at(self.width);
+        self.indices = rest;
+
+        let index = match self.width {
+            1 => index[0] as usize,
+            2 => u16::from_le_bytes(index.try_into().unwrap()) as usize,
+            _ => u32::from_le_bytes(index.try_into().unwrap()) as usize,
+        };
+
+        // the index marking a missing value is always past the end of the dictionary
+        Some(self.dictionary.get(index).map(|value| String::from_utf8_lossy(value).into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::block_string::{deserialize_strings, serialize_strings, validate_strings};
+
+    #[test]
+    fn full_loop() {
+        let vals = vec![Some(String::from("ok")), None, Some(String::from("error")), Some(String::from("ok")),
+                        Some(String::new()), Some(String::from("ok"))];
+        let serialized = serialize_strings(&vals);
+        assert!(validate_strings(&serialized, vals.len()));
+
+        // each distinct string is only stored once
+        assert_eq!(serialized.len(), 8 + (4 + 2) + (4 + 5) + 4 + vals.len());
+        assert_eq!(deserialize_strings(&serialized), vals);
+    }
+
+    #[test]
+    fn widens_indices() {
+        let vals: Vec<_> = (0..300).map(|i| Some(format!("v{}", i))).chain([None]).collect();
+        let serialized = serialize_strings(&vals);
+        assert!(validate_strings(&serialized, vals.len()));
+        assert_eq!(deserialize_strings(&serialized), vals);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let serialized = serialize_strings(&[Some(String::from("ok")), None]);
+        assert!(!validate_strings(&serialized, 3));
+        assert!(!validate_strings(&serialized[..serialized.len() - 1], 2));
+        assert!(!validate_strings(&serialized[..6], 2));
+        assert!(deserialize_strings(&serialized[..6]).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Compression for the timestamp column of a block.
+//!
+//! Most series are written at a regular cadence, so rather than storing each timestamp in full, we
+//! store the difference between consecutive deltas, i.e. the "delta of deltas", which is zero for
+//! evenly spaced timestamps. Each number is zigzag encoded, so that small negative numbers stay
+//! small, and then written as a variable-length integer, taking a single byte for anything below
+//! 64 in absolute value.
+//!
+//! A column is laid out as:
+//!
+//! [COUNT] [FIRST] [FIRST DELTA] [DELTA OF DELTAS...]
+//! varint  varint  varint        varint
+//!
+//! The timestamp column is stored separately from the values, so that scans that only care about
+//! time, such as counting entries, never need to decode the values.
+
+/// Serialize timestamps into a compressed format.
+pub fn serialize_timestamps(times: &[i64]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(times.len() + 16);
+    write_varint(&mut buffer, times.len() as u64);
+
+    let mut prev = 0i64;
+    let mut prev_delta = 0i64;
+    for (i, &time) in times.iter().enumerate() {
+        match i {
+            0 => write_varint(&mut buffer, zigzag(time)),
+            _ => {
+                let delta = time.wrapping_sub(prev);
+                write_varint(&mut buffer, zigzag(delta.wrapping_sub(prev_delta)));
+                prev_delta = delta;
+            }
+        }
+        prev = time;
+    }
+
+    buffer
+}
+
+/// Deserialize timestamps from the compressed format produced by [serialize_timestamps].
+pub fn deserialize_timestamps(raw: &[u8]) -> Vec<i64> {
+    TimestampIter::new(raw).collect()
+}
+
+/// Iterates over timestamps in the compressed format, decoding them as they're read.
+pub struct TimestampIter<'a> {
+    raw: &'a [u8],
+    index: usize,
+
+    remaining: usize,
+    prev: Option<i64>,
+    delta: i64,
+}
+
+impl<'a> TimestampIter<'a> {
+    pub fn new(raw: &'a [u8]) -> TimestampIter<'a> {
+        let mut index = 0;
+        let remaining = read_varint(raw, &mut index).unwrap_or(0) as usize;
+
+        TimestampIter { raw, index, remaining, prev: None, delta: 0 }
+    }
+}
+
+impl<'a> Iterator for TimestampIter<'a> {
+    type Item = i64;
+
+    #[inline]
+    fn next(&mut self) -> Option<i64> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let value = match read_varint(self.raw, &mut self.index) {
+            Some(value) => unzigzag(value),
+            None => {
+                // the column is truncated or corrupt, so nothing after this point can be trusted
+                self.remaining = 0;
+                return None;
+            }
+        };
+        let time = match self.prev {
+            None => value,
+            Some(prev) => {
+                self.delta = self.delta.wrapping_add(value);
+                prev.wrapping_add(self.delta)
+            }
+        };
+
+        self.prev = Some(time);
+        Some(time)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// Check that a serialized column is well formed, and holds exactly `count` timestamps.
+pub fn validate_timestamps(raw: &[u8], count: usize) -> bool {
+    let mut index = 0;
+    if read_varint(raw, &mut index) != Some(count as u64) {
+        return false;
+    }
+
+    (0..count).all(|_| read_varint(raw, &mut index).is_some()) && index == raw.len()
+}
+
+/// Map signed integers to unsigned ones, such that numbers with a small absolute value map to
+/// small numbers: 0 -> 0, -1 -> 1, 1 -> 2, -2 -> 3, ...
+#[inline]
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[inline]
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+/// Write an unsigned integer as a LEB128 variable-length integer, 7 bits per byte, with the high
+/// bit of each byte set if more bytes follow.
+#[inline]
+fn write_varint(buffer: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buffer.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buffer.push(n as u8);
+}
+
+/// Read a variable-length integer written by [write_varint], or None if the input ends before the
+/// integer does, or it doesn't fit in 64 bits.
+#[inline]
+fn read_varint(raw: &[u8], index: &mut usize) -> Option<u64> {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        if shift > 63 {
+            return None;
+        }
+        let byte = *raw.get(*index)?;
+        *index += 1;
+
+        n |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::block_time::{deserialize_timestamps, serialize_timestamps, unzigzag, validate_timestamps, zigzag};
+
+    #[test]
+    fn zigzags() {
+        for (n, expected) in [(0, 0), (-1, 1), (1, 2), (-2, 3), (i64::MAX, u64::MAX - 1), (i64::MIN, u64::MAX)] {
+            assert_eq!(zigzag(n), expected);
+            assert_eq!(unzigzag(expected), n);
+        }
+    }
+
+    #[test]
+    fn full_loop() {
+        let times = vec![1663644227213092171, 1663644227213092181, 1663644227213092191, 1663644227213092190,
+                         1663644227313092171, -5, i64::MAX, i64::MIN, 0];
+        let serialized = serialize_timestamps(&times);
+
+        assert!(validate_timestamps(&serialized, times.len()));
+        assert_eq!(deserialize_timestamps(&serialized), times);
+
+        assert!(deserialize_timestamps(&serialize_timestamps(&[])).is_empty());
+    }
+
+    #[test]
+    fn compresses_regular_timestamps() {
+        // one entry every second, with a little jitter
+        let times: Vec<_> = (0..1000).map(|i| 1663644227000000000 + i * 1_000_000_000 + (i % 3)).collect();
+        let serialized = serialize_timestamps(&times);
+
+        assert!(serialized.len() < 2 * times.len(), "{} bytes", serialized.len());
+        assert_eq!(deserialize_timestamps(&serialized), times);
+    }
+
+    #[test]
+    fn rejects_corrupt_timestamps() {
+        let times: Vec<_> = (0..100).map(|i| i * 1_000_000_007).collect();
+        let serialized = serialize_timestamps(&times);
+        assert!(!validate_timestamps(&serialized, times.len() + 1));
+
+        // every truncation of the column decodes fewer timestamps, rather than panicking
+        for len in 0..serialized.len() {
+            assert!(!validate_timestamps(&serialized[..len], times.len()));
+            assert!(deserialize_timestamps(&serialized[..len]).len() < times.len());
+        }
+
+        // a varint that never ends, or doesn't fit in 64 bits
+        assert!(deserialize_timestamps(&[0x02, 0x80, 0x80]).is_empty());
+        assert!(deserialize_timestamps(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).is_empty());
+        assert!(!validate_timestamps(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01], 1));
+    }
+}
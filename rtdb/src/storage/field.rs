@@ -1,22 +1,28 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions, remove_file, rename};
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
 
-use std::sync::{Arc, Mutex};
 
 // bytecheck can be used to validate your data if you want
 use bytecheck::CheckBytes;
+use log::error;
 use rkyv::{Archive, Deserialize, Serialize};
 use crate::DataValue;
-use crate::storage::block_manager::BlockManager;
-use crate::storage::DEFAULT_DATA_DIR;
-
-use crate::storage::field_block::FieldStorageBlock;
-use crate::storage::field_index::FieldStorageBlockSummary;
+use crate::storage::block_manager::{BlockManager, SCAN_THRESHOLD};
+use crate::storage::data_dir;
+
+use crate::storage::field_block::{ENTRIES_PER_BLOCK, FieldStorageBlock};
+use crate::storage::field_index::{FieldStorageBlockSummary, write_index_header};
+use crate::storage::metadata::DuplicatePolicy;
+use crate::storage::series::SeriesSummary;
+use crate::storage::tombstone::Tombstone;
 use crate::wire_protocol::DataType;
 
 // TODO: Another idea is to not have the time in record, which can be really redundant in the common case
 //  of multiple fields being written under the same series entry (i.e. with the same timestamp)
-#[derive(Archive, Copy, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[derive(Archive, Clone, Deserialize, Serialize, Debug, PartialEq)]
 #[archive(compare(PartialEq))]
 #[archive_attr(derive(CheckBytes, Debug))]
 pub struct FieldEntry {
@@ -31,135 +37,868 @@ pub struct FieldStorage {
     pub name: String,
     pub data_type: DataType,
 
+    /// How entries at a time that already has an entry are handled.
+    pub duplicates: DuplicatePolicy,
+
+    series_name: String,
+
     data_file_handle: File,
     index_file_handle: File,
 
     block_summaries: Vec<FieldStorageBlockSummary>,
 
+    /// Size of the data file, which is where the next block will be written.
+    data_len: u64,
+
     // buffer to be written as a block
     curr_block: FieldStorageBlock,
 
-    // todo
-    block_manager: Arc<Mutex<BlockManager>>,
+    /// Late entries, whose timestamps fall within blocks that were already flushed to disk. They
+    /// are merged into those blocks once enough of them accumulate, see [FieldStorage::compact].
+    overlay: FieldStorageBlock,
+
+    /// Deleted time ranges, whose entries may still be in flushed blocks until they're purged by
+    /// [FieldStorage::compact].
+    tombstones: Vec<Tombstone>,
+
+    block_manager: BlockManager,
 }
 
 impl FieldStorage {
     // TODO: actually, should a lot of this work be moved to the block manager?
     // TODO: split new into load and new, and load summaries accordingly
-    pub fn load<'a>(series_name: &str, field_name: &str) -> FieldStorage {
-        let (data_file, index_file) = FieldStorage::get_files(series_name, field_name, true);
+    /// Load a field from disk, creating its files if it doesn't exist yet.
+    ///
+    /// Fails if the field's files can't be opened, its index was written by an incompatible
+    /// version of the database, or its tombstones can't be read.
+    pub fn load(series_name: &str, field_name: &str, data_type: DataType, duplicates: DuplicatePolicy) -> io::Result<FieldStorage> {
+        let (data_file, mut index_file) = FieldStorage::get_files(series_name, field_name, true)?;
 
         // TODO: tmp
-        let (data_file2, _) = FieldStorage::get_files(series_name, field_name, true);
+        let (data_file2, _) = FieldStorage::get_files(series_name, field_name, true)?;
 
         // TODO: reorganize, this is redundant
-        let filename = format!("{}/{}/{}", DEFAULT_DATA_DIR, series_name, field_name);
+        let filename = format!("{}/{}/{}", data_dir(), series_name, field_name);
         let index_filename = format!("{}_index", filename);
-        let summaries = FieldStorageBlockSummary::load_all(&index_filename);
-
-        if summaries.len() > 0 {
-            // TODO: figure out datatype, maybe we should just save it, but that means saving a new file? or maybe as extension?
+        let summaries = FieldStorageBlockSummary::load_all(&index_filename)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if index_file.metadata()?.len() == 0 {
+            write_index_header(&mut index_file)?;
         }
+        let data_len = data_file.metadata()?.len();
+        let tombstones = Tombstone::load_all(&format!("{}/{}/_{}_tombstones", data_dir(), series_name, field_name))?;
 
-        FieldStorage {
-            data_type: DataType::Float, // TODO
+        Ok(FieldStorage {
+            data_type,
+            duplicates,
             name: field_name.to_owned(),
+            series_name: series_name.to_owned(),
             block_summaries: summaries,
+            data_len,
             curr_block: FieldStorageBlock::new(),
+            overlay: FieldStorageBlock::new(),
+            tombstones,
             data_file_handle: data_file,
             index_file_handle: index_file,
-            block_manager: Arc::new(Mutex::new(BlockManager::new(data_file2))),
+            block_manager: BlockManager::new(data_file2),
+        })
+    }
+
+    /// Returns all entries within `[start, end]`. Fails if a block can't be read, or is corrupt.
+    pub fn read(&self, start: Option<i64>, end: Option<i64>) -> io::Result<Vec<FieldEntry>> {
+        let mut records = self.read_blocks(start, end)?;
+        records.extend_from_slice(self.curr_block.range(start, end));
+
+        match self.overlay.entries.is_empty() {
+            true => Ok(records),
+            false => Ok(merge_sorted(records, self.overlay.read(start, end))),
         }
     }
 
-    pub fn read(&self, start: Option<i64>, end: Option<i64>) -> Vec<FieldEntry> {
-        // TODO: use a modified binary search to narrow down which blocks we scan, using summaries
-        let start_block = 0;
-        let end_block = self.block_summaries.len();
+    /// Returns all entries within `[start, end]` that were flushed to disk.
+    fn read_blocks(&self, start: Option<i64>, end: Option<i64>) -> io::Result<Vec<FieldEntry>> {
+        // blocks are flushed in time order, so their summaries are sorted by time, which lets us
+        // binary search for the range of blocks that overlap [start, end]
+        let start_block = match start {
+            Some(start) => self.block_summaries.partition_point(|summary| summary.latest_timestamp < start),
+            None => 0,
+        };
+        let end_block = match end {
+            Some(end) => self.block_summaries.partition_point(|summary| summary.start_timestamp <= end),
+            None => self.block_summaries.len(),
+        };
+
+        let summaries = &self.block_summaries[start_block..end_block];
+        let mut records = Vec::with_capacity((summaries.len() + 1) * ENTRIES_PER_BLOCK);
 
-        let mut block_manager = self.block_manager.lock().unwrap();
-        let mut records: Vec<_> = (start_block..end_block).flat_map(|offset| {
-            let block = block_manager.load(offset);
-            block.read(start, end)
-        }).collect();
-        records.extend(self.curr_block.read(start, end));
+        // large scans stream through blocks, rather than loading each of them into the cache
+        let scan = summaries.len() > SCAN_THRESHOLD;
+        for summary in summaries {
+            let from = records.len();
+            match scan {
+                true => self.block_manager.scan(summary, start, end, &mut records)?,
+                false => records.extend_from_slice(self.block_manager.load(summary)?.range(start, end)),
+            }
+            self.remove_deleted(summary, &mut records, from);
+        }
 
-        records
+        Ok(records)
     }
 
-    pub fn insert(&mut self, entry: FieldEntry) {
+    /// Remove the entries in `records[from..]`, which were read from the given block, that were
+    /// deleted after the block was written.
+    fn remove_deleted(&self, summary: &FieldStorageBlockSummary, records: &mut Vec<FieldEntry>, from: usize) {
+        let tombstones: Vec<_> = self.tombstones.iter().filter(|t| t.applies_to(summary)).collect();
+        if tombstones.is_empty() {
+            return;
+        }
+
+        let read = records.split_off(from);
+        records.extend(read.into_iter().filter(|entry| !tombstones.iter().any(|t| t.covers(entry.time))));
+    }
+
+    /// Count the field's entries, and find its earliest and latest ones.
+    ///
+    /// Entry counts are read from block headers, so only blocks with deleted entries, and those
+    /// that late entries fall into, have to be read.
+    pub fn summarize(&self) -> io::Result<SeriesSummary> {
+        let mut count = self.curr_block.entries.len();
+        let mut first = self.curr_block.entries.first().map(|e| e.time);
+        let mut last = self.curr_block.entries.last().map(|e| e.time);
+
+        for summary in &self.block_summaries {
+            let (block_count, range) = match self.tombstones.iter().any(|t| t.applies_to(summary)) {
+                false => (self.block_manager.count(summary)?, Some((summary.start_timestamp, summary.latest_timestamp))),
+                true => {
+                    let mut entries = vec![];
+                    self.block_manager.scan(summary, None, None, &mut entries)?;
+                    self.remove_deleted(summary, &mut entries, 0);
+                    (entries.len(), entries.first().zip(entries.last()).map(|(a, b)| (a.time, b.time)))
+                }
+            };
+
+            count += block_count;
+            if let Some((start, end)) = range {
+                first = Some(first.map_or(start, |first| first.min(start)));
+                last = Some(last.map_or(end, |last| last.max(end)));
+            }
+        }
+
+        // late entries replace flushed entries with the same timestamp
+        for entry in &self.overlay.entries {
+            if self.read_blocks(Some(entry.time), Some(entry.time))?.is_empty() {
+                count += 1;
+            }
+            first = Some(first.map_or(entry.time, |first| first.min(entry.time)));
+            last = Some(last.map_or(entry.time, |last| last.max(entry.time)));
+        }
+
+        Ok(SeriesSummary {
+            name: self.name.clone(),
+            data_type: Some(self.data_type.clone()),
+            count: count as u64,
+            first,
+            last,
+        })
+    }
+
+    /// Delete all entries within `[start, end]`.
+    ///
+    /// Buffered entries are removed straight away, while flushed entries are only hidden by a
+    /// tombstone, until the blocks holding them are rewritten by [FieldStorage::compact].
+    ///
+    /// Fails without deleting anything if the tombstone can't be saved.
+    pub fn delete(&mut self, start: Option<i64>, end: Option<i64>) -> io::Result<()> {
+        let tombstone = Tombstone {
+            start: start.unwrap_or(i64::MIN),
+            end: end.unwrap_or(i64::MAX),
+            offset: self.data_len,
+        };
+
+        if self.block_summaries.iter().any(|summary| tombstone.applies_to(summary)) {
+            self.tombstones.push(tombstone);
+            if let Err(err) = self.save_tombstones() {
+                self.tombstones.pop();
+                return Err(err);
+            }
+        }
+
+        self.curr_block.entries.retain(|entry| !tombstone.covers(entry.time));
+        self.overlay.entries.retain(|entry| !tombstone.covers(entry.time));
+        Ok(())
+    }
+
+    /// Whether there are deleted entries that have yet to be purged from disk.
+    #[inline]
+    pub fn has_tombstones(&self) -> bool {
+        !self.tombstones.is_empty()
+    }
+
+    fn save_tombstones(&self) -> io::Result<()> {
+        let path = format!("{}/{}/_{}_tombstones", data_dir(), self.series_name, self.name);
+        Tombstone::save_all(&path, &self.tombstones)
+    }
+
+    /// Save the tombstones after some were cleared. Tombstones only apply to blocks written before
+    /// them, so ones left on disk that no longer apply to any block don't hide anything, and are
+    /// cleared again by the next compaction.
+    fn save_cleared_tombstones(&self) {
+        if let Err(err) = self.save_tombstones() {
+            error!("failed to save the tombstones of field {}: {}", self.name, err);
+        }
+    }
+
+    /// Insert an entry, and return whether any data was written to disk as a result, either by
+    /// flushing a block or by compacting late entries into existing blocks.
+    pub fn insert(&mut self, entry: FieldEntry) -> io::Result<bool> {
         // we first attempt to write to the current block, and only write to disk if the block is
-        // filled. TODO: what does this mean for data reliability?. TODO: move into curr block
+        // filled. Until then, entries are only safe from crashes thanks to the series' write-ahead
+        // log.
         // TODO: this logic should probably be m/oved into block manager, right? maybe? would at least remove need for file handle
+        let mut flushed = false;
+        if !self.curr_block.has_space() {
+            self.flush_block()?;
+            flushed = true;
+        }
+
+        // entries older than the latest flushed block can't go in the current block, or blocks
+        // would overlap in time
+        if self.is_flushed(entry.time) {
+            // entries in the overlay replace flushed entries with the same timestamp, so unless
+            // the last write wins, duplicates of flushed entries never make it there
+            let is_duplicate = self.duplicates != DuplicatePolicy::LastWriteWins
+                && !self.read_blocks(Some(entry.time), Some(entry.time))?.is_empty();
+            if is_duplicate {
+                return Ok(flushed);
+            }
+
+            self.overlay.insert(entry, self.duplicates);
+            if self.overlay.entries.len() >= ENTRIES_PER_BLOCK {
+                self.compact()?;
+                flushed = true;
+            }
+        } else {
+            self.curr_block.insert(entry, self.duplicates);
+        }
+
+        Ok(flushed)
+    }
+
+    /// Write the current block to disk, and start a new one. If the block can't be written, it's
+    /// kept as the current block, and anything written of it is truncated away again.
+    fn flush_block(&mut self) -> io::Result<()> {
+        let index_len = self.index_file_handle.metadata()?.len();
+        let written = self.curr_block.write_data(&mut self.data_file_handle, &self.data_type)
+            .and_then(|length| self.curr_block.write_summary(&mut self.index_file_handle, self.data_len, length as u64));
+        let summary = match written {
+            Ok(summary) => summary,
+            Err(err) => {
+                self.truncate_partial_write(Some(index_len));
+                return Err(err);
+            }
+        };
+        self.data_len += summary.length;
+        self.block_summaries.push(summary);
+
+        // the block must be durable before the write-ahead log forgets about its entries
+        self.data_file_handle.sync_data()?;
+        self.index_file_handle.sync_data()?;
+
+        let block = std::mem::take(&mut self.curr_block);
+        self.block_manager.insert(self.block_summaries.last().unwrap(), block);
+        Ok(())
+    }
+
+    /// Merge the late entries in the overlay into the flushed blocks whose time ranges they fall
+    /// into, and purge deleted entries from the blocks holding them.
+    ///
+    /// Affected blocks are rewritten at the end of the data file, and the index is then replaced
+    /// atomically, so a crash at any point leaves either the old or the new blocks in place, and
+    /// the overlay's entries are still in the write-ahead log until it's checkpointed. Tombstones
+    /// never apply to the rewritten blocks, so they're only cleared once the index is replaced.
+    ///
+    /// Fails without changing the index if a block can't be read, in which case the overlay is
+    /// left in place.
+    pub fn compact(&mut self) -> io::Result<()> {
+        if self.overlay.entries.is_empty() && self.tombstones.is_empty() {
+            return Ok(());
+        }
 
-        // let block = self.curr_block;
+        let overlay = self.overlay.entries.clone();
 
-        match self.curr_block.has_space() {
-            true => self.curr_block.insert(entry),
-            false => {
-                self.curr_block.write_data(&mut self.data_file_handle);
-                let summary = self.curr_block.write_summary(&mut self.index_file_handle);
-                self.block_summaries.push(summary);
+        // an entry belongs to the first block that ends at or after it. Entries are only added to
+        // the overlay if a flushed block ends after them, so there always is one.
+        let mut late: BTreeMap<usize, Vec<FieldEntry>> = BTreeMap::new();
+        for entry in overlay {
+            let index = self.block_summaries.partition_point(|summary| summary.latest_timestamp < entry.time);
+            late.entry(index.min(self.block_summaries.len() - 1)).or_default().push(entry);
+        }
 
-                let mut block_manager = self.block_manager.lock().unwrap();
-                block_manager.blocks.push(self.curr_block.clone());
+        let mut summaries = Vec::with_capacity(self.block_summaries.len() + late.len());
+        let mut replaced = vec![];
+        for (index, summary) in self.block_summaries.iter().enumerate() {
+            let entries = late.remove(&index);
+            let tombstones: Vec<_> = self.tombstones.iter().filter(|t| t.applies_to(summary)).collect();
+            if entries.is_none() && tombstones.is_empty() {
+                summaries.push(summary.clone());
+                continue;
+            }
 
-                self.curr_block = FieldStorageBlock::new();
-                self.curr_block.insert(entry);
+            let mut existing = self.block_manager.load(summary)?.entries.clone();
+            existing.retain(|entry| !tombstones.iter().any(|t| t.covers(entry.time)));
+            replaced.push(summary.clone());
+
+            // blocks whose entries were all deleted are dropped altogether
+            let entries = entries.unwrap_or_default();
+            for chunk in merge_sorted(existing, entries).chunks(ENTRIES_PER_BLOCK) {
+                let block = FieldStorageBlock { entries: chunk.to_vec() };
+                let length = match block.write_data(&mut self.data_file_handle, &self.data_type) {
+                    Ok(length) => length as u64,
+                    Err(err) => {
+                        self.truncate_partial_write(None);
+                        return Err(err);
+                    }
+                };
+                summaries.push(FieldStorageBlockSummary::from_entries(&block.entries, self.data_len, length));
+                self.data_len += length;
             }
+        }
+
+        self.data_file_handle.sync_data()?;
+        self.replace_index(summaries)?;
+        for summary in &replaced {
+            self.block_manager.evict(summary);
+        }
+        self.overlay = FieldStorageBlock::new();
+
+        if !self.tombstones.is_empty() {
+            self.tombstones.clear();
+            self.save_cleared_tombstones();
+        }
+        self.reclaim_space();
+        Ok(())
+    }
+
+    /// Drop entries older than the cutoff. Flushed blocks whose entries are all older are dropped
+    /// straight away, along with any late entries that belong to them, and the disk space they
+    /// used is reclaimed. Older buffered entries are dropped as well. Returns whether any entries
+    /// were dropped or deleted.
+    ///
+    /// The first remaining block may still hold older entries. Deleting them means rewriting the
+    /// block, and the cutoff moves every time retention is enforced, so they're only deleted once
+    /// at least half of what's left of the block's time range is older, see
+    /// [FieldStorage::delete]. This way, each block is rewritten a handful of times at most before
+    /// it's dropped as a whole.
+    pub fn expire(&mut self, cutoff: i64) -> io::Result<bool> {
+        if cutoff == i64::MIN {
+            return Ok(false);
+        }
+
+        let expired = self.block_summaries.partition_point(|summary| summary.latest_timestamp < cutoff);
+        if expired > 0 {
+            for summary in &self.block_summaries[..expired] {
+                self.block_manager.evict(summary);
+            }
+
+            let summaries = self.block_summaries[expired..].to_vec();
+            self.replace_index(summaries)?;
+
+            // tombstones of dropped blocks are no longer needed
+            let tombstones = self.tombstones.len();
+            let summaries = &self.block_summaries;
+            self.tombstones.retain(|t| summaries.iter().any(|summary| t.applies_to(summary)));
+            if self.tombstones.len() < tombstones {
+                self.save_cleared_tombstones();
+            }
+
+            self.reclaim_space();
+        }
+
+        // blocks are sorted by time, so only the first one can still hold older entries
+        let rewrite = self.block_summaries.first().is_some_and(|summary| self.mostly_expired(summary, cutoff));
+        let stale = self.buffered_entries().any(|entry| entry.time < cutoff);
+        if rewrite {
+            self.delete(None, Some(cutoff - 1))?;
+        } else if stale {
+            self.curr_block.entries.retain(|entry| entry.time >= cutoff);
+            self.overlay.entries.retain(|entry| entry.time >= cutoff);
+        }
+
+        Ok(expired > 0 || rewrite || stale)
+    }
+
+    /// Whether at least half of the time range of a block that hasn't been deleted yet is older
+    /// than the cutoff.
+    fn mostly_expired(&self, summary: &FieldStorageBlockSummary, cutoff: i64) -> bool {
+        let deleted_until = self.tombstones.iter()
+            .filter(|t| t.start == i64::MIN && t.applies_to(summary))
+            .map(|t| t.end)
+            .max();
+        let start = match deleted_until {
+            Some(end) => summary.start_timestamp.max(end.saturating_add(1)),
+            None => summary.start_timestamp,
         };
+
+        let expired = cutoff as i128 - start as i128;
+        expired > 0 && expired * 2 > summary.latest_timestamp as i128 - start as i128
+    }
+
+    /// Replace the index with the given block summaries.
+    ///
+    /// The new index is written to a temporary file and synced before replacing the old one, so a
+    /// crash at any point leaves either the old or the new index in place.
+    fn replace_index(&mut self, summaries: Vec<FieldStorageBlockSummary>) -> io::Result<()> {
+        let index_path = format!("{}/{}/{}_index", data_dir(), self.series_name, self.name);
+        let tmp_path = format!("{}/{}/_{}_index.tmp", data_dir(), self.series_name, self.name);
+        let mut tmp = File::create(&tmp_path)?;
+        write_index_header(&mut tmp)?;
+        for summary in &summaries {
+            summary.write(&mut tmp)?;
+        }
+        tmp.sync_all()?;
+        rename(&tmp_path, &index_path)?;
+
+        self.index_file_handle = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&index_path)?;
+        self.block_summaries = summaries;
+        Ok(())
+    }
+
+    /// Truncate whatever part of a block was written after the end of the data file, and of its
+    /// summary after the given length of the index, so later blocks are still written where the
+    /// index expects them. Blocks that were fully written, but aren't in the index yet, are left
+    /// for [FieldStorage::reclaim_space].
+    fn truncate_partial_write(&self, index_len: Option<u64>) {
+        if let Err(err) = self.block_manager.truncate(self.data_len) {
+            error!("failed to truncate partially written block of field {}: {}", self.name, err);
+        }
+        if let Some(index_len) = index_len {
+            if let Err(err) = self.index_file_handle.set_len(index_len) {
+                error!("failed to truncate partially written summary of field {}: {}", self.name, err);
+            }
+        }
+    }
+
+    /// Free the disk space of all parts of the data file that aren't used by any block in the
+    /// index, such as blocks that were replaced by compaction or dropped by retention. The data
+    /// file keeps its size, so the offsets of the remaining blocks don't change.
+    ///
+    /// Must only be called once the index no longer refers to the freed blocks durably.
+    fn reclaim_space(&mut self) {
+        let mut used: Vec<_> = self.block_summaries.iter().map(|s| (s.offset, s.offset + s.length)).collect();
+        used.sort_unstable();
+
+        let mut start = 0;
+        for (offset, end) in used.into_iter().chain([(self.data_len, self.data_len)]) {
+            if offset > start {
+                if let Err(err) = punch_hole(&self.data_file_handle, start, offset - start) {
+                    error!("failed to reclaim space in the data file of field {}: {}", self.name, err);
+                    return;
+                }
+            }
+            start = start.max(end);
+        }
+    }
+
+    /// Entries that have been inserted, but not yet flushed to disk.
+    #[inline]
+    pub fn buffered_entries(&self) -> impl Iterator<Item=&FieldEntry> {
+        self.overlay.entries.iter().chain(self.curr_block.entries.iter())
+    }
+
+    /// Whether an entry at the given time would already have been flushed to disk.
+    #[inline]
+    pub fn is_flushed(&self, time: i64) -> bool {
+        match self.block_summaries.last() {
+            Some(summary) => time <= summary.latest_timestamp,
+            None => false,
+        }
+    }
+
+    /// Whether there is an entry at the given time.
+    pub fn has_entry(&self, time: i64) -> io::Result<bool> {
+        Ok(!self.read(Some(time), Some(time))?.is_empty())
+    }
+
+    /// Delete the files of a field, which must not be loaded.
+    ///
+    /// The data file is renamed first, after which the field no longer exists as far as
+    /// [SeriesStorage::load](crate::storage::series::SeriesStorage::load) is concerned, and the
+    /// remaining files are deleted. Calling this again after a crash finishes the job.
+    pub fn remove(series_name: &str, field_name: &str) -> io::Result<()> {
+        let filename = format!("{}/{}/{}", data_dir(), series_name, field_name);
+        let dropped = format!("{}/{}/_{}_dropped", data_dir(), series_name, field_name);
+        if let Err(err) = rename(&filename, &dropped) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err);
+            }
+        }
+
+        for path in [
+            format!("{}_index", filename),
+            format!("{}/{}/_{}_tombstones", data_dir(), series_name, field_name),
+            dropped,
+        ] {
+            if let Err(err) = remove_file(&path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
     }
 
     /// Returns handles to a data file and an index file, respectively.
-    fn get_files(series_name: &str, field_name: &str, append: bool) -> (File, File) {
-        let filename = format!("{}/{}/{}", DEFAULT_DATA_DIR, series_name, field_name);
+    fn get_files(series_name: &str, field_name: &str, append: bool) -> io::Result<(File, File)> {
+        let filename = format!("{}/{}/{}", data_dir(), series_name, field_name);
         let data_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .append(append)
-            .open(&filename)
-            .unwrap();
+            .open(&filename)?;
 
         let index_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .append(append)
-            .open(format!("{}_index", filename))
-            .unwrap();
+            .open(format!("{}_index", filename))?;
 
-        // TODO: error handling ^
+        Ok((data_file, index_file))
+    }
+}
 
-        (data_file, index_file)
+/// Deallocate a range of a file, which then reads as zeroes, without changing the file's size.
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, length: u64) -> io::Result<()> {
+    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+    // SAFETY: fallocate doesn't touch any memory, and the file descriptor is valid for as long as
+    // the file is borrowed
+    match unsafe { libc::fallocate(file.as_raw_fd(), mode, offset as libc::off_t, length as libc::off_t) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
     }
 }
 
+/// Reclaiming space isn't supported on other platforms yet, so unused parts of the data file keep
+/// taking up disk space.
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _length: u64) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Merge two lists of entries, each sorted by time and without duplicate timestamps, into a single
+/// sorted list. Entries of `b` replace entries of `a` with the same timestamp.
+fn merge_sorted(a: Vec<FieldEntry>, b: Vec<FieldEntry>) -> Vec<FieldEntry> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut b = b.into_iter().peekable();
+
+    for entry in a {
+        while let Some(next) = b.next_if(|next| next.time < entry.time) {
+            merged.push(next);
+        }
+
+        match b.next_if(|next| next.time == entry.time) {
+            Some(next) => merged.push(next),
+            None => merged.push(entry),
+        }
+    }
+    merged.extend(b);
+
+    merged
+}
+
 
 #[cfg(test)]
 mod tests {
     use std::{fs, time};
+    use std::os::unix::fs::MetadataExt;
     use crate::DataValue;
+    use crate::storage::data_dir;
 
+    use crate::storage::block_manager::SCAN_THRESHOLD;
     use crate::storage::field::{FieldEntry, FieldStorage};
     use crate::storage::field_block::ENTRIES_PER_BLOCK;
+    use crate::storage::metadata::DuplicatePolicy;
+    use crate::wire_protocol::DataType;
 
     #[test]
     fn it_inserts() {
-        fs::remove_dir("test_series");
-        fs::create_dir("test_series");
-        let mut s = FieldStorage::load("test_series", "field1");
+        let _ = fs::remove_dir_all(format!("{}/test_field_inserts", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_inserts", data_dir())).unwrap();
+        let mut s = FieldStorage::load("test_field_inserts", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
 
         for i in 0..ENTRIES_PER_BLOCK * 10 + 1 {
-            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as i64 });
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as i64 }).unwrap();
+        }
+    }
+
+    #[test]
+    fn it_skips_blocks_outside_range() {
+        let _ = fs::remove_dir_all(format!("{}/test_block_pruning", data_dir()));
+        fs::create_dir_all(format!("{}/test_block_pruning", data_dir())).unwrap();
+
+        let mut s = FieldStorage::load("test_block_pruning", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..ENTRIES_PER_BLOCK * 4 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i as i64 * 10 }).unwrap();
+        }
+
+        // reload, so that no blocks are in memory yet
+        let s = FieldStorage::load("test_block_pruning", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        assert_eq!(s.block_summaries.len(), 3);
+
+        let start = (ENTRIES_PER_BLOCK as i64 + 50) * 10;
+        let records = s.read(Some(start), Some(start + 100)).unwrap();
+        assert_eq!(records.len(), 11);
+        assert_eq!(records[0].time, start);
+        assert_eq!(records[10].time, start + 100);
+        assert_eq!(s.block_manager.cached_blocks(), 1);
+    }
+
+    #[test]
+    fn it_scans_large_ranges() {
+        let _ = fs::remove_dir_all(format!("{}/test_field_scan", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_scan", data_dir())).unwrap();
+
+        let mut s = FieldStorage::load("test_field_scan", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        let count = ENTRIES_PER_BLOCK * (SCAN_THRESHOLD + 2);
+        for i in 0..count {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i as i64 }).unwrap();
+        }
+
+        // scanned blocks are decoded in place, and not cached
+        let s = FieldStorage::load("test_field_scan", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        let records = s.read(Some(5), None).unwrap();
+        assert_eq!(records.len(), count - ENTRIES_PER_BLOCK - 5);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(*record, FieldEntry { value: DataValue::Float((i + 5) as f64), time: (i + 5) as i64 });
+        }
+        assert_eq!(s.block_manager.cached_blocks(), 0);
+    }
+
+    #[test]
+    fn it_reloads_flushed_bools() {
+        let _ = fs::remove_dir_all(format!("{}/test_field_bools", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_bools", data_dir())).unwrap();
+
+        let mut s = FieldStorage::load("test_field_bools", "field1", DataType::Bool, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..ENTRIES_PER_BLOCK * 2 + 5 {
+            s.insert(FieldEntry { value: DataValue::Bool(i % 3 == 0), time: i as i64 }).unwrap();
+        }
+
+        let s = FieldStorage::load("test_field_bools", "field1", DataType::Bool, DuplicatePolicy::LastWriteWins).unwrap();
+        let records = s.read(None, None).unwrap();
+        assert_eq!(records.len(), ENTRIES_PER_BLOCK * 2);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(*record, FieldEntry { value: DataValue::Bool(i % 3 == 0), time: i as i64 });
+        }
+    }
+
+    #[test]
+    fn it_compacts_late_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_late_entries", data_dir()));
+        fs::create_dir_all(format!("{}/test_late_entries", data_dir())).unwrap();
+
+        // even timestamps first, filling 3 blocks
+        let mut s = FieldStorage::load("test_late_entries", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..ENTRIES_PER_BLOCK * 3 + 1 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i as i64 * 2 }).unwrap();
+        }
+        assert_eq!(s.block_summaries.len(), 3);
+
+        // then backfill odd timestamps across all of them, which are readable straight away
+        let late: Vec<_> = (0..ENTRIES_PER_BLOCK as i64 * 3).filter(|i| i % 3 == 0).map(|i| i * 2 + 1).collect();
+        for &time in &late[..ENTRIES_PER_BLOCK - 1] {
+            assert!(!s.insert(FieldEntry { value: DataValue::Float(-1.0), time }).unwrap());
+        }
+        assert_eq!(s.block_summaries.len(), 3);
+        assert_eq!(s.buffered_entries().count(), ENTRIES_PER_BLOCK);
+
+        let records = s.read(Some(1), Some(7)).unwrap();
+        assert_eq!(records.iter().map(|e| e.time).collect::<Vec<_>>(), vec![1, 2, 4, 6, 7]);
+
+        // once enough late entries accumulate, they're merged into the blocks they belong to
+        assert!(s.insert(FieldEntry { value: DataValue::Float(-1.0), time: late[ENTRIES_PER_BLOCK - 1] }).unwrap());
+        assert_eq!(s.buffered_entries().count(), 1);
+        assert!(s.block_summaries.len() > 3);
+        assert!(s.block_summaries.windows(2).all(|w| w[0].latest_timestamp <= w[1].start_timestamp));
+        assert!(s.has_entry(1).unwrap());
+        assert!(!s.has_entry(3).unwrap());
+
+        let records = s.read(None, None).unwrap();
+        assert_eq!(records.len(), ENTRIES_PER_BLOCK * 4 + 1);
+        assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+        assert_eq!(s.read(Some(1), Some(7)).unwrap(), records[1..6].to_vec());
+
+        // compacted blocks replace the old ones in the index
+        let s = FieldStorage::load("test_late_entries", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        assert_eq!(s.read(None, None).unwrap(), records[..records.len() - 1].to_vec());
+        assert!(fs::metadata(format!("{}/test_late_entries/_field1_index.tmp", data_dir())).is_err());
+    }
+
+    #[test]
+    fn it_expires_old_blocks() {
+        let _ = fs::remove_dir_all(format!("{}/test_expiry", data_dir()));
+        fs::create_dir_all(format!("{}/test_expiry", data_dir())).unwrap();
+
+        // enough blocks to span several pages of the filesystem, so dropping them frees space
+        let mut s = FieldStorage::load("test_expiry", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..ENTRIES_PER_BLOCK * 100 + 1 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i as i64 }).unwrap();
         }
+        // a late entry belonging to the first block
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 1 }).unwrap();
+        assert_eq!(s.block_summaries.len(), 100);
+        let allocated = fs::metadata(format!("{}/test_expiry/field1", data_dir())).unwrap().blocks();
+
+        // a block that's only slightly expired isn't rewritten, but the blocks before it are dropped
+        let cutoff = ENTRIES_PER_BLOCK as i64 * 90 + 5;
+        assert!(s.expire(cutoff).unwrap());
+        assert_eq!(s.block_summaries.len(), 10);
+        assert_eq!(s.buffered_entries().count(), 1);
+        assert!(!s.has_tombstones());
+        assert!(!s.expire(cutoff + 1).unwrap());
+        assert_eq!(s.read(None, None).unwrap().len(), ENTRIES_PER_BLOCK * 10 + 1);
+
+        // entries older than the cutoff are deleted once most of their block is, even if the block
+        // isn't dropped
+        let cutoff = ENTRIES_PER_BLOCK as i64 * 90 + ENTRIES_PER_BLOCK as i64 / 2;
+        assert!(s.expire(cutoff).unwrap());
+        assert!(s.has_tombstones());
+        assert!(!s.expire(cutoff).unwrap());
+        // what's left of the block has to mostly expire again before it's rewritten again
+        assert!(!s.expire(cutoff + 5).unwrap());
+
+        let records = s.read(None, None).unwrap();
+        assert_eq!(records.len(), ENTRIES_PER_BLOCK * 10 + 1 - ENTRIES_PER_BLOCK / 2);
+        assert_eq!(records[0].time, cutoff);
+
+        // the dropped blocks no longer take up disk space, but the data file keeps its size
+        let data = fs::metadata(format!("{}/test_expiry/field1", data_dir())).unwrap();
+        assert!(data.blocks() < allocated);
+        assert_eq!(data.len(), s.block_summaries[9].offset + s.block_summaries[9].length);
+
+        s.compact().unwrap();
+        assert_eq!(s.read(None, None).unwrap(), records);
+
+        let mut reloaded = FieldStorage::load("test_expiry", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        assert_eq!(reloaded.read(None, None).unwrap(), records[..records.len() - 1].to_vec());
+        assert!(!reloaded.expire(cutoff).unwrap());
+
+        // buffered entries expire as well
+        assert!(s.expire(i64::MAX).unwrap());
+        assert_eq!(s.block_summaries.len(), 0);
+        assert!(s.read(None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_deletes_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_field_delete", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_delete", data_dir())).unwrap();
+
+        let times = |entries: Vec<FieldEntry>| entries.iter().map(|e| e.time).collect::<Vec<_>>();
+        let block = ENTRIES_PER_BLOCK as i64;
+
+        // 3 flushed blocks, a late entry and a half full current block
+        let mut s = FieldStorage::load("test_field_delete", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..block * 3 + block / 2 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i * 2 }).unwrap();
+        }
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 11 }).unwrap();
+
+        // deleted entries are hidden straight away, whether they're flushed or buffered
+        s.delete(Some(10), Some(block * 2 * 3 + 9)).unwrap();
+        assert!(s.has_tombstones());
+        assert_eq!(times(s.read(Some(6), Some(block * 2 * 3 + 12)).unwrap()), vec![6, 8, block * 6 + 10, block * 6 + 12]);
+        assert_eq!(s.buffered_entries().count(), block as usize / 2 - 5);
+
+        // entries written after the deletion aren't affected by it
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 100 }).unwrap();
+        assert_eq!(times(s.read(Some(6), Some(100)).unwrap()), vec![6, 8, 100]);
+
+        // a deletion only applying to buffered entries doesn't need a tombstone
+        let mut s = FieldStorage::load("test_field_delete", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        assert!(s.has_tombstones());
+        assert_eq!(times(s.read(Some(6), Some(100)).unwrap()), vec![6, 8]);
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: block * 10 }).unwrap();
+        s.delete(Some(block * 10), None).unwrap();
+        assert_eq!(s.tombstones.len(), 1);
+
+        // compaction purges deleted entries from disk, dropping blocks that are left empty
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 100 }).unwrap();
+        s.compact().unwrap();
+        assert!(!s.has_tombstones());
+        assert_eq!(s.block_summaries.len(), 1);
+        assert_eq!(times(s.read(None, None).unwrap()), vec![0, 2, 4, 6, 8, 100]);
+
+        let s = FieldStorage::load("test_field_delete", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        assert!(!s.has_tombstones());
+        assert_eq!(times(s.read(None, None).unwrap()), vec![0, 2, 4, 6, 8, 100]);
+    }
+
+    #[test]
+    fn it_keeps_entries_whose_deletion_cant_be_saved() {
+        let _ = fs::remove_dir_all(format!("{}/test_field_delete_failure", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_delete_failure", data_dir())).unwrap();
+
+        let mut s = FieldStorage::load("test_field_delete_failure", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 + 10 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i }).unwrap();
+        }
+
+        // tombstones are saved through a temporary file, which can't be created over a directory
+        fs::create_dir(format!("{}/test_field_delete_failure/_field1_tombstones.tmp", data_dir())).unwrap();
+        assert!(s.delete(Some(5), None).is_err());
+        assert!(!s.has_tombstones());
+        assert_eq!(s.read(None, None).unwrap().len(), ENTRIES_PER_BLOCK + 10);
+    }
+
+    #[test]
+    fn it_summarizes() {
+        let _ = fs::remove_dir_all(format!("{}/test_field_summary", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_summary", data_dir())).unwrap();
+
+        let mut s = FieldStorage::load("test_field_summary", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+        let summary = s.summarize().unwrap();
+        assert_eq!((summary.count, summary.first, summary.last), (0, None, None));
+
+        for i in 0..ENTRIES_PER_BLOCK as i64 * 2 + 10 {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i * 2 }).unwrap();
+        }
+        // one late entry replaces a flushed entry, while the other is new
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 2 }).unwrap();
+        s.insert(FieldEntry { value: DataValue::Float(-1.0), time: 3 }).unwrap();
+
+        let summary = s.summarize().unwrap();
+        assert_eq!(summary.name, "field1");
+        assert_eq!(summary.data_type, Some(DataType::Float));
+        assert_eq!((summary.count, summary.first, summary.last), (ENTRIES_PER_BLOCK as u64 * 2 + 11, Some(0), Some(ENTRIES_PER_BLOCK as i64 * 4 + 18)));
+
+        // deleted entries aren't counted, even before they're purged
+        s.delete(None, Some(1)).unwrap();
+        let summary = s.summarize().unwrap();
+        assert_eq!((summary.count, summary.first), (ENTRIES_PER_BLOCK as u64 * 2 + 10, Some(2)));
+        assert_eq!(s.read(None, None).unwrap().len() as u64, summary.count);
     }
 
     #[test]
     fn it_reads() {
-        let s = FieldStorage::load("test_series", "field1");
-        let records = s.read(None, None);
-        dbg!(records.len());
-        dbg!(records);
+        let _ = fs::remove_dir_all(format!("{}/test_field_reads", data_dir()));
+        fs::create_dir_all(format!("{}/test_field_reads", data_dir())).unwrap();
+        let mut s = FieldStorage::load("test_field_reads", "field1", DataType::Float, DuplicatePolicy::LastWriteWins).unwrap();
+
+        let count = ENTRIES_PER_BLOCK * 2 + 1;
+        for i in 0..count {
+            s.insert(FieldEntry { value: DataValue::Float(i as f64), time: i as i64 }).unwrap();
+        }
+
+        // entries are read from both written blocks and the current block
+        let records = s.read(None, None).unwrap();
+        assert_eq!(records.len(), count);
+        assert_eq!(records[count - 1], FieldEntry { value: DataValue::Float((count - 1) as f64), time: (count - 1) as i64 });
     }
 }
\ No newline at end of file
@@ -1,41 +1,40 @@
 use std::fs::File;
+use std::io;
 use std::io::Write;
-use std::mem::size_of;
 use std::os::unix::fs::FileExt;
 
-use nom::AsBytes;
-use rkyv::{Deserialize};
-
+use crate::storage::block_time::{serialize_timestamps, validate_timestamps, TimestampIter};
 use crate::storage::field::FieldEntry;
 use crate::storage::field_index::FieldStorageBlockSummary;
-
-const PTR_SIZE: usize = std::mem::size_of::<usize>();
-
-/// The size of each block.
-///
-/// A block is simply a vector of field entries. As such, the size of a block is the number of
-/// entries by the size of each entry, plus the size of a usize used to keep the vector's length.
-///
-/// We use certain powers of 2 to align with typical page sizes. In the future, this may be
-/// configurable.
-/// TODO: use at last 4096 after we're done testing.
-const BLOCK_SIZE: usize = 2400 + PTR_SIZE;
-
-/// The size of each entry in 8-bit bytes.
-const ENTRY_SIZE: usize = size_of::<FieldEntry>();
+use crate::storage::metadata::DuplicatePolicy;
+use crate::storage::storage_block::{BLOCK_FORMAT_VERSION, BlockHeader, ColumnIter, HEADER_SIZE, StorageBlock};
+use crate::wire_protocol::DataType;
 
 /// The max number of entries recorded in a single block.
-pub const ENTRIES_PER_BLOCK: usize = (BLOCK_SIZE - 8) / ENTRY_SIZE;
+///
+/// Blocks are compressed when they're written to disk, so their size on disk varies, and they're
+/// sized by their number of entries instead.
+/// TODO: use more after we're done testing.
+pub const ENTRIES_PER_BLOCK: usize = 100;
 
 // block, with header and stuff
 // inspiration: https://docs.influxdata.com/influxdb/v1.5/concepts/storage_engine/#compression
 /// A block of measurements for a single field, under a single series.
 /// Measurements are first stored in a block, and once a block fills, it is flushed to disk.
+///
+/// On disk, a block is made up of a [BlockHeader], followed by a column of timestamps, and a
+/// column of values, each compressed with an encoding specific to its type.
 #[derive(Debug, PartialEq, Clone)] // TODO: we shouldn't be cloning this, I was just lazy to refactor
 pub struct FieldStorageBlock {
     pub entries: Vec<FieldEntry>,
 }
 
+impl Default for FieldStorageBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FieldStorageBlock {
     pub fn new() -> FieldStorageBlock {
         FieldStorageBlock {
@@ -43,39 +42,29 @@ impl FieldStorageBlock {
         }
     }
 
+    /// Returns all entries with a timestamp within `[from, until]`. Entries are expected to be
+    /// sorted by time, so the bounds are found using binary search.
     pub fn read(&self, from: Option<i64>, until: Option<i64>) -> Vec<FieldEntry> {
-        if from.is_none() && until.is_none() {
-            return self.entries[..].to_vec();
-        }
-
-        let mut start_index = 0;
-        if let Some(from) = from {
-            // TODO: check against block bounds - actually this should be done by the field, or block manager, but not this low
+        self.range(from, until).to_vec()
+    }
 
-            // TODO: implement binary search for this
-            let index = self.entries.iter().position(|entry| entry.time >= from);
-            start_index = match index {
-                None => 0,
-                Some(index) => index,
-            }
-        }
+    /// Returns the slice of entries with a timestamp within `[from, until]`.
+    pub fn range(&self, from: Option<i64>, until: Option<i64>) -> &[FieldEntry] {
+        let start_index = match from {
+            Some(from) => self.entries.partition_point(|entry| entry.time < from),
+            None => 0,
+        };
 
-        let mut end_index = self.entries.len();
-        if let Some(until) = until {
-            // TODO: check against block bounds - see ^
+        let end_index = match until {
+            Some(until) => self.entries.partition_point(|entry| entry.time <= until),
+            None => self.entries.len(),
+        };
 
-            // TODO: implement binary search for this
-            let index = self.entries.iter().rposition(|entry| entry.time <= until);
-            end_index = match index {
-                None => self.entries.len(),
-                Some(index) => index,
-            }
+        if start_index >= end_index {
+            return &[];
         }
 
-        // TODO: find start index,
-        // TODO: find end index
-
-        self.entries[start_index..end_index].to_vec()
+        &self.entries[start_index..end_index]
     }
 
     /// Whether the block has space for accepting a new record.
@@ -84,96 +73,425 @@ impl FieldStorageBlock {
         self.entries.len() < ENTRIES_PER_BLOCK
     }
 
-    /// Insert entry into collection.
-    pub fn insert(&mut self, entry: FieldEntry) {
-        // TODO: insert in order. check if it's latest, and if not use binary search
-        self.entries.push(entry);
+    /// Insert entry into collection, keeping entries sorted by time. If there already is an entry
+    /// with the same timestamp, the policy decides which of the two is kept.
+    pub fn insert(&mut self, entry: FieldEntry, duplicates: DuplicatePolicy) {
+        // entries almost always arrive in order, so check if it's the latest before searching
+        match self.entries.last() {
+            Some(last) if entry.time <= last.time => {}
+            _ => {
+                self.entries.push(entry);
+                return;
+            }
+        }
+
+        let index = self.entries.partition_point(|e| e.time < entry.time);
+        match self.entries.get_mut(index) {
+            Some(existing) if existing.time == entry.time => {
+                if duplicates == DuplicatePolicy::LastWriteWins {
+                    *existing = entry;
+                }
+            }
+            _ => self.entries.insert(index, entry),
+        }
     }
 
-    pub fn load(file: &File, block_offset: usize) -> FieldStorageBlock {
-        let mut bytes = [0; BLOCK_SIZE];
+    /// Load the block described by the given summary from a data file.
+    pub fn load(file: &File, summary: &FieldStorageBlockSummary) -> io::Result<FieldStorageBlock> {
+        let mut bytes = vec![0; summary.length as usize];
+        file.read_exact_at(&mut bytes, summary.offset)?;
+
+        FieldStorageBlock::deserialize(&bytes).map_err(|error| corrupt_block(summary, error))
+    }
 
-        if let Err(_error) = file.read_exact_at(&mut bytes, (block_offset * BLOCK_SIZE) as u64) {
-            dbg!(_error);
-            return FieldStorageBlock { entries: vec![] };
+    /// Serialize the block's entries, which must all be of the given type. Fails if values of the
+    /// type can't be stored.
+    pub fn serialize(&self, data_type: &DataType) -> Result<Vec<u8>, String> {
+        let times: Vec<_> = self.entries.iter().map(|entry| entry.time).collect();
+        let times = serialize_timestamps(&times);
+
+        let column = StorageBlock::from_values(self.entries.iter().map(|entry| &entry.value), data_type)?;
+        let values = column.serialize();
+
+        let header = BlockHeader {
+            version: BLOCK_FORMAT_VERSION,
+            data_type: data_type.clone(),
+            encoding: column.encoding(),
+            count: self.entries.len() as u32,
+            min_time: self.entries.iter().map(|entry| entry.time).min().unwrap_or(0),
+            max_time: self.entries.iter().map(|entry| entry.time).max().unwrap_or(0),
+            times_len: times.len() as u32,
+            values_len: values.len() as u32,
         };
 
-        let archived = rkyv::check_archived_root::<Vec<FieldEntry>>(&bytes).unwrap();
-        let deserialized: Vec<FieldEntry> = archived.deserialize(&mut rkyv::Infallible).unwrap();
-        FieldStorageBlock {
-            entries: deserialized,
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + times.len() + values.len());
+        bytes.extend(header.serialize());
+        bytes.extend(times);
+        bytes.extend(values);
+        Ok(bytes)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<FieldStorageBlock, String> {
+        let view = BlockView::parse(bytes)?;
+        let entries: Vec<_> = view.entries().collect();
+        if entries.len() != view.header.count as usize {
+            return Err(String::from(TRUNCATED_VALUES));
         }
+
+        Ok(FieldStorageBlock { entries })
     }
 
-    /// Flush the block's data entries to out.
-    pub fn write_data<T: Write>(&self, out: &mut T) {
-        // TODO: at some point we need to optimize timestamps for storage, and data too
+    /// Flush the block's data entries to out, and return the number of bytes written. On failure,
+    /// part of the block may have been written.
+    pub fn write_data<T: Write>(&self, out: &mut T, data_type: &DataType) -> io::Result<usize> {
+        let bytes = self.serialize(data_type).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Flush the block's summary to out, typically an index file. The offset and length describe
+    /// where the block was written to in the data file.
+    pub fn write_summary<T: Write>(&self, out: &mut T, offset: u64, length: u64) -> io::Result<FieldStorageBlockSummary> {
+        let summary = FieldStorageBlockSummary::from_entries(&self.entries, offset, length);
+        summary.write(out)?;
+        Ok(summary)
+    }
+}
+
+/// The error returned when the block described by a summary can't be decoded.
+pub fn corrupt_block(summary: &FieldStorageBlockSummary, error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt block at offset {}: {}", summary.offset, error))
+}
+
+/// The error for a block whose value column holds fewer values than its header claims.
+const TRUNCATED_VALUES: &str = "block values end before the entry count in its header";
+
+/// A serialized block, whose header and column lengths have been validated, but whose columns are
+/// only decoded as they're iterated over. This lets blocks be scanned in place, e.g. straight from
+/// a memory mapped data file, without allocating for their entries.
+pub struct BlockView<'a> {
+    pub header: BlockHeader,
+    times: &'a [u8],
+    values: &'a [u8],
+}
+
+impl<'a> BlockView<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<BlockView<'a>, String> {
+        let header = BlockHeader::deserialize(bytes)?;
 
-        let bytes = rkyv::to_bytes::<_, BLOCK_SIZE>(&self.entries).expect("failed to serialize Field Entries");
-        // let deserialized = rkyv::from_bytes::<Vec<FieldEntry>>(&bytes).expect("failed to deserialize vec");
-        // dbg!(deserialized);
+        let times_end = HEADER_SIZE + header.times_len as usize;
+        let values_end = times_end + header.values_len as usize;
+        if bytes.len() < values_end {
+            return Err(String::from("block is shorter than its header claims"));
+        }
 
+        let times = &bytes[HEADER_SIZE..times_end];
+        let values = &bytes[times_end..values_end];
+        if !validate_timestamps(times, header.count as usize) {
+            return Err(String::from("block timestamps don't match the entry count in its header"));
+        }
+        StorageBlock::validate(values, header.encoding, header.count as usize)?;
 
-        out.write(bytes.as_bytes());
+        Ok(BlockView { header, times, values })
     }
 
-    /// Flush the block's summary to out, typically an index file.
-    pub fn write_summary<T: Write>(&self, out: &mut T) -> FieldStorageBlockSummary {
-        let summary = FieldStorageBlockSummary::from_entries(&self.entries);
-        summary.write(out);
-        summary
+    /// Iterate over all entries of the block, in order.
+    pub fn entries(&self) -> impl Iterator<Item=FieldEntry> + 'a {
+        let values = ColumnIter::new(self.values, self.header.encoding, self.header.count as usize);
+        TimestampIter::new(self.times).zip(values).map(|(time, value)| FieldEntry { time, value })
+    }
+
+    /// Append the entries with a timestamp within `[from, until]` to out. Timestamps are delta
+    /// encoded, so entries before `from` still have to be decoded, but they're skipped over
+    /// without being collected.
+    ///
+    /// Fails if the values end before an entry past `until`, or the end of the block, is decoded,
+    /// in which case nothing is appended to out.
+    pub fn read_into(&self, from: Option<i64>, until: Option<i64>, out: &mut Vec<FieldEntry>) -> Result<(), String> {
+        let from = from.unwrap_or(i64::MIN);
+        let until = until.unwrap_or(i64::MAX);
+
+        let start = out.len();
+        let mut decoded = 0;
+        for entry in self.entries() {
+            // entries are in order, so the rest of the block is past the range
+            if entry.time > until {
+                return Ok(());
+            }
+            decoded += 1;
+            if entry.time >= from {
+                out.push(entry);
+            }
+        }
+
+        if decoded != self.header.count as usize {
+            out.truncate(start);
+            return Err(String::from(TRUNCATED_VALUES));
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
     use std::fs::File;
     use crate::DataValue;
+    use crate::storage::data_dir;
+
+    use crate::storage::field::FieldEntry;
+    use crate::storage::field_block::{BlockView, ENTRIES_PER_BLOCK, FieldStorageBlock};
+    use crate::storage::field_index::{FieldStorageBlockSummary, write_index_header};
+    use crate::storage::metadata::DuplicatePolicy;
+    use crate::storage::storage_block::{BlockHeader, Encoding, HEADER_SIZE};
+    use crate::wire_protocol::DataType;
+
+    /// Write the blocks to a data file and an index in a directory of their own, and return the
+    /// opened data file and the path of the index.
+    fn write_blocks(dir: &str, blocks: &[FieldStorageBlock]) -> (File, String) {
+        let dir = format!("{}/{}", data_dir(), dir);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut data = vec![];
+        let mut index = vec![];
+        write_index_header(&mut index).unwrap();
+        for block in blocks {
+            let offset = data.len() as u64;
+            let length = block.write_data(&mut data, &DataType::Float).unwrap();
+            block.write_summary(&mut index, offset, length as u64).unwrap();
+        }
 
-    use crate::storage::field::{FieldStorage};
-    use crate::storage::field_block::FieldStorageBlock;
+        let index_path = format!("{}/field1_index", dir);
+        fs::write(format!("{}/field1", dir), data).unwrap();
+        fs::write(&index_path, index).unwrap();
+        (File::open(format!("{}/field1", dir)).unwrap(), index_path)
+    }
 
     #[test]
     fn it_reads_a_block() {
-        let f = File::open("data/test_series/field2").unwrap();
+        let mut block = FieldStorageBlock::new();
+        for i in 0..10 {
+            block.insert(FieldEntry { time: 100 + i * 10, value: DataValue::from(i as f64) }, DuplicatePolicy::LastWriteWins);
+        }
+        let (f, index_path) = write_blocks("test_block_read", &[block.clone()]);
 
-        // TODO: use premade, fixed test shim files, and then just assert against slices
-        let s = FieldStorageBlock::load(&f, 0);
-        dbg!(&s);
-        // assert_eq!(s.entries.len(), 10);
-        assert!(s.entries.len() > 0);
+        let summaries = FieldStorageBlockSummary::load_all(&index_path).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!((summaries[0].start_timestamp, summaries[0].latest_timestamp), (100, 190));
+
+        let s = FieldStorageBlock::load(&f, &summaries[0]).unwrap();
+        assert_eq!(s, block);
 
         let values = s.read(None, None);
-        // assert_eq!(values.len(), 100);
-        assert_eq!(values[0].value, DataValue::from(123.0));
-        assert_eq!(values[0].time, 1662352954755105835);
+        assert_eq!(values.len(), 10);
+        assert_eq!(values[0], FieldEntry { time: 100, value: DataValue::from(0.0) });
 
-        let values = s.read(Some(1662352954755112508), None);
-        // assert_eq!(values.len(), 4);
-        assert_eq!(values[0].value, DataValue::from(5.0));
-        assert_eq!(values[0].time, 1662352954755112508);
+        let values = s.read(Some(150), None);
+        assert_eq!(values.len(), 5);
+        assert_eq!(values[0], FieldEntry { time: 150, value: DataValue::from(5.0) });
 
-        let values = s.read(None, Some(1662352954755112608));
+        let values = s.read(None, Some(165));
         assert_eq!(values.len(), 7);
-        // assert_eq!(values[values.len() - 1].value, DataValue::from(5.0));
-        assert_eq!(values[values.len() - 1].time, 1662352954755112508);
+        assert_eq!(values[values.len() - 1].time, 160);
+
+        let values = s.read(Some(150), Some(179));
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[values.len() - 1], FieldEntry { time: 170, value: DataValue::from(7.0) });
+    }
+
+    #[test]
+    fn it_reads_a_range() {
+        let mut block = FieldStorageBlock::new();
+        for i in 0..10 {
+            block.insert(FieldEntry { time: i * 10, value: DataValue::from(i as f64) }, DuplicatePolicy::LastWriteWins);
+        }
+
+        assert_eq!(block.read(None, None).len(), 10);
+
+        let values = block.read(Some(20), Some(50));
+        assert_eq!(values.iter().map(|e| e.time).collect::<Vec<_>>(), vec![20, 30, 40, 50]);
+
+        let values = block.read(Some(15), Some(55));
+        assert_eq!(values.iter().map(|e| e.time).collect::<Vec<_>>(), vec![20, 30, 40, 50]);
+
+        let values = block.read(Some(85), None);
+        assert_eq!(values.iter().map(|e| e.time).collect::<Vec<_>>(), vec![90]);
+
+        let values = block.read(None, Some(0));
+        assert_eq!(values.iter().map(|e| e.time).collect::<Vec<_>>(), vec![0]);
+
+        assert!(block.read(Some(100), None).is_empty());
+        assert!(block.read(None, Some(-1)).is_empty());
+        assert!(block.read(Some(21), Some(29)).is_empty());
+    }
+
+    #[test]
+    fn it_inserts_in_order() {
+        let mut block = FieldStorageBlock::new();
+        for time in [10, 30, 20, 40, 0] {
+            block.insert(FieldEntry { time, value: DataValue::from(time as f64) }, DuplicatePolicy::LastWriteWins);
+        }
+
+        let times: Vec<_> = block.entries.iter().map(|e| e.time).collect();
+        assert_eq!(times, vec![0, 10, 20, 30, 40]);
+        assert_eq!(block.read(Some(20), Some(20)), vec![FieldEntry { time: 20, value: DataValue::from(20.0) }]);
+    }
+
+    #[test]
+    fn it_handles_duplicates() {
+        let mut block = FieldStorageBlock::new();
+        for time in [10, 20, 30] {
+            block.insert(FieldEntry { time, value: DataValue::from(1.0) }, DuplicatePolicy::LastWriteWins);
+        }
+
+        block.insert(FieldEntry { time: 20, value: DataValue::from(2.0) }, DuplicatePolicy::LastWriteWins);
+        block.insert(FieldEntry { time: 30, value: DataValue::from(2.0) }, DuplicatePolicy::LastWriteWins);
+        assert_eq!(block.entries.iter().map(|e| e.value.clone()).collect::<Vec<_>>(),
+                   vec![DataValue::from(1.0), DataValue::from(2.0), DataValue::from(2.0)]);
+
+        block.insert(FieldEntry { time: 10, value: DataValue::from(3.0) }, DuplicatePolicy::KeepFirst);
+        block.insert(FieldEntry { time: 30, value: DataValue::from(3.0) }, DuplicatePolicy::KeepFirst);
+        assert_eq!(block.entries.iter().map(|e| e.value.clone()).collect::<Vec<_>>(),
+                   vec![DataValue::from(1.0), DataValue::from(2.0), DataValue::from(2.0)]);
+    }
+
+    #[test]
+    fn it_serializes_typed_columns() {
+        let mut block = FieldStorageBlock::new();
+        for i in 0..10 {
+            let value = match i {
+                3 => DataValue::None,
+                _ => DataValue::from(i % 2 == 0),
+            };
+            block.insert(FieldEntry { time: 1663644227213092171 + i * 1000, value }, DuplicatePolicy::LastWriteWins);
+        }
+
+        let bytes = block.serialize(&DataType::Bool).unwrap();
+        let header = BlockHeader::deserialize(&bytes).unwrap();
+        assert_eq!(header.data_type, DataType::Bool);
+        assert_eq!(header.encoding, Encoding::Bool2Bit);
+        assert_eq!(header.count, 10);
+        assert_eq!(header.min_time, 1663644227213092171);
+        assert_eq!(header.max_time, 1663644227213092171 + 9000);
+        assert_eq!(FieldStorageBlock::deserialize(&bytes), Ok(block));
+
+        let mut block = FieldStorageBlock::new();
+        for i in 0..100 {
+            block.insert(FieldEntry { time: i * 10, value: DataValue::from(20.0 + (i / 10) as f64) }, DuplicatePolicy::LastWriteWins);
+        }
+
+        // 24 bytes per entry, as an uncompressed vector of field entries
+        let bytes = block.serialize(&DataType::Float).unwrap();
+        assert!(bytes.len() * 5 < 100 * 24, "{} bytes", bytes.len());
+        assert_eq!(FieldStorageBlock::deserialize(&bytes), Ok(block));
+    }
+
+    #[test]
+    fn it_reads_a_view() {
+        let mut block = FieldStorageBlock::new();
+        for i in 0..10 {
+            block.insert(FieldEntry { time: i * 10, value: DataValue::from(i % 3 == 0) }, DuplicatePolicy::LastWriteWins);
+        }
+        let bytes = block.serialize(&DataType::Bool).unwrap();
+
+        let view = BlockView::parse(&bytes).unwrap();
+        assert_eq!(view.entries().collect::<Vec<_>>(), block.entries);
+        for (from, until) in [(Some(15), Some(50)), (Some(90), None), (Some(91), None), (None, Some(-1))] {
+            let mut entries = vec![];
+            view.read_into(from, until, &mut entries).unwrap();
+            assert_eq!(entries, block.read(from, until));
+        }
+
+        // a block cut short is rejected before anything is decoded
+        assert!(BlockView::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_truncated_values() {
+        let mut block = FieldStorageBlock::new();
+        for i in 0..3 {
+            block.insert(FieldEntry { time: i, value: DataValue::from(1.5 * i as f64 - 7.25) }, DuplicatePolicy::LastWriteWins);
+        }
 
-        let values = s.read(Some(1662352954755112508), Some(1662352954755113199));
-        // assert_eq!(values.len(), 3);
-        assert_eq!(values[values.len() - 1].value, 5.0);
-        assert_eq!(values[values.len() - 1].time, 1662352954755112708);
+        // cut the end off the float column, which is the last part of the block, while keeping the
+        // header consistent with it, so that only decoding the values can tell
+        let mut bytes = block.serialize(&DataType::Float).unwrap();
+        bytes.truncate(bytes.len() - 2);
+        let mut header = BlockHeader::deserialize(&bytes).unwrap();
+        header.values_len -= 2;
+        bytes[..HEADER_SIZE].copy_from_slice(&header.serialize());
+
+        assert!(FieldStorageBlock::deserialize(&bytes).is_err());
+
+        // reading past the end of the values fails without returning part of the block
+        let view = BlockView::parse(&bytes).unwrap();
+        let mut entries = vec![];
+        assert!(view.read_into(None, None, &mut entries).is_err());
+        assert!(view.read_into(Some(1), None, &mut entries).is_err());
+        assert!(entries.is_empty());
     }
 
     #[test]
-    fn it_reads2() {
-        let f = File::open("test_series_value1").unwrap();
+    fn it_rejects_corrupt_timestamps() {
+        let mut block = FieldStorageBlock::new();
+        for i in 0..3 {
+            block.insert(FieldEntry { time: i * 1000, value: DataValue::from(i as f64) }, DuplicatePolicy::LastWriteWins);
+        }
+        let bytes = block.serialize(&DataType::Float).unwrap();
+
+        // set the continuation bit on the last timestamp, so that it runs on into the values
+        let times_end = HEADER_SIZE + BlockHeader::deserialize(&bytes).unwrap().times_len as usize;
+        let mut corrupt = bytes.clone();
+        corrupt[times_end - 1] |= 0x80;
+        assert!(BlockView::parse(&corrupt).is_err());
+
+        // claim more entries than the timestamp column holds
+        let mut corrupt = bytes;
+        corrupt[HEADER_SIZE] += 1;
+        assert!(BlockView::parse(&corrupt).is_err());
+    }
 
-        let _s = FieldStorage::load("test_series", "value1");
-        let s = FieldStorageBlock::load(&f, 0);
-        dbg!(s);
+    #[test]
+    fn it_rejects_unknown_versions() {
+        let mut block = FieldStorageBlock::new();
+        block.insert(FieldEntry { time: 1, value: DataValue::from(1.0) }, DuplicatePolicy::LastWriteWins);
 
-        let s2 = FieldStorageBlock::load(&f, 1);
-        dbg!(s2);
-        // s.insert(Entry { value: 123, time: time::UNIX_EPOCH.elapsed().unwrap().as_nanos() });
+        let mut bytes = block.serialize(&DataType::Float).unwrap();
+        bytes[0] += 1;
+        assert!(FieldStorageBlock::deserialize(&bytes).is_err());
+    }
+
+    #[test]
+    fn it_fails_short_writes() {
+        let mut block = FieldStorageBlock::new();
+        block.insert(FieldEntry { time: 1, value: DataValue::from(1.0) }, DuplicatePolicy::LastWriteWins);
+
+        let mut buffer = [0; HEADER_SIZE];
+        assert!(block.write_data(&mut &mut buffer[..], &DataType::Float).is_err());
+        assert!(block.write_summary(&mut &mut buffer[..4], 0, 1).is_err());
+
+        let mut out = vec![];
+        let length = block.write_data(&mut out, &DataType::Float).unwrap();
+        assert_eq!(length, out.len());
+    }
+
+    #[test]
+    fn it_reads_blocks_at_their_offsets() {
+        let blocks: Vec<_> = (0..2).map(|b| {
+            let mut block = FieldStorageBlock::new();
+            for i in 0..ENTRIES_PER_BLOCK as i64 {
+                let time = b * ENTRIES_PER_BLOCK as i64 + i;
+                block.insert(FieldEntry { time, value: DataValue::from(time as f64) }, DuplicatePolicy::LastWriteWins);
+            }
+            block
+        }).collect();
+        let (f, index_path) = write_blocks("test_block_offsets", &blocks);
+
+        let summaries = FieldStorageBlockSummary::load_all(&index_path).unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[1].offset, summaries[0].length);
+        assert_eq!(FieldStorageBlock::load(&f, &summaries[0]).unwrap(), blocks[0]);
+        assert_eq!(FieldStorageBlock::load(&f, &summaries[1]).unwrap(), blocks[1]);
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use core::mem::size_of;
 use std::fs::read;
+use std::io;
 use std::io::Write;
 
 use bytecheck::CheckBytes;
@@ -15,50 +16,142 @@ pub struct FieldStorageBlockSummary {
     // TODO: We should also store min, max, and mean, for faster aggregating
     pub start_timestamp: i64,
     pub latest_timestamp: i64,
+
+    /// Position of the block in the data file, in bytes. Blocks are compressed, so they vary in size.
+    pub offset: u64,
+    /// Size of the block in the data file, in bytes.
+    pub length: u64,
 }
 
 const SUMMARY_BLOCK_SIZE: usize = size_of::<FieldStorageBlockSummary>();
 
+/// Identifies a file as a field index. Indexes written before the index was versioned have no
+/// header, and start straight with a block summary instead.
+const INDEX_MAGIC: [u8; 4] = *b"RTIX";
+
+/// The version of the on-disk index format written by this version of the database. It must be
+/// bumped whenever the layout of block summaries changes.
+pub const INDEX_FORMAT_VERSION: u8 = 1;
+
+/// The size of the header at the start of every index file, laid out as:
+///
+/// [MAGIC] [VERSION] [RESERVED]
+/// u8[4]   u8        u8[3]
+///
+/// and followed by the block summaries. Its size keeps the summaries aligned.
+pub const INDEX_HEADER_SIZE: usize = 8;
+
+/// Write the header of an index file to out, which must be empty.
+pub fn write_index_header<T: Write>(out: &mut T) -> io::Result<()> {
+    let mut header = [0; INDEX_HEADER_SIZE];
+    header[..4].copy_from_slice(&INDEX_MAGIC);
+    header[4] = INDEX_FORMAT_VERSION;
+    out.write_all(&header)
+}
+
 impl FieldStorageBlockSummary {
-    pub fn from_entries(entries: &Vec<FieldEntry>) -> FieldStorageBlockSummary {
+    pub fn from_entries(entries: &[FieldEntry], offset: u64, length: u64) -> FieldStorageBlockSummary {
         let earliest = entries[0].time;
         let latest = entries[entries.len() - 1].time;
 
         FieldStorageBlockSummary {
             start_timestamp: earliest,
             latest_timestamp: latest,
+            offset,
+            length,
         }
     }
 
-    /// Load all block summaries from a given index file.
+    /// Load all block summaries from a given index file. An empty file has no summaries.
+    ///
+    /// Fails if the index was written by another version of the database, or is corrupt.
     /// TODO: there may? be a faster way to do this
-    pub fn load_all(path: &str) -> Vec<FieldStorageBlockSummary> {
-        let bytes = read(path).unwrap();
+    pub fn load_all(path: &str) -> Result<Vec<FieldStorageBlockSummary>, String> {
+        let bytes = read(path).map_err(|e| format!("failed to read field index {}: {}", path, e))?;
+        if bytes.is_empty() {
+            return Ok(vec![]);
+        }
 
-        let mut summaries = Vec::with_capacity(bytes.len() / SUMMARY_BLOCK_SIZE);
+        if bytes.len() < INDEX_HEADER_SIZE || bytes[..4] != INDEX_MAGIC {
+            return Err(format!("field index {} was written by an older version of the database, and can't be \
+                read; its series must be re-imported", path));
+        }
+        if bytes[4] != INDEX_FORMAT_VERSION {
+            return Err(format!("field index {} has unsupported format version: {}", path, bytes[4]));
+        }
+
+        let body = &bytes[INDEX_HEADER_SIZE..];
+        if body.len() % SUMMARY_BLOCK_SIZE != 0 {
+            return Err(format!("field index {} is corrupt: its length isn't a multiple of the summary size", path));
+        }
 
-        for offset in (0..bytes.len()).step_by(SUMMARY_BLOCK_SIZE) {
-            let summary = rkyv::from_bytes::<FieldStorageBlockSummary>(&bytes[offset..offset + SUMMARY_BLOCK_SIZE]).unwrap();
+        let mut summaries = Vec::with_capacity(body.len() / SUMMARY_BLOCK_SIZE);
+        for offset in (0..body.len()).step_by(SUMMARY_BLOCK_SIZE) {
+            let summary = rkyv::from_bytes::<FieldStorageBlockSummary>(&body[offset..offset + SUMMARY_BLOCK_SIZE])
+                .map_err(|e| format!("field index {} has a corrupt block summary at {}: {}", path, offset, e))?;
             summaries.push(summary);
         }
 
-        summaries
+        Ok(summaries)
     }
 
-    pub fn write<T: Write>(&self, out: &mut T) {
+    pub fn write<T: Write>(&self, out: &mut T) -> io::Result<()> {
         let bytes = rkyv::to_bytes::<_, 4096>(self).expect("failed to serialize block summary");
-        out.write(bytes.as_bytes());
+        out.write_all(bytes.as_bytes())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::field_index::FieldStorageBlockSummary;
+    use std::fs;
+    use crate::storage::data_dir;
+
+    use crate::storage::field_index::{FieldStorageBlockSummary, write_index_header};
 
     #[test]
     fn load() {
-        // TODO: shim file
-        let summaries = FieldStorageBlockSummary::load_all("test_series_value1_index");
-        dbg!(summaries);
+        let _ = fs::remove_dir_all(format!("{}/test_index_load", data_dir()));
+        fs::create_dir_all(format!("{}/test_index_load", data_dir())).unwrap();
+        let path = &format!("{}/test_index_load/field1_index", data_dir());
+
+        let summaries = vec![
+            FieldStorageBlockSummary { start_timestamp: 1, latest_timestamp: 5, offset: 0, length: 64 },
+            FieldStorageBlockSummary { start_timestamp: 6, latest_timestamp: 9, offset: 64, length: 48 },
+        ];
+        let mut bytes = vec![];
+        write_index_header(&mut bytes).unwrap();
+        for summary in &summaries {
+            summary.write(&mut bytes).unwrap();
+        }
+        fs::write(path, &bytes).unwrap();
+
+        assert_eq!(FieldStorageBlockSummary::load_all(path).unwrap(), summaries);
+    }
+
+    #[test]
+    fn loads_versioned_indexes() {
+        let _ = fs::remove_dir_all(format!("{}/test_index_versions", data_dir()));
+        fs::create_dir_all(format!("{}/test_index_versions", data_dir())).unwrap();
+        let path = &format!("{}/test_index_versions/field1_index", data_dir());
+
+        let summary = FieldStorageBlockSummary { start_timestamp: 1, latest_timestamp: 2, offset: 0, length: 64 };
+        let mut bytes = vec![];
+        write_index_header(&mut bytes).unwrap();
+        summary.write(&mut bytes).unwrap();
+        fs::write(path, &bytes).unwrap();
+        assert_eq!(FieldStorageBlockSummary::load_all(path).unwrap(), vec![summary]);
+
+        fs::write(path, []).unwrap();
+        assert!(FieldStorageBlockSummary::load_all(path).unwrap().is_empty());
+
+        // a newer version of the index
+        bytes[4] += 1;
+        fs::write(path, &bytes).unwrap();
+        assert!(FieldStorageBlockSummary::load_all(path).is_err());
+
+        // an index from before the index was versioned, which holds only a 16 byte summary
+        fs::write(path, [0; 16]).unwrap();
+        let error = FieldStorageBlockSummary::load_all(path).unwrap_err();
+        assert!(error.contains("older version"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fs::{File, read_to_string, rename};
+use std::io;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::data_dir;
+use crate::wire_protocol::DataType;
+
+/// Name of the metadata file, within a series' directory.
+const METADATA_FILENAME: &str = "_meta";
+
+/// What happens when an entry is written to a field at a time that already has an entry.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// The new entry replaces the existing one, i.e. writes are upserts.
+    #[default]
+    LastWriteWins,
+    /// The new entry is ignored.
+    KeepFirst,
+    /// The new entry is rejected with an error.
+    Reject,
+}
+
+/// Metadata describing a series, persisted alongside its data as YAML.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct SeriesMetadata {
+    #[serde(skip)]
+    path: String,
+
+    /// The data type of each field, recorded when the field is first written to.
+    pub fields: BTreeMap<String, DataType>,
+
+    /// How entries with the same field and timestamp as an existing entry are handled.
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+
+    /// How long entries are kept, in nanoseconds, or None to keep them forever.
+    #[serde(default)]
+    pub retention: Option<i64>,
+}
+
+impl SeriesMetadata {
+    /// Load the metadata of the given series, or return empty metadata if there is none yet.
+    /// Fails if the metadata can't be read, or is corrupt.
+    pub fn load(series_name: &str) -> io::Result<SeriesMetadata> {
+        let path = format!("{}/{}/{}", data_dir(), series_name, METADATA_FILENAME);
+
+        let mut metadata: SeriesMetadata = match read_to_string(&path) {
+            Ok(data) => serde_yaml::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                format!("series metadata {} is corrupt: {}", path, e)))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => SeriesMetadata::default(),
+            Err(err) => return Err(err),
+        };
+        metadata.path = path;
+
+        Ok(metadata)
+    }
+
+    /// Record the data type of a new field, and save the metadata to disk. The field is forgotten
+    /// again if the metadata can't be saved.
+    pub fn add_field(&mut self, field: &str, data_type: DataType) -> io::Result<()> {
+        self.fields.insert(field.to_owned(), data_type);
+        self.save().inspect_err(|_| {
+            self.fields.remove(field);
+        })
+    }
+
+    /// Forget about a dropped field, and save the metadata to disk.
+    pub fn remove_field(&mut self, field: &str) -> io::Result<()> {
+        match self.fields.remove(field) {
+            Some(_) => self.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Change how duplicate entries are handled, and save the metadata to disk. The policy is left
+    /// unchanged if the metadata can't be saved.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) -> io::Result<()> {
+        let previous = std::mem::replace(&mut self.duplicates, policy);
+        self.save().inspect_err(|_| self.duplicates = previous)
+    }
+
+    /// Change how long entries are kept, and save the metadata to disk. The retention period is
+    /// left unchanged if the metadata can't be saved.
+    pub fn set_retention(&mut self, retention: Option<i64>) -> io::Result<()> {
+        let previous = std::mem::replace(&mut self.retention, retention);
+        self.save().inspect_err(|_| self.retention = previous)
+    }
+
+    /// Save the metadata to disk, replacing the previous version atomically.
+    fn save(&self) -> io::Result<()> {
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut tmp = File::create(&tmp_path)?;
+
+        let data = serde_yaml::to_string(&self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        tmp.write_all(data.as_bytes())?;
+        tmp.sync_all()?;
+
+        rename(&tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::storage::data_dir;
+
+    use crate::storage::metadata::{DuplicatePolicy, SeriesMetadata};
+    use crate::wire_protocol::DataType;
+
+    #[test]
+    fn it_saves_and_loads() {
+        let _ = fs::remove_dir_all(format!("{}/test_metadata", data_dir()));
+        fs::create_dir_all(format!("{}/test_metadata", data_dir())).unwrap();
+
+        let mut metadata = SeriesMetadata::load("test_metadata").unwrap();
+        assert!(metadata.fields.is_empty());
+        assert_eq!(metadata.duplicates, DuplicatePolicy::LastWriteWins);
+
+        metadata.add_field("value1", DataType::Float).unwrap();
+        metadata.add_field("value2", DataType::Bool).unwrap();
+        metadata.set_duplicate_policy(DuplicatePolicy::Reject).unwrap();
+        metadata.set_retention(Some(1000)).unwrap();
+
+        let metadata = SeriesMetadata::load("test_metadata").unwrap();
+        assert_eq!(metadata.fields.get("value1"), Some(&DataType::Float));
+        assert_eq!(metadata.fields.get("value2"), Some(&DataType::Bool));
+        assert_eq!(metadata.duplicates, DuplicatePolicy::Reject);
+        assert_eq!(metadata.retention, Some(1000));
+    }
+
+    #[test]
+    fn it_rejects_corrupt_metadata() {
+        let _ = fs::remove_dir_all(format!("{}/test_metadata_corrupt", data_dir()));
+        fs::create_dir_all(format!("{}/test_metadata_corrupt", data_dir())).unwrap();
+        fs::write(format!("{}/test_metadata_corrupt/_meta", data_dir()), "fields: [").unwrap();
+
+        assert!(SeriesMetadata::load("test_metadata_corrupt").is_err());
+    }
+}
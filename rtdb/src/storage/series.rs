@@ -1,19 +1,30 @@
-use std::fs::{create_dir_all, read_dir};
-use std::io::{Read};
-use std::io::ErrorKind::AlreadyExists;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, rename};
+use std::io;
+use std::io::ErrorKind::{AlreadyExists, NotFound};
+use std::path::Path;
 use std::str;
 
+use bytecheck::CheckBytes;
 use fnv::FnvHashMap;
+use log::error;
+use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::{DataValue, RecordCollection};
-use crate::lang::{Selection, SelectQuery};
-use crate::storage::DEFAULT_DATA_DIR;
+use crate::execution::ExecutionError;
+use crate::execution::aggregate::aggregate_records;
+use crate::lang::{Aggregation, Condition, Selection, SelectQuery};
+use crate::storage::data_dir;
 use crate::storage::field::{FieldEntry, FieldStorage};
+use crate::storage::metadata::{DuplicatePolicy, SeriesMetadata};
+use crate::storage::tag_index::parse_series_key;
+use crate::storage::wal::WriteAheadLog;
 use crate::wire_protocol::{DataType, FieldDescription};
 
 /// A series entry is a collection of values, each corresponding to a different field under the
 /// same series, all sharing the same timestamp.
-#[derive(Debug, PartialEq, serde::Serialize)]
+#[derive(Archive, Deserialize, Serialize, Debug, PartialEq, serde::Serialize)]
+#[archive_attr(derive(CheckBytes, Debug))]
 pub struct SeriesEntry {
     pub fields: Vec<String>,
     pub values: Vec<DataValue>,
@@ -22,135 +33,578 @@ pub struct SeriesEntry {
     pub time: i64,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct SeriesSummary<'a> {
-    name: &'a str,
-    fields: Vec<String>,
+/// A summary of a series, or of one of its fields, as returned by SHOW SERIES and SHOW FIELDS
+/// respectively.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct SeriesSummary {
+    pub name: String,
+
+    /// The data type of a field, or None for a series, whose fields may have different types.
+    pub data_type: Option<DataType>,
+
+    /// Number of entries, which for a series is summed over all of its fields.
+    pub count: u64,
+
+    /// Timestamps of the earliest and latest entries, or None if there are no entries.
+    pub first: Option<i64>,
+    pub last: Option<i64>,
 }
 
 #[derive(Debug)]
-pub struct SeriesStorage<'a> {
-    pub(crate) series_name: &'a str,
+pub struct SeriesStorage {
+    pub(crate) series_name: String,
+
+    /// Tags shared by all entries of the series, as encoded in its name.
+    tags: Vec<(String, String)>,
     field_storages: FnvHashMap<String, FieldStorage>,
+    metadata: SeriesMetadata,
+    wal: WriteAheadLog,
 }
 
-impl SeriesStorage<'_> {
-    pub fn new(series_name: &str) -> SeriesStorage {
-        if let Err(err) = create_dir_all(format!("{}/{}", DEFAULT_DATA_DIR, series_name)) {
-            match err.kind() {
-                AlreadyExists => {}
-                _ => panic!("{}", err),
+impl SeriesStorage {
+    /// Whether a series with the given name has been created.
+    pub fn exists(series_name: &str) -> bool {
+        !series_name.is_empty() && Path::new(&format!("{}/{}", data_dir(), series_name)).is_dir()
+    }
+
+    /// The names of all series that have been created.
+    pub fn list() -> Vec<String> {
+        let entries = match read_dir(data_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            // series being dropped are renamed to start with an underscore
+            .filter(|name| !name.starts_with('_'))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Delete a series and all of its data, which must not be loaded.
+    ///
+    /// The series' directory is renamed first, after which the series no longer exists, and it's
+    /// then deleted. Series left over from a crash in between are cleaned up by
+    /// [SeriesStorage::remove_dropped].
+    pub fn remove(series_name: &str) -> io::Result<()> {
+        let dir = format!("{}/{}", data_dir(), series_name);
+        let dropped = format!("{}/_dropped_{}", data_dir(), series_name);
+
+        // a directory can't be renamed over one that isn't empty
+        SeriesStorage::remove_dir(&dropped)?;
+        rename(&dir, &dropped)?;
+        SeriesStorage::remove_dir(&dropped)
+    }
+
+    /// Delete the directories of series that were dropped, but not fully deleted.
+    pub fn remove_dropped() {
+        let entries = match read_dir(data_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if entry.file_name().to_str().is_some_and(|name| name.starts_with("_dropped_")) {
+                let path = entry.path().to_string_lossy().into_owned();
+                if let Err(err) = SeriesStorage::remove_dir(&path) {
+                    error!("failed to delete dropped series '{}': {}", path, err);
+                }
+            }
+        }
+    }
+
+    fn remove_dir(path: &str) -> io::Result<()> {
+        match remove_dir_all(path) {
+            Err(err) if err.kind() != NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn new(series_name: &str) -> io::Result<SeriesStorage> {
+        if let Err(err) = create_dir_all(format!("{}/{}", data_dir(), series_name)) {
+            if err.kind() != AlreadyExists {
+                return Err(err);
             }
         };
 
-        SeriesStorage { series_name, field_storages: FnvHashMap::default() }
+        Ok(SeriesStorage {
+            series_name: series_name.to_owned(),
+            tags: parse_series_key(series_name).1,
+            field_storages: FnvHashMap::default(),
+            metadata: SeriesMetadata::load(series_name)?,
+            wal: WriteAheadLog::open(series_name)?,
+        })
     }
 
-    pub fn load(series_name: &str) -> SeriesStorage {
-        let files = match read_dir(format!("{}/{}", DEFAULT_DATA_DIR, series_name)) {
+    /// Load a series from disk, or create it if it doesn't exist, and restore the entries that were
+    /// buffered when it was last open from its write-ahead log.
+    pub fn load(series_name: &str) -> io::Result<SeriesStorage> {
+        let files = match read_dir(format!("{}/{}", data_dir(), series_name)) {
             Ok(files) => files,
-            Err(_) => {
-                println!("Create new series");
-                return SeriesStorage::new(series_name);
-            }
+            Err(_) => return SeriesStorage::new(series_name),
         };
 
+        let mut metadata = SeriesMetadata::load(series_name)?;
+
         let mut fields = vec![];
         for entry in files {
             let file = entry.unwrap().file_name();
             let filename = file.to_str().unwrap();
-            if !filename.ends_with("_index") {
-                // TODO: figure out what data type a field is
-                fields.push(FieldDescription { name: filename.to_owned(), data_type: DataType::Float });
+
+            // finish dropping fields that were only partially deleted
+            if let Some(field) = filename.strip_prefix('_').and_then(|f| f.strip_suffix("_dropped")) {
+                FieldStorage::remove(series_name, field)?;
+                metadata.remove_field(field)?;
+            }
+
+            // series metadata files, such as the write-ahead log, start with an underscore
+            if !filename.ends_with("_index") && !filename.starts_with('_') {
+                // fields written before data types were persisted can only have been floats
+                let data_type = metadata.fields.get(filename).cloned().unwrap_or(DataType::Float);
+                fields.push(FieldDescription { name: filename.to_owned(), data_type });
             }
         }
 
-        let field_storages = fields.iter().map(|f| (f.name.to_owned(), FieldStorage::load(series_name, &f.name))).collect();
+        let field_storages = fields.into_iter()
+            .map(|f| Ok((f.name.to_owned(), FieldStorage::load(series_name, &f.name, f.data_type, metadata.duplicates)?)))
+            .collect::<io::Result<_>>()?;
 
-        SeriesStorage {
-            series_name,
+        let mut storage = SeriesStorage {
+            series_name: series_name.to_owned(),
+            tags: parse_series_key(series_name).1,
             field_storages,
-        }
+            metadata,
+            wal: WriteAheadLog::open(series_name)?,
+        };
+        storage.replay_wal()?;
+
+        Ok(storage)
     }
 
-    pub fn read(&self, query: SelectQuery) -> RecordCollection {
-        if Some(query.end) < Some(query.start) {
-            return RecordCollection::empty();
+    pub fn read(&self, query: SelectQuery) -> Result<RecordCollection, ExecutionError> {
+        read_series(&[self], &[self], query)
+    }
+
+    /// Insert an entry into the series. The entry is recorded in the write-ahead log before being
+    /// written to field storage, so it isn't lost if the process crashes before it's flushed.
+    ///
+    /// Fails without writing anything if any value doesn't match the data type of its field, if a
+    /// field is given more than once, or if the series rejects duplicates and a field already has
+    /// an entry at the same time.
+    pub fn insert(&mut self, entry: SeriesEntry) -> Result<(), String> {
+        self.validate(&entry)?;
+
+        // a failed append may have left part of a record at the end of the log, and anything
+        // appended after it would be lost when the log is replayed
+        if self.wal.is_torn() {
+            self.checkpoint_wal()
+                .map_err(|err| format!("failed to checkpoint the write-ahead log: {}", err))?;
         }
+        self.wal.append(&entry)
+            .map_err(|err| format!("failed to write to the write-ahead log: {}", err))?;
 
+        // the entry is durable once it's logged, so failing to checkpoint only leaves the log
+        // longer than it needs to be, until the next checkpoint
+        let flushed = self.apply(entry)
+            .map_err(|err| format!("failed to create field storage: {}", err))?;
+        if flushed {
+            if let Err(err) = self.checkpoint_wal() {
+                error!("failed to checkpoint the write-ahead log of '{}': {}", self.series_name, err);
+            }
+        }
 
-        let fields: Vec<_> = match query.selections.is_empty() {
-            true => self.field_storages.values().collect(),
-            false => {
-                let field_names: Vec<_> = query.selections.iter().map(|f| {
-                    match f {
-                        Selection::Field(name) => { *name }
-                        Selection::Expression(_) => { todo!() }
-                    }
-                }).collect();
-                self.field_storages.values().filter(|&f| field_names.contains(&f.name.as_str())).collect()
+        Ok(())
+    }
+
+    /// Check that every value of an entry is of the same type as its field, if the field exists,
+    /// and that it doesn't duplicate an existing entry if the series' policy forbids it. Each field
+    /// may only be given once, as which of its values would be kept would otherwise depend on the
+    /// order they're applied in.
+    fn validate(&self, entry: &SeriesEntry) -> Result<(), String> {
+        for (i, (field, value)) in entry.fields.iter().zip(&entry.values).enumerate() {
+            if entry.fields[..i].contains(field) {
+                return Err(format!("field '{}' is given more than once", field));
             }
-        };
 
-        // if only the series is specified, select all fields unmodified
-        let selections: Vec<_> = match query.selections.is_empty() {
-            true => { self.field_storages.values().map(|f| Selection::Field(f.name.as_str())).collect() }
-            false => { query.selections }
-        };
+            let data_type = match value.data_type() {
+                Some(data_type) => data_type,
+                None => return Err(format!("missing value for field '{}'", field)),
+            };
+
+            if let Some(storage) = self.field_storages.get(field) {
+                if storage.data_type != data_type {
+                    return Err(format!("cannot insert a {:?} value into field '{}' of type {:?}",
+                                       data_type, field, storage.data_type));
+                }
 
-        let records: Vec<Vec<FieldEntry>> = selections.iter().map(|selection| {
-            match selection {
-
-                // TODO: the selection should be passed down to the field storage, and that should
-                //  be responsible for fetching its own data. I think...
-                Selection::Field(field) => {
-                    match self.field_storages.get(*field) {
-                        Some(storage) => {
-                            storage.read(query.start, query.end)
-                        }
-                        None => {
-                            println!("Field not found!! :(");
-                            vec![]
-                        }
+                // looking for an existing entry may have to read a block, so it's only done when the
+                // answer matters
+                if self.metadata.duplicates == DuplicatePolicy::Reject {
+                    let is_duplicate = storage.has_entry(entry.time)
+                        .map_err(|err| format!("failed to read field '{}': {}", field, err))?;
+                    if is_duplicate {
+                        return Err(format!("field '{}' already has an entry at time {}", field, entry.time));
                     }
                 }
-                Selection::Expression(_) => { todo!() }
             }
-        }).collect();
+        }
+
+        Ok(())
+    }
+
+    /// Change how entries with the same field and timestamp as an existing entry are handled.
+    ///
+    /// Late entries that are still pending were accepted under the previous policy, and would be
+    /// replayed from the write-ahead log under the new one, so they're compacted into their blocks
+    /// first.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) -> io::Result<()> {
+        for storage in self.field_storages.values_mut() {
+            storage.compact()?;
+        }
+        self.checkpoint_wal()?;
+
+        self.metadata.set_duplicate_policy(policy)?;
+        for storage in self.field_storages.values_mut() {
+            storage.duplicates = policy;
+        }
+        Ok(())
+    }
+
+    /// Summarize the series as a whole.
+    pub fn summarize(&self) -> io::Result<SeriesSummary> {
+        let fields = self.summarize_fields()?;
+        Ok(SeriesSummary {
+            name: self.series_name.clone(),
+            data_type: None,
+            count: fields.iter().map(|f| f.count).sum(),
+            first: fields.iter().filter_map(|f| f.first).min(),
+            last: fields.iter().filter_map(|f| f.last).max(),
+        })
+    }
+
+    /// Summarize each field of the series, sorted by name.
+    pub fn summarize_fields(&self) -> io::Result<Vec<SeriesSummary>> {
+        let mut fields = self.field_storages.values().map(|storage| storage.summarize()).collect::<io::Result<Vec<_>>>()?;
+        fields.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(fields)
+    }
+
+    /// The data type of a field of the series, or None if it doesn't have the field.
+    pub fn field_type(&self, field: &str) -> Option<DataType> {
+        self.field_storages.get(field).map(|storage| storage.data_type.clone())
+    }
+
+    /// Whether the series has a field with the given name.
+    pub fn has_field(&self, field: &str) -> bool {
+        self.field_storages.contains_key(field)
+    }
+
+    /// Delete a field and all of its data.
+    pub fn drop_field(&mut self, field: &str) -> io::Result<()> {
+        // dropping the field's storage closes its files, and evicts its blocks from the cache
+        self.field_storages.remove(field);
+
+        // its buffered entries mustn't be replayed from the log
+        self.checkpoint_wal()?;
+
+        FieldStorage::remove(&self.series_name, field)?;
+        self.metadata.remove_field(field)
+    }
+
+    /// Delete all entries within `[start, end]` from the given fields, or from all fields if none
+    /// are given. Deleted entries are hidden from queries straight away, but only removed from
+    /// disk once they're purged, see [SeriesStorage::purge_deleted].
+    ///
+    /// Fails if a field's deletion can't be saved, in which case the remaining fields are left as
+    /// they are, while other fields may already have been deleted.
+    pub fn delete(&mut self, fields: &[String], start: Option<i64>, end: Option<i64>) -> io::Result<()> {
+        let mut deleted = Ok(());
+        for (name, storage) in self.field_storages.iter_mut() {
+            if fields.is_empty() || fields.contains(name) {
+                deleted = storage.delete(start, end);
+                if deleted.is_err() {
+                    break;
+                }
+            }
+        }
+
+        // deleted entries that were still buffered mustn't be replayed from the log
+        self.checkpoint_wal()?;
+        deleted
+    }
+
+    /// Rewrite the blocks of all fields that hold deleted entries without them. Returns whether
+    /// any blocks were rewritten.
+    pub fn purge_deleted(&mut self) -> io::Result<bool> {
+        let mut purged = false;
+        for storage in self.field_storages.values_mut() {
+            if storage.has_tombstones() {
+                storage.compact()?;
+                purged = true;
+            }
+        }
+
+        // compaction also merges late entries into their blocks, so they no longer need logging
+        if purged {
+            self.checkpoint_wal()?;
+        }
+
+        Ok(purged)
+    }
+
+    /// Change how long entries are kept, in nanoseconds, or None to keep them forever. Entries
+    /// older than the new retention period are only dropped when it's next enforced.
+    pub fn set_retention(&mut self, retention: Option<i64>) -> io::Result<()> {
+        self.metadata.set_retention(retention)
+    }
+
+    /// How long entries are kept, in nanoseconds, or None if they're kept forever.
+    pub fn retention(&self) -> Option<i64> {
+        self.metadata.retention
+    }
+
+    /// Drop entries older than the retention period, relative to the given time in nanoseconds
+    /// since Unix epoch. Blocks whose entries are all older are dropped straight away, while older
+    /// entries in a block that's only partly expired are hidden once most of it is, until they're
+    /// purged, see [FieldStorage::expire] and [SeriesStorage::purge_deleted].
+    ///
+    /// Returns whether any entries were dropped.
+    pub fn enforce_retention(&mut self, now: i64) -> io::Result<bool> {
+        let retention = match self.metadata.retention {
+            Some(retention) => retention,
+            None => return Ok(false),
+        };
+
+        let cutoff = now.saturating_sub(retention);
+        let mut expired = false;
+        for storage in self.field_storages.values_mut() {
+            expired |= storage.expire(cutoff)?;
+        }
+
+        // dropped entries that were still buffered mustn't be replayed from the log
+        if expired {
+            self.checkpoint_wal()?;
+        }
 
-        merge_records(&records, &selections, &fields)
+        Ok(expired)
     }
 
-    pub fn insert(&mut self, entry: SeriesEntry) {
-        for i in 0..entry.fields.len() {
-            let field = &entry.fields[i];
-            let value = entry.values[i];
+    /// Write an entry to field storage, without logging it. Returns whether any field flushed a
+    /// block to disk as a result.
+    fn apply(&mut self, entry: SeriesEntry) -> io::Result<bool> {
+        let mut flushed = false;
+
+        for (field, value) in entry.fields.iter().zip(entry.values) {
 
             match self.field_storages.get_mut(field) {
                 None => {
-                    let mut new_storage = FieldStorage::load(self.series_name, &field);
-                    new_storage.insert(FieldEntry { value, time: entry.time });
+                    let data_type = value.data_type().unwrap_or(DataType::Float);
+                    if !self.metadata.fields.contains_key(field) {
+                        self.metadata.add_field(field, data_type.clone())?;
+                    }
+
+                    let mut new_storage = FieldStorage::load(&self.series_name, field, data_type, self.metadata.duplicates)?;
+                    flushed |= new_storage.insert(FieldEntry { value, time: entry.time })?;
                     self.field_storages.insert(field.to_owned(), new_storage);
                 }
                 Some(field_storage) => {
-                    field_storage.insert(FieldEntry { value, time: entry.time })
+                    flushed |= field_storage.insert(FieldEntry { value, time: entry.time })?
                 }
             }
         }
+
+        Ok(flushed)
+    }
+
+    /// Rewrite the write-ahead log so that it only holds the entries that are still buffered in
+    /// memory, i.e. those that haven't been flushed to disk as part of a block.
+    fn checkpoint_wal(&mut self) -> io::Result<()> {
+        let mut entries: BTreeMap<i64, SeriesEntry> = BTreeMap::new();
+        for storage in self.field_storages.values() {
+            for entry in storage.buffered_entries() {
+                let series_entry = entries.entry(entry.time)
+                    .or_insert_with(|| SeriesEntry { fields: vec![], values: vec![], time: entry.time });
+                series_entry.fields.push(storage.name.clone());
+                series_entry.values.push(entry.value.clone());
+            }
+        }
+
+        self.wal.checkpoint(&entries.into_values().collect::<Vec<_>>())
+    }
+
+    /// Restore entries that were buffered in memory when the series was last open, from its
+    /// write-ahead log.
+    ///
+    /// If the log ends in a torn record, it's checkpointed even if nothing was flushed, since
+    /// entries appended after the torn record would otherwise never be replayed.
+    fn replay_wal(&mut self) -> io::Result<()> {
+        let log = self.wal.entries()?;
+
+        let mut flushed = false;
+        for entry in log.entries {
+            // if we crashed after flushing a block but before checkpointing the log, some entries
+            // may already be on disk. Replaying them is harmless, as writing the same entries in
+            // the same order again leaves each field with the same entry for each timestamp.
+            flushed |= self.apply(entry)?;
+        }
+
+        if flushed || log.torn {
+            self.checkpoint_wal()?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the entries of one or more series as if they were a single series, e.g. all series of a
+/// measurement with different tags. Rows of different series are interleaved by time, and
+/// aggregations are computed over the rows of all of them. Rows of different series with the same
+/// timestamp aren't merged, since they describe different sets of tags, so the result holds one
+/// row for each of them, in the order of `storages`.
+///
+/// Fields are looked up in all series of the measurement, given by `measurement`, while only the
+/// `storages` that may hold matching rows are read, so that the columns of the result don't depend
+/// on which series match. Selecting a field that none of the measurement's series have is an error.
+///
+/// A query is aggregated if it has an interval, or selects any aggregation. Rows are then reduced
+/// to one per interval, or to a single row, and fields selected without an aggregation take the
+/// last value of their group, e.g. `SELECT cpu[max(usage), host_count]` returns the maximum usage
+/// along with the latest host count.
+pub fn read_series(measurement: &[&SeriesStorage], storages: &[&SeriesStorage], query: SelectQuery) -> Result<RecordCollection, ExecutionError> {
+    if let (Some(start), Some(end)) = (query.start, query.end) {
+        if end < start {
+            return Ok(RecordCollection::empty());
+        }
+    }
+
+    let field_storage = |field: &str| measurement.iter().find_map(|s| s.field_storages.get(field));
+
+    // if only the series is specified, select all fields unmodified
+    let selections: Vec<_> = match query.selections.is_empty() {
+        true => {
+            let mut names: Vec<&str> = measurement.iter()
+                .flat_map(|s| s.field_storages.values().map(|f| f.name.as_str()))
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            names.into_iter().map(Selection::Field).collect()
+        }
+        false => { query.selections }
+    };
+
+    let is_aggregate = query.interval.is_some()
+        || selections.iter().any(|s| matches!(s, Selection::Expression(_)));
+
+    let mut columns: Vec<&str> = vec![];
+    let mut fields = vec![];
+
+    // only used for aggregate queries, in which plain fields take the last value of each group, as
+    // documented above
+    let mut aggregations = vec![];
+    let mut aggregate_fields = vec![];
+
+    for selection in &selections {
+        let (field, aggregation) = match selection {
+            Selection::Field(field) => (*field, None),
+            Selection::Expression(expression) => match &expression.expression {
+                Selection::Field(field) => (*field, Some(expression.aggregator)),
+                Selection::Expression(_) => {
+                    return Err(ExecutionError::InvalidQuery(String::from("nested aggregations are not supported")));
+                }
+            }
+        };
+
+        match field_storage(field) {
+            Some(storage) if aggregation.is_some_and(|aggregation| !aggregation.supports(&storage.data_type)) => {
+                return Err(ExecutionError::InvalidQuery(format!("cannot compute the {} of field '{}' of type {}",
+                                                                aggregation.unwrap().name(), field, storage.data_type)));
+            }
+            Some(storage) => {
+                columns.push(field);
+                fields.push(FieldDescription { name: storage.name.clone(), data_type: storage.data_type.clone() });
+
+                aggregations.push(aggregation.unwrap_or(Aggregation::Last));
+                aggregate_fields.push(match aggregation {
+                    Some(aggregation) => FieldDescription {
+                        name: format!("{}({})", aggregation.name(), field),
+                        data_type: aggregation.data_type(&storage.data_type),
+                    },
+                    None => FieldDescription { name: storage.name.clone(), data_type: storage.data_type.clone() },
+                });
+            }
+            None => return Err(ExecutionError::FieldNotFound(field.to_owned())),
+        }
+    }
+
+    // fields that are only referenced by the WHERE clause are read too, but never returned
+    if let Some(condition) = &query.condition {
+        for field in condition.fields() {
+            if !columns.contains(&field) {
+                columns.push(field);
+            }
+        }
+    }
+
+    // TODO: the selection should be passed down to the field storage, and that should
+    //  be responsible for fetching its own data. I think...
+    let mut results: Vec<RecordCollection> = vec![];
+    for series in storages {
+        let records = columns.iter().map(|&field| {
+            match series.field_storages.get(field) {
+                Some(storage) => storage.read(query.start, query.end),
+                None => Ok(vec![]),
+            }
+        }).collect::<io::Result<Vec<Vec<FieldEntry>>>>()?;
+
+        let records = merge_records(&records, fields.clone(), &columns, query.condition.as_ref(), &series.tags);
+        if !records.elements.is_empty() {
+            results.push(records);
+        }
+    }
+
+    let records = match results.len() {
+        0 => RecordCollection::empty(),
+        1 => results.pop().unwrap(),
+        _ => {
+            let mut rows: Vec<&[DataValue]> = results.iter()
+                .flat_map(|records| records.elements.chunks_exact(fields.len() + 1))
+                .collect();
+
+            // the sort is stable, so rows with the same timestamp keep the order of their series
+            rows.sort_by_key(|row| match row[0] {
+                DataValue::Timestamp(time) => time,
+                _ => i64::MAX,
+            });
+            RecordCollection { elements: rows.concat(), fields }
+        }
+    };
+
+    match is_aggregate {
+        true => Ok(aggregate_records(&records, &aggregations, aggregate_fields, query.interval, query.start)),
+        false => Ok(records),
     }
 }
 
 /// Merge "columns" of fields into a single vector of records, sorting and matching entries by
 /// their timestamp.
+///
+/// The first `fields.len()` columns are returned, in order, while any remaining columns are only
+/// used to evaluate the condition, if given, along with the tags of the series the entries belong
+/// to. Rows for which the condition doesn't hold are dropped, as are rows without any values for
+/// the returned columns, i.e. those that only have values for columns used by the condition.
 /// TODO: instead of outputting this into an intermediate result, these should get piped directly into the output
-pub fn merge_records(entries: &Vec<Vec<FieldEntry>>, selections: &Vec<Selection>, fields: &Vec<&FieldStorage>) -> RecordCollection {
+pub fn merge_records(entries: &[Vec<FieldEntry>], fields: Vec<FieldDescription>, columns: &[&str], condition: Option<&Condition>, tags: &[(String, String)]) -> RecordCollection {
     // TODO: I don't this check does exactly what we want to do, but at some point we have to guard
     //  against empty results
     if entries.iter().all(|col| col.is_empty()) {
         return RecordCollection::empty();
     }
 
-    let selection_count = selections.len();
+    let column_count = entries.len();
+    let selection_count = fields.len();
 
     let max_min_rows = match entries.iter().map(|f| f.len()).min() {
         None => return RecordCollection::empty(),
@@ -164,7 +618,7 @@ pub fn merge_records(entries: &Vec<Vec<FieldEntry>>, selections: &Vec<Selection>
     let mut exhausted_count = 0;
 
     let mut next_elems: Vec<_> = entries.iter().map(|f| {
-        let val = f.get(0);
+        let val = f.first();
         match val {
             Some(v) => v,
             None => {
@@ -174,10 +628,12 @@ pub fn merge_records(entries: &Vec<Vec<FieldEntry>>, selections: &Vec<Selection>
         }
     }).collect();
 
-    let mut indices = vec![0; entries.len()];
+    let mut indices = vec![0; column_count];
 
+    // values of the row currently being merged, for all columns
+    let mut row = vec![DataValue::None; column_count];
 
-    'outer: loop {
+    while exhausted_count < column_count {
         let mut earliest = next_elems[0].time;
         for &e in &next_elems[1..] {
             if e.time < earliest {
@@ -185,34 +641,37 @@ pub fn merge_records(entries: &Vec<Vec<FieldEntry>>, selections: &Vec<Selection>
             }
         }
 
-        elements.push(DataValue::Timestamp(earliest));
-
-        for i in 0..selection_count {
+        for i in 0..column_count {
             let entry = next_elems[i];
 
             if entry.time == earliest {
-                elements.push(entry.value);
+                row[i] = entry.value.clone();
                 indices[i] += 1;
 
                 if indices[i] == entries[i].len() {
                     exhausted_count += 1;
-                    if exhausted_count == selection_count {
-                        break 'outer;
-                    }
-
                     next_elems[i] = &exhausted_field;
                 } else {
                     next_elems[i] = &entries[i][indices[i]];
                 }
             } else {
-                elements.push(DataValue::None);
+                row[i] = DataValue::None;
             }
         }
-    }
 
-    let fields = fields.iter().map(|&field_storage| {
-        FieldDescription { name: field_storage.name.clone(), data_type: field_storage.data_type.clone() }
-    }).collect();
+        if row[..selection_count].iter().all(|value| *value == DataValue::None) {
+            continue;
+        }
+
+        if let Some(condition) = condition {
+            if !condition.evaluate(&row, columns, tags) {
+                continue;
+            }
+        }
+
+        elements.push(DataValue::Timestamp(earliest));
+        elements.extend_from_slice(&row[..selection_count]);
+    }
 
     RecordCollection { fields, elements }
 }
@@ -221,15 +680,23 @@ pub fn merge_records(entries: &Vec<Vec<FieldEntry>>, selections: &Vec<Selection>
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use crate::storage::data_dir;
 
     use crate::DataValue;
-    use crate::lang::{Selection, SelectQuery};
+    use crate::execution::ExecutionError;
+    use crate::lang::{Aggregation, Comparison, Condition, Operator, SelectExpression, Selection, SelectQuery};
+    use crate::lang::query::parse_select;
+    use crate::storage::field::FieldEntry;
     use crate::storage::field_block::ENTRIES_PER_BLOCK;
-    use crate::storage::series::{SeriesEntry, SeriesStorage};
+    use crate::storage::metadata::{DuplicatePolicy, SeriesMetadata};
+    use crate::storage::series::{merge_records, SeriesEntry, SeriesStorage, SeriesSummary};
     use crate::util::new_timestamp;
+    use crate::wire_protocol::{DataType, FieldDescription};
 
     fn clear_tmp_files() {
-        fs::remove_dir("test_series");
+        let _ = fs::remove_dir_all(format!("{}/test_series", data_dir()));
     }
 
     // TODO: update these tests when we're including timestamps
@@ -281,11 +748,472 @@ mod tests {
     // }
 
 
+    #[test]
+    fn merge_filtered() {
+        let entries = vec![
+            vec![FieldEntry { value: DataValue::from(1.0), time: 1 }, FieldEntry { value: DataValue::from(2.0), time: 2 }, FieldEntry { value: DataValue::from(5.0), time: 3 }],
+            vec![FieldEntry { value: DataValue::from(true), time: 1 }, FieldEntry { value: DataValue::from(false), time: 3 }],
+        ];
+        let fields = vec![FieldDescription { name: String::from("field1"), data_type: DataType::Float }];
+
+        // field2 is only used for filtering, and isn't returned
+        let condition = Condition::Or(
+            Box::new(Condition::Comparison(Comparison { field: "field1", operator: Operator::Gt, value: DataValue::from(4.0) })),
+            Box::new(Condition::Comparison(Comparison { field: "field2", operator: Operator::Eq, value: DataValue::from(true) })),
+        );
+
+        let records = merge_records(&entries, fields, &["field1", "field2"], Some(&condition), &[]);
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(1), DataValue::from(1.0),
+            DataValue::Timestamp(3), DataValue::from(5.0),
+        ]);
+
+        // a missing value fails any comparison, so its negation holds
+        let condition = Condition::Not(Box::new(
+            Condition::Comparison(Comparison { field: "field2", operator: Operator::Eq, value: DataValue::from(true) })
+        ));
+        let fields = vec![FieldDescription { name: String::from("field1"), data_type: DataType::Float }];
+        let records = merge_records(&entries, fields, &["field1", "field2"], Some(&condition), &[]);
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(2), DataValue::from(2.0),
+            DataValue::Timestamp(3), DataValue::from(5.0),
+        ]);
+
+        // rows with only a value for the filtered field have nothing to return
+        let entries = vec![
+            vec![FieldEntry { value: DataValue::from(1.0), time: 1 }],
+            vec![FieldEntry { value: DataValue::from(true), time: 1 }, FieldEntry { value: DataValue::from(true), time: 2 }],
+        ];
+        let condition = Condition::Comparison(Comparison { field: "field2", operator: Operator::Eq, value: DataValue::from(true) });
+        let fields = vec![FieldDescription { name: String::from("field1"), data_type: DataType::Float }];
+        let records = merge_records(&entries, fields, &["field1", "field2"], Some(&condition), &[]);
+        assert_eq!(records.elements, vec![DataValue::Timestamp(1), DataValue::from(1.0)]);
+    }
+
+    #[test]
+    fn it_aggregates() {
+        let _ = fs::remove_dir_all(format!("{}/test_aggregates", data_dir()));
+        let mut s = SeriesStorage::new("test_aggregates").unwrap();
+
+        for (i, value) in [3.0, 1.0, 4.0, 1.0, 5.0, 9.0].iter().enumerate() {
+            s.insert(SeriesEntry { fields: vec![String::from("value1")], values: vec![DataValue::from(*value)], time: i as i64 * 10 }).unwrap();
+        }
+
+        let mut input = String::from("SELECT test_aggregates[first(value1), last(value1), min(value1), max(value1), mean(value1)]");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+                   vec!["first(value1)", "last(value1)", "min(value1)", "max(value1)", "mean(value1)"]);
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(0), DataValue::from(3.0), DataValue::from(9.0), DataValue::from(1.0), DataValue::from(9.0), DataValue::from(23.0 / 6.0),
+        ]);
+
+        let mut input = String::from("SELECT test_aggregates[first(value1), last(value1), min(value1), max(value1), mean(value1)] GROUP BY time(30ns)");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(0), DataValue::from(3.0), DataValue::from(4.0), DataValue::from(1.0), DataValue::from(4.0), DataValue::from(8.0 / 3.0),
+            DataValue::Timestamp(30), DataValue::from(1.0), DataValue::from(9.0), DataValue::from(1.0), DataValue::from(9.0), DataValue::from(5.0),
+        ]);
+
+        let mut input = String::from("SELECT test_aggregates[max(value1)] AFTER 15 BEFORE 35");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.elements, vec![DataValue::Timestamp(15), DataValue::from(4.0)]);
+
+        // nested aggregations can't be parsed, but can still be built by hand
+        let nested = Selection::Expression(Box::new(SelectExpression {
+            expression: Selection::Expression(Box::new(SelectExpression { expression: Selection::Field("value1"), aggregator: Aggregation::Mean })),
+            aggregator: Aggregation::Max,
+        }));
+        let query = SelectQuery { series: "test_aggregates", selections: vec![nested], start: None, end: None, condition: None, interval: None };
+        assert_eq!(s.read(query).err(), Some(ExecutionError::InvalidQuery(String::from("nested aggregations are not supported"))));
+
+        // fields without an aggregation take the last value of each group
+        let mut input = String::from("SELECT test_aggregates[value1, min(value1)] GROUP BY time(30ns)");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.fields[0].name, "value1");
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(0), DataValue::from(4.0), DataValue::from(1.0),
+            DataValue::Timestamp(30), DataValue::from(9.0), DataValue::from(1.0),
+        ]);
+
+        // strings have no mean, but can still be aggregated otherwise
+        s.insert(SeriesEntry { fields: vec![String::from("name")], values: vec![DataValue::from("a")], time: 60 }).unwrap();
+        let mut input = String::from("SELECT test_aggregates[mean(name)]");
+        assert_eq!(s.read(parse_select(&mut input).unwrap()).err(),
+                   Some(ExecutionError::InvalidQuery(String::from("cannot compute the mean of field 'name' of type string"))));
+        let mut input = String::from("SELECT test_aggregates[last(name)]");
+        assert_eq!(s.read(parse_select(&mut input).unwrap()).unwrap().elements, vec![DataValue::Timestamp(60), DataValue::from("a")]);
+    }
+
+    #[test]
+    fn it_recovers_buffered_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_recovery", data_dir()));
+
+        let mut s = SeriesStorage::new("test_recovery").unwrap();
+        let count = ENTRIES_PER_BLOCK * 2 + 10;
+        for i in 0..count {
+            s.insert(SeriesEntry {
+                fields: vec![String::from("value1")],
+                values: vec![DataValue::from(i as f64)],
+                time: i as i64,
+            }).unwrap();
+        }
+        drop(s);
+
+        // all entries, including those that were only buffered in memory, survive a restart
+        let s = SeriesStorage::load("test_recovery").unwrap();
+        let mut input = String::from("SELECT test_recovery[value1]");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.len(), count);
+        assert_eq!(records.elements[records.elements.len() - 2], DataValue::Timestamp(count as i64 - 1));
+
+        // entries that were flushed aren't replayed again
+        drop(s);
+        let s = SeriesStorage::load("test_recovery").unwrap();
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.len(), count);
+    }
+
+    #[test]
+    fn it_recovers_after_torn_records() {
+        let _ = fs::remove_dir_all(format!("{}/test_torn_recovery", data_dir()));
+
+        let entry = |time: i64| SeriesEntry {
+            fields: vec![String::from("value1")],
+            values: vec![DataValue::from(time as f64)],
+            time,
+        };
+
+        let mut s = SeriesStorage::new("test_torn_recovery").unwrap();
+        for i in 0..5 {
+            s.insert(entry(i)).unwrap();
+        }
+        drop(s);
+
+        // simulate a crash halfway through writing a record
+        let mut file = OpenOptions::new().append(true).open(format!("{}/test_torn_recovery/_wal", data_dir())).unwrap();
+        file.write_all(&[100, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+
+        // entries written after the torn record must survive the next restart as well
+        let mut s = SeriesStorage::load("test_torn_recovery").unwrap();
+        for i in 5..10 {
+            s.insert(entry(i)).unwrap();
+        }
+        drop(s);
+
+        let s = SeriesStorage::load("test_torn_recovery").unwrap();
+        let mut input = String::from("SELECT test_torn_recovery[value1]");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.len(), 10);
+    }
+
+    #[test]
+    fn it_recovers_late_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_late_recovery", data_dir()));
+
+        let entry = |time: i64| SeriesEntry {
+            fields: vec![String::from("value1")],
+            values: vec![DataValue::from(time as f64)],
+            time,
+        };
+
+        let mut s = SeriesStorage::new("test_late_recovery").unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 * 2 {
+            s.insert(entry(i * 10)).unwrap();
+        }
+        // late entries, older than the flushed block, but not compacted yet
+        for i in 0..10 {
+            s.insert(entry(i * 10 + 5)).unwrap();
+        }
+        drop(s);
+
+        let s = SeriesStorage::load("test_late_recovery").unwrap();
+        let mut input = String::from("SELECT test_late_recovery[value1] BEFORE 30");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(0), DataValue::from(0.0),
+            DataValue::Timestamp(5), DataValue::from(5.0),
+            DataValue::Timestamp(10), DataValue::from(10.0),
+            DataValue::Timestamp(15), DataValue::from(15.0),
+            DataValue::Timestamp(20), DataValue::from(20.0),
+            DataValue::Timestamp(25), DataValue::from(25.0),
+            DataValue::Timestamp(30), DataValue::from(30.0),
+        ]);
+
+        let mut input = String::from("SELECT test_late_recovery[value1]");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.len(), ENTRIES_PER_BLOCK * 2 + 10);
+    }
+
+    #[test]
+    fn it_handles_duplicate_timestamps() {
+        let _ = fs::remove_dir_all(format!("{}/test_duplicates", data_dir()));
+
+        let entry = |time: i64, value: f64| SeriesEntry {
+            fields: vec![String::from("value1")],
+            values: vec![DataValue::from(value)],
+            time,
+        };
+        let value_at = |s: &SeriesStorage, time: i64| {
+            let mut input = format!("SELECT test_duplicates[value1] AFTER {} BEFORE {}", time, time);
+            s.read(parse_select(&mut input).unwrap()).unwrap().elements
+        };
+
+        // by default, the last write wins, both for buffered and flushed entries
+        let mut s = SeriesStorage::new("test_duplicates").unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 + 10 {
+            s.insert(entry(i, 1.0)).unwrap();
+        }
+        s.insert(entry(5, 2.0)).unwrap();
+        s.insert(entry(ENTRIES_PER_BLOCK as i64 + 5, 2.0)).unwrap();
+        assert_eq!(value_at(&s, 5), vec![DataValue::Timestamp(5), DataValue::from(2.0)]);
+        assert_eq!(value_at(&s, ENTRIES_PER_BLOCK as i64 + 5),
+                   vec![DataValue::Timestamp(ENTRIES_PER_BLOCK as i64 + 5), DataValue::from(2.0)]);
+
+        s.set_duplicate_policy(DuplicatePolicy::KeepFirst).unwrap();
+        s.insert(entry(5, 3.0)).unwrap();
+        s.insert(entry(6, 3.0)).unwrap();
+        assert_eq!(value_at(&s, 5), vec![DataValue::Timestamp(5), DataValue::from(2.0)]);
+        assert_eq!(value_at(&s, 6), vec![DataValue::Timestamp(6), DataValue::from(1.0)]);
+
+        s.set_duplicate_policy(DuplicatePolicy::Reject).unwrap();
+        assert!(s.insert(entry(7, 3.0)).is_err());
+        assert!(s.insert(entry(ENTRIES_PER_BLOCK as i64 + 7, 3.0)).is_err());
+        s.insert(entry(ENTRIES_PER_BLOCK as i64 + 100, 3.0)).unwrap();
+        drop(s);
+
+        // the policy and the surviving entries are restored after a restart
+        let s = SeriesStorage::load("test_duplicates").unwrap();
+        assert_eq!(s.metadata.duplicates, DuplicatePolicy::Reject);
+        assert_eq!(value_at(&s, 5), vec![DataValue::Timestamp(5), DataValue::from(2.0)]);
+        assert_eq!(value_at(&s, 7), vec![DataValue::Timestamp(7), DataValue::from(1.0)]);
+
+        let mut input = String::from("SELECT test_duplicates[value1]");
+        assert_eq!(s.read(parse_select(&mut input).unwrap()).unwrap().len(), ENTRIES_PER_BLOCK + 11);
+    }
+
+    #[test]
+    fn it_rejects_repeated_fields() {
+        let _ = fs::remove_dir_all(format!("{}/test_repeated_fields", data_dir()));
+
+        let mut s = SeriesStorage::new("test_repeated_fields").unwrap();
+        let entry = |values: Vec<DataValue>| SeriesEntry { fields: vec![String::from("value1"); values.len()], values, time: 1 };
+        assert!(s.insert(entry(vec![DataValue::from(1.0), DataValue::from(2.0)])).is_err());
+        assert!(s.insert(entry(vec![DataValue::from(1.0), DataValue::from(true)])).is_err());
+        assert!(!s.has_field("value1"));
+
+        s.insert(entry(vec![DataValue::from(1.0)])).unwrap();
+        assert!(s.insert(entry(vec![DataValue::from(1.0), DataValue::from(2.0)])).is_err());
+    }
+
+    #[test]
+    fn it_enforces_retention() {
+        let _ = fs::remove_dir_all(format!("{}/test_retention", data_dir()));
+
+        let entry = |time: i64| SeriesEntry {
+            fields: vec![String::from("value1")],
+            values: vec![DataValue::from(time as f64)],
+            time,
+        };
+        let count = |s: &SeriesStorage| {
+            let mut input = String::from("SELECT test_retention[value1]");
+            s.read(parse_select(&mut input).unwrap()).unwrap().len()
+        };
+
+        let mut s = SeriesStorage::new("test_retention").unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 * 3 + 1 {
+            s.insert(entry(i)).unwrap();
+        }
+        // a late entry, which is only in the write-ahead log
+        s.insert(entry(-1)).unwrap();
+
+        // without a retention period, nothing is ever dropped
+        let now = ENTRIES_PER_BLOCK as i64 * 10;
+        assert!(!s.enforce_retention(now).unwrap());
+
+        // older entries are dropped, whether or not their whole block is
+        s.set_retention(Some(ENTRIES_PER_BLOCK as i64 * 8 + 1)).unwrap();
+        assert!(s.enforce_retention(now).unwrap());
+        assert_eq!(count(&s), ENTRIES_PER_BLOCK + 2);
+        assert!(!s.enforce_retention(now).unwrap());
+        drop(s);
+
+        // the dropped late entry isn't replayed after a restart
+        let mut s = SeriesStorage::load("test_retention").unwrap();
+        assert_eq!(s.retention(), Some(ENTRIES_PER_BLOCK as i64 * 8 + 1));
+        assert_eq!(count(&s), ENTRIES_PER_BLOCK + 2);
+
+        s.set_retention(None).unwrap();
+        assert!(!s.enforce_retention(i64::MAX).unwrap());
+        assert_eq!(count(&s), ENTRIES_PER_BLOCK + 2);
+    }
+
+    #[test]
+    fn it_deletes_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_series_delete", data_dir()));
+
+        let entry = |time: i64| SeriesEntry {
+            fields: vec![String::from("value1"), String::from("value2")],
+            values: vec![DataValue::from(time as f64), DataValue::from(true)],
+            time,
+        };
+        let select = |s: &SeriesStorage, query: &str| {
+            let mut input = String::from(query);
+            s.read(parse_select(&mut input).unwrap()).unwrap().len()
+        };
+
+        let mut s = SeriesStorage::new("test_series_delete").unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 * 2 + 10 {
+            s.insert(entry(i)).unwrap();
+        }
+
+        // deleting from one field leaves the others untouched
+        s.delete(&[String::from("value1")], Some(5), None).unwrap();
+        assert_eq!(select(&s, "SELECT test_series_delete[value1]"), 5);
+        assert_eq!(select(&s, "SELECT test_series_delete[value2]"), ENTRIES_PER_BLOCK * 2 + 10);
+
+        s.delete(&[], None, Some(1)).unwrap();
+        assert_eq!(select(&s, "SELECT test_series_delete[value1]"), 3);
+        assert_eq!(select(&s, "SELECT test_series_delete[value2]"), ENTRIES_PER_BLOCK * 2 + 8);
+        drop(s);
+
+        // deleted entries stay deleted after a restart, including those that were buffered
+        let mut s = SeriesStorage::load("test_series_delete").unwrap();
+        assert_eq!(select(&s, "SELECT test_series_delete[value1]"), 3);
+        assert_eq!(select(&s, "SELECT test_series_delete[value2]"), ENTRIES_PER_BLOCK * 2 + 8);
+
+        assert!(s.purge_deleted().unwrap());
+        assert!(!s.purge_deleted().unwrap());
+        assert_eq!(select(&s, "SELECT test_series_delete[value1]"), 3);
+        assert_eq!(select(&s, "SELECT test_series_delete[value2]"), ENTRIES_PER_BLOCK * 2 + 8);
+    }
+
+    #[test]
+    fn it_drops_fields() {
+        let _ = fs::remove_dir_all(format!("{}/test_drop_field", data_dir()));
+
+        let entry = |time: i64| SeriesEntry {
+            fields: vec![String::from("value1"), String::from("value2"), String::from("value3")],
+            values: vec![DataValue::from(time as f64), DataValue::from(true), DataValue::from(1.0)],
+            time,
+        };
+        let select = |s: &SeriesStorage, query: &str| {
+            let mut input = String::from(query);
+            s.read(parse_select(&mut input).unwrap()).unwrap().len()
+        };
+
+        let mut s = SeriesStorage::new("test_drop_field").unwrap();
+        for i in 0..ENTRIES_PER_BLOCK as i64 + 10 {
+            s.insert(entry(i)).unwrap();
+        }
+        s.delete(&[String::from("value1")], None, Some(5)).unwrap();
+
+        s.drop_field("value1").unwrap();
+        assert!(!s.has_field("value1"));
+        assert!(!s.metadata.fields.contains_key("value1"));
+        for file in ["value1", "value1_index", "_value1_tombstones", "_value1_dropped"] {
+            assert!(fs::metadata(format!("{}/test_drop_field/{}", data_dir(), file)).is_err(), "{}", file);
+        }
+
+        // a crash after the data file was renamed leaves the field dropped
+        s.field_storages.remove("value2");
+        s.checkpoint_wal().unwrap();
+        fs::rename(format!("{}/test_drop_field/value2", data_dir()), format!("{}/test_drop_field/_value2_dropped", data_dir())).unwrap();
+        drop(s);
+
+        // neither field comes back, even from the write-ahead log
+        let mut s = SeriesStorage::load("test_drop_field").unwrap();
+        assert!(!s.has_field("value1"));
+        assert!(!s.has_field("value2"));
+        assert!(!s.metadata.fields.contains_key("value2"));
+        assert!(fs::metadata(format!("{}/test_drop_field/value2_index", data_dir())).is_err());
+        assert_eq!(select(&s, "SELECT test_drop_field[value3]"), ENTRIES_PER_BLOCK + 10);
+
+        // a field with the same name starts out empty, and may have a different type
+        s.insert(SeriesEntry { fields: vec![String::from("value1")], values: vec![DataValue::from(false)], time: 1 }).unwrap();
+        assert_eq!(s.metadata.fields.get("value1"), Some(&DataType::Bool));
+        assert_eq!(select(&s, "SELECT test_drop_field[value1]"), 1);
+    }
+
+    #[test]
+    fn it_rejects_fields_whose_type_cant_be_saved() {
+        let _ = fs::remove_dir_all(format!("{}/test_metadata_failure", data_dir()));
+
+        let entry = |field: &str| SeriesEntry { fields: vec![String::from(field)], values: vec![DataValue::from(1.0)], time: 1 };
+        let mut s = SeriesStorage::new("test_metadata_failure").unwrap();
+        s.insert(entry("value1")).unwrap();
+
+        // the metadata is saved through a temporary file, which can't be created over a directory
+        fs::create_dir(format!("{}/test_metadata_failure/_meta.tmp", data_dir())).unwrap();
+        assert!(s.insert(entry("value2")).is_err());
+        assert!(!s.has_field("value2"));
+        assert!(!s.metadata.fields.contains_key("value2"));
+
+        fs::remove_dir(format!("{}/test_metadata_failure/_meta.tmp", data_dir())).unwrap();
+        s.insert(entry("value2")).unwrap();
+        assert_eq!(SeriesMetadata::load("test_metadata_failure").unwrap().fields.get("value2"), Some(&DataType::Float));
+    }
+
+    #[test]
+    fn it_summarizes() {
+        let _ = fs::remove_dir_all(format!("{}/test_series_summary", data_dir()));
+
+        let mut s = SeriesStorage::new("test_series_summary").unwrap();
+        assert_eq!(s.summarize().unwrap(), SeriesSummary { name: String::from("test_series_summary"), data_type: None, count: 0, first: None, last: None });
+
+        s.insert(SeriesEntry { fields: vec![String::from("value2")], values: vec![DataValue::from(true)], time: 1 }).unwrap();
+        s.insert(SeriesEntry {
+            fields: vec![String::from("value1"), String::from("value2")],
+            values: vec![DataValue::from(1.0), DataValue::from(false)],
+            time: 3,
+        }).unwrap();
+        drop(s);
+
+        // buffered entries are restored from the write-ahead log
+        let s = SeriesStorage::load("test_series_summary").unwrap();
+        assert_eq!(s.summarize().unwrap(), SeriesSummary { name: String::from("test_series_summary"), data_type: None, count: 3, first: Some(1), last: Some(3) });
+        assert_eq!(s.summarize_fields().unwrap(), vec![
+            SeriesSummary { name: String::from("value1"), data_type: Some(DataType::Float), count: 1, first: Some(3), last: Some(3) },
+            SeriesSummary { name: String::from("value2"), data_type: Some(DataType::Bool), count: 2, first: Some(1), last: Some(3) },
+        ]);
+        assert!(SeriesStorage::list().contains(&String::from("test_series_summary")));
+    }
+
+    #[test]
+    fn it_persists_data_types() {
+        let _ = fs::remove_dir_all(format!("{}/test_data_types", data_dir()));
+
+        let mut s = SeriesStorage::new("test_data_types").unwrap();
+        s.insert(SeriesEntry {
+            fields: vec![String::from("value1"), String::from("value2")],
+            values: vec![DataValue::from(1.0), DataValue::from(true)],
+            time: 1,
+        }).unwrap();
+
+        // values of the wrong type are rejected, without writing any of the entry
+        let result = s.insert(SeriesEntry {
+            fields: vec![String::from("value1"), String::from("value2")],
+            values: vec![DataValue::from(2.0), DataValue::from(2.0)],
+            time: 2,
+        });
+        assert!(result.is_err());
+        drop(s);
+
+        let s = SeriesStorage::load("test_data_types").unwrap();
+        let mut input = String::from("SELECT test_data_types[value1, value2]");
+        let records = s.read(parse_select(&mut input).unwrap()).unwrap();
+        assert_eq!(records.fields, vec![
+            FieldDescription { name: String::from("value1"), data_type: DataType::Float },
+            FieldDescription { name: String::from("value2"), data_type: DataType::Bool },
+        ]);
+        assert_eq!(records.elements, vec![DataValue::Timestamp(1), DataValue::from(1.0), DataValue::from(true)]);
+    }
+
     #[test]
     fn it_writes_a_series_entry() {
         clear_tmp_files();
 
-        let mut s = SeriesStorage::new("test_series");
+        let mut s = SeriesStorage::new("test_series").unwrap();
 
         for _i in 0..ENTRIES_PER_BLOCK * 5 + 1 {
             let entry1 = SeriesEntry {
@@ -299,39 +1227,51 @@ mod tests {
                 time: new_timestamp(),
             };
 
-            s.insert(entry1);
-            s.insert(entry2);
+            s.insert(entry1).unwrap();
+            s.insert(entry2).unwrap();
         }
     }
 
     #[test]
     fn it_reads() {
-        // clear_tmp_files();
-
-        let s = SeriesStorage::load("test_series");
-        // for _ in 0..ENTRIES_PER_BLOCK * 10 + 1 {
-        //     s.insert(SeriesEntry {
-        //         values: HashMap::from([(String::from("value1"), 1.0), (String::from("value2"), -1.0)]),
-        //         time: time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as i64,
-        //     });
-        // }
-
-        dbg!(&s.field_storages.get("field1").unwrap());
-        let _r = s.read(SelectQuery {
-            series: "test_series",
+        let _ = fs::remove_dir_all(format!("{}/test_series_read", data_dir()));
+
+        let mut s = SeriesStorage::new("test_series_read").unwrap();
+        for time in 1..=2 {
+            s.insert(SeriesEntry {
+                fields: vec!["field1".to_owned(), "field2".to_owned()],
+                values: vec![DataValue::from(time as f64), DataValue::from(time == 2)],
+                time,
+            }).unwrap();
+        }
+        drop(s);
+
+        let s = SeriesStorage::load("test_series_read").unwrap();
+        let records = s.read(SelectQuery {
+            series: "test_series_read",
             selections: vec![Selection::Field("field1")],
             start: None,
             end: None,
-        });
-        // dbg!(r.rows.len());
+            condition: None,
+            interval: None,
+        }).unwrap();
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(1), DataValue::from(1.0),
+            DataValue::Timestamp(2), DataValue::from(2.0),
+        ]);
 
-        dbg!(&s.field_storages.get("field2").unwrap());
-        let _r = s.read(SelectQuery {
-            series: "test_series",
+        let records = s.read(SelectQuery {
+            series: "test_series_read",
             selections: vec![Selection::Field("field2")],
             start: None,
             end: None,
-        });
+            condition: None,
+            interval: None,
+        }).unwrap();
+        assert_eq!(records.elements, vec![
+            DataValue::Timestamp(1), DataValue::from(false),
+            DataValue::Timestamp(2), DataValue::from(true),
+        ]);
     }
 
 
@@ -1,26 +1,294 @@
-use crate::storage::block_bool::{deserialize_bools, serialize_bools, serialize_floats};
+use crate::DataValue;
+use std::iter::Take;
+
+use crate::storage::block_bool::{BoolIter, deserialize_bools, serialize_bools};
+use crate::storage::block_float::{deserialize_floats, FloatIter, serialize_floats};
+use crate::storage::block_int::{deserialize_ints, IntIter, serialize_ints, validate_ints};
+use crate::storage::block_string::{deserialize_strings, serialize_strings, StringIter, validate_strings};
 use crate::wire_protocol::DataType;
 
+/// The version of the on-disk block format written by this version of the database. Readers check
+/// it before anything else, so the layout of everything after the version may change between
+/// versions.
+pub const BLOCK_FORMAT_VERSION: u8 = 1;
+
+/// The size of a serialized block header, in bytes.
+pub const HEADER_SIZE: usize = 32;
+
+/// How the values of a block are encoded. New encodings may be added without changing the
+/// block format version, as long as existing ones keep their ids.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Encoding {
+    /// XOR compressed floats, see [crate::storage::block_float].
+    Float64Xor = 1,
+    /// 2 bits per value, see [crate::storage::block_bool].
+    Bool2Bit = 2,
+    /// Dictionary encoded strings, see [crate::storage::block_string].
+    StringDict = 3,
+    /// Delta, zig-zag and simple8b encoded integers, see [crate::storage::block_int].
+    Int64Simple8b = 4,
+}
+
+impl TryFrom<u8> for Encoding {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Encoding::Float64Xor),
+            2 => Ok(Encoding::Bool2Bit),
+            3 => Ok(Encoding::StringDict),
+            4 => Ok(Encoding::Int64Simple8b),
+            e => Err(format!("unknown block encoding: {}", e)),
+        }
+    }
+}
+
+/// The header at the start of every block on disk, laid out as:
+///
+/// [VERSION] [DATA_TYPE] [ENCODING] [RESERVED] [COUNT] [MIN_TIME] [MAX_TIME] [TIMES_LEN] [VALUES_LEN]
+/// u8        u8          u8         u8         u32     i64        i64        u32         u32
+///
+/// and followed by the timestamp column, then the value column, whose lengths in bytes are given
+/// by the header. All integers are little endian.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    pub version: u8,
+    pub data_type: DataType,
+    pub encoding: Encoding,
+    pub count: u32,
+    pub min_time: i64,
+    pub max_time: i64,
+    pub times_len: u32,
+    pub values_len: u32,
+}
+
+impl BlockHeader {
+    pub fn serialize(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0] = self.version;
+        bytes[1] = self.data_type.clone() as u8;
+        bytes[2] = self.encoding as u8;
+        bytes[4..8].copy_from_slice(&self.count.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.min_time.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.max_time.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.times_len.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.values_len.to_le_bytes());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<BlockHeader, String> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(String::from("block is too short to contain a header"));
+        }
+
+        let version = bytes[0];
+        if version != BLOCK_FORMAT_VERSION {
+            return Err(format!("unsupported block format version: {}", version));
+        }
+
+        let data_type = DataType::try_from(bytes[1])?;
+        let encoding = Encoding::try_from(bytes[2])?;
+
+        Ok(BlockHeader {
+            version,
+            data_type,
+            encoding,
+            count: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            min_time: i64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            max_time: i64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            times_len: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+            values_len: u32::from_le_bytes(bytes[28..32].try_into().unwrap()),
+        })
+    }
+}
+
+/// A typed, null-aware column of values.
 pub enum StorageBlock {
     Bool(Vec<Option<bool>>),
     Float64(Vec<Option<f64>>),
+    String(Vec<Option<String>>),
+    Int64(Vec<Option<i64>>),
 }
 
 impl StorageBlock {
+    /// Collect values of the given type into a column. Values of any other type are treated as
+    /// missing. Fails for types that have no column encoding, i.e. timestamps, which are only
+    /// stored as the time of entries.
+    pub fn from_values<'a, I>(values: I, data_type: &DataType) -> Result<StorageBlock, String>
+        where
+            I: Iterator<Item=&'a DataValue>
+    {
+        let column = match data_type {
+            DataType::Float => StorageBlock::Float64(values.map(|v| match v {
+                DataValue::Float(f) => Some(*f),
+                _ => None,
+            }).collect()),
+            DataType::Bool => StorageBlock::Bool(values.map(|v| match v {
+                DataValue::Bool(b) => Some(*b),
+                _ => None,
+            }).collect()),
+            DataType::String => StorageBlock::String(values.map(|v| match v {
+                DataValue::String(s) => Some(s.clone()),
+                _ => None,
+            }).collect()),
+            DataType::Integer => StorageBlock::Int64(values.map(|v| match v {
+                DataValue::Integer(i) => Some(*i),
+                _ => None,
+            }).collect()),
+            DataType::Timestamp => return Err(format!("fields of type {} can't be stored", data_type)),
+        };
+
+        Ok(column)
+    }
+
+    /// Returns the column's values, with missing values as DataValue::None.
+    pub fn into_values(self) -> Vec<DataValue> {
+        match self {
+            StorageBlock::Bool(values) => values.into_iter().map(|v| v.map_or(DataValue::None, DataValue::Bool)).collect(),
+            StorageBlock::Float64(values) => values.into_iter().map(|v| v.map_or(DataValue::None, DataValue::Float)).collect(),
+            StorageBlock::String(values) => values.into_iter().map(|v| v.map_or(DataValue::None, DataValue::String)).collect(),
+            StorageBlock::Int64(values) => values.into_iter().map(|v| v.map_or(DataValue::None, DataValue::Integer)).collect(),
+        }
+    }
+
+    /// The encoding used when serializing this column.
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            StorageBlock::Bool(_) => Encoding::Bool2Bit,
+            StorageBlock::Float64(_) => Encoding::Float64Xor,
+            StorageBlock::String(_) => Encoding::StringDict,
+            StorageBlock::Int64(_) => Encoding::Int64Simple8b,
+        }
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         match self {
             StorageBlock::Bool(values) => { serialize_bools(values) }
             StorageBlock::Float64(values) => { serialize_floats(values) }
+            StorageBlock::String(values) => { serialize_strings(values) }
+            StorageBlock::Int64(values) => { serialize_ints(values) }
+        }
+    }
+
+    /// Deserialize a column of `count` values from a buffer in the given encoding.
+    pub fn deserialize_from(buffer: &[u8], encoding: Encoding, count: usize) -> StorageBlock {
+        match encoding {
+            Encoding::Float64Xor => { StorageBlock::Float64(deserialize_floats(buffer)) }
+            Encoding::Bool2Bit => {
+                // bools are packed 4 to a byte, so the last byte may be padded
+                let mut values = deserialize_bools(buffer);
+                values.truncate(count);
+                StorageBlock::Bool(values)
+            }
+            Encoding::StringDict => { StorageBlock::String(deserialize_strings(buffer)) }
+            Encoding::Int64Simple8b => { StorageBlock::Int64(deserialize_ints(buffer)) }
+        }
+    }
+
+    /// Check that a serialized column holds exactly `count` values, without decoding it.
+    pub fn validate(buffer: &[u8], encoding: Encoding, count: usize) -> Result<(), String> {
+        let valid = match encoding {
+            Encoding::Float64Xor => buffer.len() >= 4 + count.div_ceil(8)
+                && u32::from_le_bytes(buffer[..4].try_into().unwrap()) as usize == count,
+            Encoding::Bool2Bit => buffer.len() == count.div_ceil(4),
+            Encoding::StringDict => validate_strings(buffer, count),
+            Encoding::Int64Simple8b => validate_ints(buffer, count),
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(String::from("block column doesn't match the entry count in its header")),
+        }
+    }
+}
+
+/// Iterates over the values of a serialized column, decoding them as they're read rather than
+/// all at once.
+pub enum ColumnIter<'a> {
+    Bool(Take<BoolIter<'a>>),
+    Float64(FloatIter<'a>),
+    String(StringIter<'a>),
+    Int64(IntIter<'a>),
+}
+
+impl<'a> ColumnIter<'a> {
+    pub fn new(buffer: &'a [u8], encoding: Encoding, count: usize) -> ColumnIter<'a> {
+        match encoding {
+            Encoding::Float64Xor => ColumnIter::Float64(FloatIter::new(buffer)),
+            // bools are packed 4 to a byte, so the last byte may be padded
+            Encoding::Bool2Bit => ColumnIter::Bool(BoolIter::new(buffer).take(count)),
+            Encoding::StringDict => ColumnIter::String(StringIter::new(buffer)),
+            Encoding::Int64Simple8b => ColumnIter::Int64(IntIter::new(buffer)),
         }
     }
+}
+
+impl<'a> Iterator for ColumnIter<'a> {
+    type Item = DataValue;
 
-    // TODO: takes either a file handler and an offset, or a byte slice (prob latter), and does the thingy
-    //  oh also needs a type...
-    pub fn deserialize_from(buffer: &[u8], data_type: DataType) -> StorageBlock {
-        match data_type {
-            DataType::Float => { todo!() }
-            DataType::Bool => { StorageBlock::Bool(deserialize_bools(buffer)) }
-            DataType::Timestamp => { todo!() }
+    #[inline]
+    fn next(&mut self) -> Option<DataValue> {
+        match self {
+            ColumnIter::Bool(values) => values.next().map(|v| v.map_or(DataValue::None, DataValue::Bool)),
+            ColumnIter::Float64(values) => values.next().map(|v| v.map_or(DataValue::None, DataValue::Float)),
+            ColumnIter::String(values) => values.next().map(|v| v.map_or(DataValue::None, DataValue::String)),
+            ColumnIter::Int64(values) => values.next().map(|v| v.map_or(DataValue::None, DataValue::Integer)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::DataValue;
+    use crate::storage::storage_block::{BLOCK_FORMAT_VERSION, BlockHeader, Encoding, StorageBlock};
+    use crate::wire_protocol::DataType;
+
+    #[test]
+    fn header_full_loop() {
+        let header = BlockHeader {
+            version: BLOCK_FORMAT_VERSION,
+            data_type: DataType::Bool,
+            encoding: Encoding::Bool2Bit,
+            count: 100,
+            min_time: -1,
+            max_time: 1663644227213092171,
+            times_len: 120,
+            values_len: 25,
+        };
+
+        assert_eq!(BlockHeader::deserialize(&header.serialize()), Ok(header));
+        assert!(BlockHeader::deserialize(&[BLOCK_FORMAT_VERSION + 1; 32]).is_err());
+    }
+
+    #[test]
+    fn column_full_loop() {
+        let values = vec![DataValue::from(true), DataValue::None, DataValue::from(false), DataValue::from(true),
+                          DataValue::from(true), DataValue::from(true), DataValue::from(true)];
+
+        let column = StorageBlock::from_values(values.iter(), &DataType::Bool).unwrap();
+        let bytes = column.serialize();
+        let column = StorageBlock::deserialize_from(&bytes, column.encoding(), values.len());
+        assert_eq!(column.into_values(), values);
+
+        let values = vec![DataValue::from(1.0), DataValue::None, DataValue::from(1.5)];
+        let column = StorageBlock::from_values(values.iter(), &DataType::Float).unwrap();
+        let bytes = column.serialize();
+        let column = StorageBlock::deserialize_from(&bytes, column.encoding(), values.len());
+        assert_eq!(column.into_values(), values);
+
+        let values = vec![DataValue::from("1.2.0"), DataValue::None, DataValue::from("1.2.0"), DataValue::from("1.3.1")];
+        let column = StorageBlock::from_values(values.iter(), &DataType::String).unwrap();
+        let bytes = column.serialize();
+        let column = StorageBlock::deserialize_from(&bytes, column.encoding(), values.len());
+        assert_eq!(column.into_values(), values);
+
+        let values = vec![DataValue::Integer(42), DataValue::None, DataValue::Integer(i64::MAX), DataValue::Integer(-7)];
+        let column = StorageBlock::from_values(values.iter(), &DataType::Integer).unwrap();
+        let bytes = column.serialize();
+        let column = StorageBlock::deserialize_from(&bytes, column.encoding(), values.len());
+        assert_eq!(column.into_values(), values);
+
+        assert!(StorageBlock::from_values(values.iter(), &DataType::Timestamp).is_err());
+    }
+}
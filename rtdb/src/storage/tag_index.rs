@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use fnv::FnvHashMap;
+
+use crate::lang::{Condition, Operator};
+use crate::storage::series::SeriesStorage;
+
+/// Returns the key of the series holding the entries of a measurement with the given tags, e.g.
+/// `cpu,host=a,region=us`. Tags are sorted by key, so that the same tags always map to the same
+/// series regardless of the order they were given in, and a measurement without tags is stored in
+/// a series named after the measurement itself.
+pub fn series_key(measurement: &str, tags: &[(String, String)]) -> String {
+    let mut tags: Vec<_> = tags.iter().collect();
+    tags.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let mut key = measurement.to_owned();
+    for (tag, value) in tags {
+        key.push(',');
+        key.push_str(tag);
+        key.push('=');
+        key.push_str(value);
+    }
+    key
+}
+
+/// Splits a series key into its measurement and tags, see [series_key].
+pub fn parse_series_key(key: &str) -> (&str, Vec<(String, String)>) {
+    let mut parts = key.split(',');
+    let measurement = parts.next().unwrap_or("");
+    let tags = parts
+        .filter_map(|tag| tag.split_once('='))
+        .map(|(tag, value)| (tag.to_owned(), value.to_owned()))
+        .collect();
+
+    (measurement, tags)
+}
+
+/// An inverted index from the tags of each measurement to the keys of the series holding them.
+#[derive(Debug, Default)]
+pub struct TagIndex {
+    measurements: FnvHashMap<String, MeasurementIndex>,
+}
+
+#[derive(Debug, Default)]
+struct MeasurementIndex {
+    /// Keys of all series of the measurement, with or without tags.
+    series: BTreeSet<String>,
+
+    /// Keys of the series holding each value of each tag.
+    tags: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl TagIndex {
+    /// Build the index from all series that have been created. Tags are encoded in the names of
+    /// series, so the index itself isn't persisted.
+    pub fn load() -> TagIndex {
+        let mut index = TagIndex::default();
+        for key in SeriesStorage::list() {
+            index.add(&key);
+        }
+        index
+    }
+
+    pub fn add(&mut self, key: &str) {
+        let (measurement, tags) = parse_series_key(key);
+        let index = self.measurements.entry(measurement.to_owned()).or_default();
+        index.series.insert(key.to_owned());
+
+        for (tag, value) in tags {
+            index.tags.entry(tag).or_default()
+                .entry(value).or_default()
+                .insert(key.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let (measurement, tags) = parse_series_key(key);
+        let index = match self.measurements.get_mut(measurement) {
+            Some(index) => index,
+            None => return,
+        };
+
+        index.series.remove(key);
+        for (tag, value) in tags {
+            if let Some(values) = index.tags.get_mut(&tag) {
+                if let Some(keys) = values.get_mut(&value) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        values.remove(&value);
+                    }
+                }
+                if values.is_empty() {
+                    index.tags.remove(&tag);
+                }
+            }
+        }
+
+        if index.series.is_empty() {
+            self.measurements.remove(measurement);
+        }
+    }
+
+    /// Whether any series of the given measurement has been created.
+    pub fn contains(&self, measurement: &str) -> bool {
+        self.measurements.contains_key(measurement)
+    }
+
+    /// Keys of all series of all measurements, sorted.
+    pub fn all_series(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.measurements.values().flat_map(|index| index.series.iter().cloned()).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Keys of all series of a measurement, sorted.
+    pub fn series(&self, measurement: &str) -> Vec<String> {
+        match self.measurements.get(measurement) {
+            Some(index) => index.series.iter().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Keys of the series of a measurement that may hold rows matching the given condition, sorted.
+    ///
+    /// Only tag equalities that must hold for the whole condition to hold, i.e. those that aren't
+    /// nested under OR or NOT, are used to narrow down the series. Rows of the returned series
+    /// still have to be checked against the full condition.
+    pub fn lookup(&self, measurement: &str, condition: Option<&Condition>) -> Vec<String> {
+        let index = match self.measurements.get(measurement) {
+            Some(index) => index,
+            None => return vec![],
+        };
+
+        let mut predicates = vec![];
+        if let Some(condition) = condition {
+            collect_required_tags(condition, &mut predicates);
+        }
+
+        let mut keys: Vec<&String> = index.series.iter().collect();
+        for (tag, value) in predicates {
+            let matching = index.tags.get(tag).and_then(|values| values.get(value));
+            keys.retain(|key| matching.is_some_and(|matching| matching.contains(*key)));
+        }
+
+        keys.into_iter().cloned().collect()
+    }
+}
+
+fn collect_required_tags<'a, 'b>(condition: &'b Condition<'a>, predicates: &mut Vec<(&'a str, &'b str)>) {
+    match condition {
+        Condition::Tag(predicate) if predicate.operator == Operator::Eq => {
+            predicates.push((predicate.key, predicate.value.as_str()));
+        }
+        Condition::And(a, b) => {
+            collect_required_tags(a, predicates);
+            collect_required_tags(b, predicates);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::{Condition, Operator, TagPredicate};
+    use crate::storage::tag_index::{parse_series_key, series_key, TagIndex};
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn eq<'a>(key: &'a str, value: &'a str) -> Condition<'a> {
+        Condition::Tag(TagPredicate { key, operator: Operator::Eq, value: String::from(value) })
+    }
+
+    #[test]
+    fn it_builds_series_keys() {
+        assert_eq!(series_key("cpu", &[]), "cpu");
+        assert_eq!(series_key("cpu", &tags(&[("region", "us"), ("host", "a")])), "cpu,host=a,region=us");
+
+        assert_eq!(parse_series_key("cpu"), ("cpu", vec![]));
+        assert_eq!(parse_series_key("cpu,host=a,region=us"), ("cpu", tags(&[("host", "a"), ("region", "us")])));
+    }
+
+    #[test]
+    fn it_looks_up_series() {
+        let mut index = TagIndex::default();
+        for key in ["cpu", "cpu,host=a,region=us", "cpu,host=b,region=us", "cpu,host=c,region=eu", "mem,host=a"] {
+            index.add(key);
+        }
+
+        assert_eq!(index.series("cpu").len(), 4);
+        assert_eq!(index.lookup("cpu", None), index.series("cpu"));
+        assert_eq!(index.lookup("cpu", Some(&eq("region", "us"))), vec!["cpu,host=a,region=us", "cpu,host=b,region=us"]);
+
+        let condition = Condition::And(Box::new(eq("region", "us")), Box::new(eq("host", "b")));
+        assert_eq!(index.lookup("cpu", Some(&condition)), vec!["cpu,host=b,region=us"]);
+
+        // series matching either side of an OR can't be ruled out by the index
+        let condition = Condition::Or(Box::new(eq("host", "a")), Box::new(eq("host", "c")));
+        assert_eq!(index.lookup("cpu", Some(&condition)).len(), 4);
+
+        assert!(index.lookup("cpu", Some(&eq("host", "d"))).is_empty());
+        assert!(index.lookup("disk", None).is_empty());
+
+        index.remove("cpu,host=b,region=us");
+        assert_eq!(index.lookup("cpu", Some(&eq("region", "us"))), vec!["cpu,host=a,region=us"]);
+
+        index.remove("mem,host=a");
+        assert!(!index.contains("mem"));
+    }
+}
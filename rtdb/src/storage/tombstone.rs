@@ -0,0 +1,132 @@
+use std::fs::{File, read, rename};
+use std::io;
+use std::io::Write;
+
+use crate::storage::field_index::FieldStorageBlockSummary;
+
+/// The size of a serialized tombstone, in bytes.
+const TOMBSTONE_SIZE: usize = 24;
+
+/// Marks the entries of a field within `[start, end]` as deleted, until the blocks holding them
+/// are rewritten without them by compaction.
+///
+/// A tombstone only applies to blocks that were written before it, i.e. that start before the
+/// data file's length at the time of deletion, so entries written after a deletion are never
+/// hidden by it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tombstone {
+    pub start: i64,
+    pub end: i64,
+
+    /// Length of the data file when the entries were deleted.
+    pub offset: u64,
+}
+
+impl Tombstone {
+    /// Whether the given block may hold entries deleted by this tombstone.
+    #[inline]
+    pub fn applies_to(&self, summary: &FieldStorageBlockSummary) -> bool {
+        summary.offset < self.offset && summary.start_timestamp <= self.end && summary.latest_timestamp >= self.start
+    }
+
+    #[inline]
+    pub fn covers(&self, time: i64) -> bool {
+        self.start <= time && time <= self.end
+    }
+
+    /// Load all tombstones from a given file, or none if it doesn't exist.
+    ///
+    /// Fails if the file can't be read, or holds a partial tombstone. Either way, ignoring the
+    /// tombstones would bring deleted entries back, and lose them for good once tombstones are
+    /// saved again.
+    pub fn load_all(path: &str) -> io::Result<Vec<Tombstone>> {
+        let bytes = match read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        if !bytes.len().is_multiple_of(TOMBSTONE_SIZE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("tombstones file {} is {} bytes long, which isn't a whole number of tombstones", path, bytes.len())));
+        }
+
+        let tombstones = bytes.chunks_exact(TOMBSTONE_SIZE)
+            .map(|bytes| Tombstone {
+                start: i64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+                end: i64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+                offset: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            })
+            .collect();
+        Ok(tombstones)
+    }
+
+    /// Save all tombstones to a given file, replacing its previous contents atomically.
+    pub fn save_all(path: &str, tombstones: &[Tombstone]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(tombstones.len() * TOMBSTONE_SIZE);
+        for tombstone in tombstones {
+            bytes.extend_from_slice(&tombstone.start.to_le_bytes());
+            bytes.extend_from_slice(&tombstone.end.to_le_bytes());
+            bytes.extend_from_slice(&tombstone.offset.to_le_bytes());
+        }
+
+        let tmp_path = format!("{}.tmp", path);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+        rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use crate::storage::data_dir;
+
+    use crate::storage::field_index::FieldStorageBlockSummary;
+    use crate::storage::tombstone::Tombstone;
+
+    #[test]
+    fn it_saves_and_loads() {
+        let _ = fs::remove_dir_all(format!("{}/test_tombstones", data_dir()));
+        fs::create_dir_all(format!("{}/test_tombstones", data_dir())).unwrap();
+
+        let path = &format!("{}/test_tombstones/_value1_tombstones", data_dir());
+        assert!(Tombstone::load_all(path).unwrap().is_empty());
+
+        let tombstones = vec![Tombstone { start: -5, end: 10, offset: 100 }, Tombstone { start: i64::MIN, end: i64::MAX, offset: 0 }];
+        Tombstone::save_all(path, &tombstones).unwrap();
+        assert_eq!(Tombstone::load_all(path).unwrap(), tombstones);
+    }
+
+    #[test]
+    fn it_fails_on_unreadable_tombstones() {
+        let _ = fs::remove_dir_all(format!("{}/test_tombstones_corrupt", data_dir()));
+        fs::create_dir_all(format!("{}/test_tombstones_corrupt", data_dir())).unwrap();
+
+        // a partially written tombstone
+        let path = &format!("{}/test_tombstones_corrupt/_value1_tombstones", data_dir());
+        let tombstones = [Tombstone { start: 1, end: 2, offset: 3 }];
+        Tombstone::save_all(path, &tombstones).unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(Tombstone::load_all(path).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // anything but a missing file is an error, such as the path being a directory
+        let path = &format!("{}/test_tombstones_corrupt", data_dir());
+        assert!(Tombstone::load_all(path).is_err());
+    }
+
+    #[test]
+    fn it_applies_to_older_blocks() {
+        let tombstone = Tombstone { start: 10, end: 20, offset: 100 };
+        let block = |start_timestamp, latest_timestamp, offset| FieldStorageBlockSummary { start_timestamp, latest_timestamp, offset, length: 50 };
+
+        assert!(tombstone.applies_to(&block(0, 10, 50)));
+        assert!(tombstone.applies_to(&block(20, 30, 50)));
+        assert!(!tombstone.applies_to(&block(0, 9, 50)));
+        assert!(!tombstone.applies_to(&block(21, 30, 50)));
+        // blocks written after the deletion, such as new or compacted blocks, are unaffected
+        assert!(!tombstone.applies_to(&block(0, 30, 100)));
+    }
+}
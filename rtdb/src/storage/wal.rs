@@ -0,0 +1,248 @@
+use std::fs::{File, OpenOptions, read, rename};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use rkyv::{AlignedVec, Deserialize};
+
+use crate::storage::data_dir;
+use crate::storage::series::SeriesEntry;
+
+/// Name of the write-ahead log file, within a series' directory. Like all series metadata files,
+/// it begins with an underscore, which can't start a field name.
+const WAL_FILENAME: &str = "_wal";
+
+/// A write-ahead log for a single series.
+///
+/// Field storages buffer entries in memory until a block is filled, so every entry is first
+/// appended to the log, and is replayed from it when the series is loaded again, e.g. after a
+/// crash. Once blocks are flushed to disk, the log is rewritten to hold only the entries that are
+/// still buffered in memory, which keeps it from growing indefinitely.
+///
+/// Each record in the log is an rkyv serialized series entry, prefixed by its length as a u32.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: String,
+    file: File,
+
+    /// Length of the log, up to the end of the last record that was fully appended.
+    len: u64,
+    /// Whether a failed append left part of a record at the end of the log, which couldn't be
+    /// truncated away again, see [WriteAheadLog::append].
+    torn: bool,
+}
+
+/// The entries read from a write-ahead log, see [WriteAheadLog::entries].
+#[derive(Debug, PartialEq)]
+pub struct LogEntries {
+    pub entries: Vec<SeriesEntry>,
+
+    /// Whether the log ends in a record that was only partially written, or is otherwise corrupt.
+    /// Entries appended after it would never be read, so the log has to be checkpointed before
+    /// anything else is appended.
+    pub torn: bool,
+}
+
+impl WriteAheadLog {
+    /// Open the write-ahead log of the given series, creating it if it doesn't exist.
+    pub fn open(series_name: &str) -> io::Result<WriteAheadLog> {
+        let path = format!("{}/{}/{}", data_dir(), series_name, WAL_FILENAME);
+        let file = WriteAheadLog::open_file(&path)?;
+        let len = file.metadata()?.len();
+
+        Ok(WriteAheadLog { path, file, len, torn: false })
+    }
+
+    /// Append an entry to the log.
+    ///
+    /// The entry is written directly to the file without any buffering in between, so it survives
+    /// the process crashing as soon as this returns. It is not synced to disk on every write
+    /// though, as that would limit us to a few hundred inserts per second.
+    ///
+    /// If the entry is only partially written, it's truncated away again, since records appended
+    /// after it would never be read. If that fails too, the log is torn, and every append fails
+    /// until the log is checkpointed.
+    pub fn append(&mut self, entry: &SeriesEntry) -> io::Result<()> {
+        if self.torn {
+            return Err(io::Error::other("the log ends in a partial record, and must be checkpointed first"));
+        }
+
+        let record = WriteAheadLog::serialize(entry);
+        if let Err(err) = self.file.write_all(&record) {
+            self.torn = self.file.set_len(self.len).is_err();
+            return Err(err);
+        }
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Whether the log ends in a partial record, and must be checkpointed before anything else can
+    /// be appended.
+    pub fn is_torn(&self) -> bool {
+        self.torn
+    }
+
+    /// Read all entries in the log, in the order they were written.
+    ///
+    /// A record that was only partially written, e.g. because of a crash, marks the end of the log,
+    /// and is reported as a torn tail.
+    pub fn entries(&self) -> io::Result<LogEntries> {
+        let bytes = read(&self.path)?;
+
+        let mut entries = vec![];
+        let mut offset = 0;
+        while offset + 4 <= bytes.len() {
+            let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            if offset + 4 + len > bytes.len() {
+                break;
+            }
+
+            // archived data needs to be aligned, which records at arbitrary offsets aren't
+            let mut record = AlignedVec::with_capacity(len);
+            record.extend_from_slice(&bytes[offset + 4..offset + 4 + len]);
+
+            match rkyv::check_archived_root::<SeriesEntry>(&record) {
+                Ok(archived) => entries.push(archived.deserialize(&mut rkyv::Infallible).unwrap()),
+                Err(_) => break,
+            }
+            offset += 4 + len;
+        }
+
+        Ok(LogEntries { entries, torn: offset < bytes.len() })
+    }
+
+    /// Replace the contents of the log with the given entries, which should be exactly those that
+    /// haven't been durably flushed to field storage yet.
+    ///
+    /// The new log is written to a temporary file and synced before replacing the old log, so a
+    /// crash at any point leaves either the old or the new log in place.
+    pub fn checkpoint(&mut self, entries: &[SeriesEntry]) -> io::Result<()> {
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut tmp = File::create(&tmp_path)?;
+
+        let mut buffer = vec![];
+        for entry in entries {
+            buffer.extend(WriteAheadLog::serialize(entry));
+        }
+        tmp.write_all(&buffer)?;
+        tmp.sync_all()?;
+
+        rename(&tmp_path, &self.path)?;
+        // the rename itself is only durable once the directory holding the log is synced
+        if let Some(dir) = Path::new(&self.path).parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        self.file = WriteAheadLog::open_file(&self.path)?;
+        self.len = buffer.len() as u64;
+        self.torn = false;
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize(entry: &SeriesEntry) -> Vec<u8> {
+        let bytes = rkyv::to_bytes::<_, 256>(entry).expect("failed to serialize series entry");
+
+        let mut record = Vec::with_capacity(4 + bytes.len());
+        record.extend((bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&bytes);
+        record
+    }
+
+    fn open_file(path: &str) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use crate::storage::data_dir;
+
+    use crate::DataValue;
+    use crate::storage::series::SeriesEntry;
+    use crate::storage::wal::{LogEntries, WriteAheadLog};
+
+    fn entry(time: i64) -> SeriesEntry {
+        SeriesEntry {
+            fields: vec![String::from("field1"), String::from("field2")],
+            values: vec![DataValue::from(time as f64), DataValue::from(true)],
+            time,
+        }
+    }
+
+    #[test]
+    fn it_replays_entries() {
+        let _ = fs::remove_dir_all(format!("{}/test_wal", data_dir()));
+        fs::create_dir_all(format!("{}/test_wal", data_dir())).unwrap();
+
+        let mut wal = WriteAheadLog::open("test_wal").unwrap();
+        for i in 0..10 {
+            wal.append(&entry(i)).unwrap();
+        }
+
+        let wal = WriteAheadLog::open("test_wal").unwrap();
+        assert_eq!(wal.entries().unwrap(), LogEntries { entries: (0..10).map(entry).collect(), torn: false });
+    }
+
+    #[test]
+    fn it_ignores_partial_records() {
+        let _ = fs::remove_dir_all(format!("{}/test_wal_partial", data_dir()));
+        fs::create_dir_all(format!("{}/test_wal_partial", data_dir())).unwrap();
+
+        let mut wal = WriteAheadLog::open("test_wal_partial").unwrap();
+        wal.append(&entry(1)).unwrap();
+
+        // simulate a crash halfway through writing a record
+        let mut file = OpenOptions::new().append(true).open(format!("{}/test_wal_partial/_wal", data_dir())).unwrap();
+        file.write_all(&[100, 0, 0, 0, 1, 2, 3]).unwrap();
+
+        assert_eq!(wal.entries().unwrap(), LogEntries { entries: vec![entry(1)], torn: true });
+
+        // checkpointing drops the torn record, so that entries appended afterwards can be read
+        wal.checkpoint(&[entry(1)]).unwrap();
+        wal.append(&entry(2)).unwrap();
+        assert_eq!(wal.entries().unwrap(), LogEntries { entries: vec![entry(1), entry(2)], torn: false });
+    }
+
+    #[test]
+    fn it_requires_a_checkpoint_after_a_torn_append() {
+        let _ = fs::remove_dir_all(format!("{}/test_wal_torn", data_dir()));
+        fs::create_dir_all(format!("{}/test_wal_torn", data_dir())).unwrap();
+
+        let mut wal = WriteAheadLog::open("test_wal_torn").unwrap();
+        wal.append(&entry(1)).unwrap();
+
+        // a file that can be neither written to nor truncated
+        wal.file = File::open(format!("{}/test_wal_torn/_wal", data_dir())).unwrap();
+        assert!(wal.append(&entry(2)).is_err());
+        assert!(wal.is_torn());
+
+        // nothing can be appended after the torn record, until the log is checkpointed
+        wal.file = WriteAheadLog::open_file(&wal.path).unwrap();
+        assert!(wal.append(&entry(3)).is_err());
+        wal.checkpoint(&[entry(1)]).unwrap();
+        wal.append(&entry(3)).unwrap();
+        assert_eq!(wal.entries().unwrap(), LogEntries { entries: vec![entry(1), entry(3)], torn: false });
+    }
+
+    #[test]
+    fn it_checkpoints() {
+        let _ = fs::remove_dir_all(format!("{}/test_wal_checkpoint", data_dir()));
+        fs::create_dir_all(format!("{}/test_wal_checkpoint", data_dir())).unwrap();
+
+        let mut wal = WriteAheadLog::open("test_wal_checkpoint").unwrap();
+        for i in 0..10 {
+            wal.append(&entry(i)).unwrap();
+        }
+
+        wal.checkpoint(&[entry(8), entry(9)]).unwrap();
+        wal.append(&entry(10)).unwrap();
+        assert_eq!(wal.entries().unwrap().entries, vec![entry(8), entry(9), entry(10)]);
+    }
+}
@@ -1,15 +1,40 @@
 //! The users module defines a set of structs that describe users who have permission to interact
 //! with the database, as well as functionality for managing and authenticating users.
 
-use std::fs::{create_dir, File};
+use std::fs::{create_dir_all, File};
+use std::io;
 use std::io::{Read, Write};
+use log::error;
 use nom::AsBytes;
+#[cfg(test)]
+use once_cell::sync::Lazy;
 
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 
 /// Default file system directory for saving user info.
-const USERS_SAVE_PATH: &'static str = "./users";
+#[cfg(not(test))]
+const USERS_SAVE_PATH: &str = "./users";
+
+/// Tests save users in a temporary directory of their own, so that test runs don't write user
+/// files into the working tree.
+#[cfg(test)]
+static TEST_USERS_SAVE_PATH: Lazy<String> = Lazy::new(|| {
+    let dir = std::env::temp_dir().join(format!("rtdb-test-users-{}", std::process::id()));
+    dir.to_string_lossy().into_owned()
+});
+
+/// Return the directory that user info is saved in.
+#[cfg(not(test))]
+fn users_dir() -> &'static str {
+    USERS_SAVE_PATH
+}
+
+/// Return the directory that user info is saved in.
+#[cfg(test)]
+fn users_dir() -> &'static str {
+    &TEST_USERS_SAVE_PATH
+}
 
 /// Authentication methods supported for user accounts.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -29,125 +54,178 @@ pub struct User {
 }
 
 impl User {
-    /// Creates a new user and saves it to disk.
-    pub fn create(name: &str, password: Option<&str>) -> User {
+    /// Creates a new user and saves it to disk. Names may only contain ASCII letters, digits and
+    /// underscores, since they're used as file names.
+    pub fn create(name: &str, password: Option<&str>) -> Result<User, String> {
+        if !is_valid_name(name) {
+            return Err(format!("Invalid user name: {}", name));
+        }
+
         let auth_method = match password {
             None => Authentication::None,
             Some(password) => Authentication::Password(hash_sha256(password)),
         };
 
         let user = User { name: name.to_owned(), auth_method };
-        user.save();
-        user
+        match user.save() {
+            Ok(()) => Ok(user),
+            Err(err) => Err(format!("Failed to save user {}: {}", name, err)),
+        }
     }
 
     /// Attempt to authenticate against an existing user.
     /// The password must be passed in already hashed with SHA256.
-    pub fn authenticate<'a>(name: &'a str, password: Option<&str>) -> Result<User, &'a str> {
-        match User::load(name) {
-            None => { Err("User does not exist") }
-            Some(user) => {
-                match &user.auth_method {
-                    Authentication::None => Ok(user),
-                    Authentication::Password(user_pwd) => {
-                        if password.is_none() {
-                            return Err("Missing password");
-                        }
-
-                        match user_pwd == password.unwrap() {
-                            true => Ok(user),
-                            false => Err("Wrong user or password")
-                        }
-                    }
-                }
-            }
+    ///
+    /// Every failure has the same message, so clients can't tell which users exist.
+    pub fn authenticate(name: &str, password: Option<&str>) -> Result<User, &'static str> {
+        const FAILED: &str = "Wrong user or password";
+        if !is_valid_name(name) {
+            return Err(FAILED);
+        }
+
+        let user = User::load(name).ok_or(FAILED)?;
+        match &user.auth_method {
+            Authentication::None => Ok(user),
+            Authentication::Password(user_pwd) => match password {
+                Some(password) if constant_time_eq(user_pwd.as_bytes(), password.as_bytes()) => Ok(user),
+                _ => Err(FAILED),
+            },
         }
     }
 
     /// Save a user to disk, overwriting any previous user data.
     /// TODO: maybe there should be a separate alter. Name shouldn't be overwritable to same file
     /// TODO: maybe we shouldn't be storing each user like this, as a separate file? idk
-    fn save(&self) {
-        create_dir(USERS_SAVE_PATH);
+    fn save(&self) -> io::Result<()> {
+        create_dir_all(users_dir())?;
 
-        let path = format!("{}/{}.txt", USERS_SAVE_PATH, self.name);
-        let file = File::create(path);
+        let path = format!("{}/{}.txt", users_dir(), self.name);
+        let data = serde_yaml::to_string(&self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        match file {
-            Ok(mut f) => {
-                let data = serde_yaml::to_string(&self).unwrap();
-                f.write_all(data.as_bytes());
-            }
-            Err(err) => {
-                dbg!(err);
-            }
-        };
+        File::create(path)?.write_all(data.as_bytes())
     }
 
-    /// Attempt to load an existing user from disk, based on name.
+    /// Attempt to load an existing user from disk, based on name. Returns None if the user doesn't
+    /// exist, or its file can't be read.
     fn load(name: &str) -> Option<User> {
-        let path = format!("{}/{}.txt", USERS_SAVE_PATH, name);
-        let file = File::open(path);
-
-        match file {
-            Ok(mut f) => {
-                let mut data = String::new();
-                f.read_to_string(&mut data).unwrap();
-
-                let user: User = serde_yaml::from_str(&data).unwrap();
-                Some(user)
+        let path = format!("{}/{}.txt", users_dir(), name);
+        let mut data = String::new();
+        if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_string(&mut data)) {
+            if err.kind() != io::ErrorKind::NotFound {
+                error!("failed to read user file {}: {}", path, err);
             }
+            return None;
+        }
+
+        match serde_yaml::from_str(&data) {
+            Ok(user) => Some(user),
             Err(err) => {
-                dbg!(err);
+                error!("failed to parse user file {}: {}", path, err);
                 None
             }
         }
     }
 }
 
+/// Whether a user name is safe to use as a file name, i.e. it's made up of only ASCII letters,
+/// digits and underscores.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+
+/// Compares two byte strings in time that only depends on their lengths, so that comparing a
+/// password hash doesn't reveal how much of it was guessed right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
 
 /// Hashes a given string using SHA256, and returns the result as a hex string.
-fn hash_sha256(str: &str) -> String {
+pub fn hash_sha256(str: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(str.as_bytes());
     let hash = hasher.finalize();
 
-    hex::encode(hash.as_bytes().to_vec())
+    hex::encode(hash.as_bytes())
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::users::{Authentication, hash_sha256, User};
+    use std::fs;
+    use std::path::Path;
+
+    use crate::users::{Authentication, constant_time_eq, hash_sha256, User, users_dir};
 
     #[test]
     fn creates_users() {
-        User::create("andres", None);
-        User::create("brendan", Some("mysecurepassword"));
+        User::create("create_none", None).unwrap();
+        User::create("create_password", Some("mysecurepassword")).unwrap();
+
+        assert!(Path::new(&format!("{}/create_none.txt", users_dir())).is_file());
+        assert!(Path::new(&format!("{}/create_password.txt", users_dir())).is_file());
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!(User::create("../escaped", None).is_err());
+        assert!(User::create("", None).is_err());
+        assert!(!Path::new(&format!("{}/../escaped.txt", users_dir())).exists());
+
+        User::create("invalid_none", None).unwrap();
+        assert!(User::authenticate("../users/invalid_none", None).is_err());
+        assert!(User::authenticate("invalid_none/", None).is_err());
+    }
+
+    #[test]
+    fn rejects_unreadable_users() {
+        fs::create_dir_all(users_dir()).unwrap();
+        fs::write(format!("{}/corrupt_user.txt", users_dir()), "not: [a user").unwrap();
+
+        assert!(User::load("corrupt_user").is_none());
+        assert!(User::authenticate("corrupt_user", None).is_err());
     }
 
     #[test]
     fn loads_users() {
-        let user = User::load("andres").unwrap();
-        assert_eq!(user.name, "andres");
+        User::create("load_none", None).unwrap();
+        User::create("load_password", Some("mysecurepassword")).unwrap();
+
+        let user = User::load("load_none").unwrap();
+        assert_eq!(user.name, "load_none");
         assert_eq!(user.auth_method, Authentication::None);
 
-        let user = User::load("brendan").unwrap();
-        assert_eq!(user.name, "brendan");
+        let user = User::load("load_password").unwrap();
+        assert_eq!(user.name, "load_password");
         assert_eq!(user.auth_method, Authentication::Password(hash_sha256("mysecurepassword")));
     }
 
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
     #[test]
     fn authenticates_users() {
-        let user = User::authenticate("andres", None).unwrap();
-        assert_eq!(user.name, "andres");
+        User::create("auth_none", None).unwrap();
+        User::create("auth_password", Some("mysecurepassword")).unwrap();
+
+        let user = User::authenticate("auth_none", None).unwrap();
+        assert_eq!(user.name, "auth_none");
         assert_eq!(user.auth_method, Authentication::None);
 
-        let user = User::authenticate("brendan", None);
+        let user = User::authenticate("auth_password", None);
         assert!(user.is_err());
 
-        let user = User::authenticate("brendan", Some(&hash_sha256("mysecurepassword"))).unwrap();
-        assert_eq!(user.name, "brendan");
+        // unknown users and wrong passwords are indistinguishable
+        assert_eq!(User::authenticate("auth_password", Some(&hash_sha256("wrong"))).err(), User::authenticate("nobody", None).err());
+
+        let user = User::authenticate("auth_password", Some(&hash_sha256("mysecurepassword"))).unwrap();
+        assert_eq!(user.name, "auth_password");
         assert_eq!(user.auth_method, Authentication::Password(hash_sha256("mysecurepassword")));
     }
 }
\ No newline at end of file
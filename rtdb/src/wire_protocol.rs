@@ -1,42 +1,61 @@
 // TODO: a lot of this code, particularly the parsing, will very easily cause panics if
 //  the input is not perfect.
 
-use std::io::{Write};
-use byteorder::ReadBytesExt;
-use tokio::net::TcpStream;
-use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use std::io;
+use std::io::Read;
+use byteorder::{BigEndian, ReadBytesExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::execution::{ClientQueryResult, ExecutionResult, InsertionResult};
+use crate::execution::{ClientQueryResult, CommandResult, ExecutionResult, InsertionResult};
+use crate::wire_protocol::command::{build_command_result, parse_command_result};
+use crate::wire_protocol::error::{ErrorResult, parse_error_result};
 use crate::wire_protocol::insert::{build_insert_result, parse_insert_result};
 use crate::wire_protocol::query::{build_query_result, ByteReader, parse_query_result};
 
 pub mod query;
 pub mod insert;
+pub mod error;
+pub mod auth;
+pub mod command;
 
-#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 #[repr(u8)]
 pub enum DataType {
     Float = 0,
     Bool = 1,
     Timestamp = 2,
+    String = 3,
+    Integer = 4,
 }
 
 impl std::convert::TryFrom<u8> for DataType {
-    type Error = ();
+    type Error = String;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(DataType::Float),
             1 => Ok(DataType::Bool),
-            e => {
-                dbg!(e);
-                Err(())
-            }
+            2 => Ok(DataType::Timestamp),
+            3 => Ok(DataType::String),
+            4 => Ok(DataType::Integer),
+            e => Err(format!("unknown data type: {}", e)),
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq, serde::Serialize)]
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Float => write!(f, "float"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Timestamp => write!(f, "timestamp"),
+            DataType::String => write!(f, "string"),
+            DataType::Integer => write!(f, "integer"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
 pub struct FieldDescription {
     pub name: String,
     pub data_type: DataType,
@@ -45,45 +64,108 @@ pub struct FieldDescription {
 /// Serialize an execution result into a byte vector that is ready to be sent back to the client,
 /// using a format custom to our database.
 /// TODO: the function name is perhaps a little on the nose (or well, verbose)
-pub async fn build_response<T>(result: &ExecutionResult, out: &mut T)
+pub async fn build_response<T>(result: &ExecutionResult, out: &mut T) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send
 {
     match result {
         ExecutionResult::Query(query_result) => build_query_result(query_result, out).await,
         ExecutionResult::Insert(insert_result) => build_insert_result(insert_result, out).await,
-    };
+        ExecutionResult::Command(command_result) => build_command_result(command_result, out).await,
+    }
 }
 
 // TODO: move to client library and rename
 pub enum ClientExecutionResult {
     Query(ClientQueryResult),
     Insert(InsertionResult),
+    Command(CommandResult),
+    Error(ErrorResult),
+    Authenticated,
 }
 
 // TODO: move to client library
-pub fn parse_result(buffer: &mut Vec<u8>) -> ClientExecutionResult {
-    let mut cursor = ByteReader::new(&buffer);
-    match cursor.read_u8().unwrap() {
+/// Parse a response sent by the server. Fails if the response is truncated or malformed, or if it
+/// has a result type this version of the protocol doesn't know about.
+pub fn parse_result(buffer: &mut [u8]) -> io::Result<ClientExecutionResult> {
+    let mut cursor = ByteReader::new(buffer);
+    let result = match cursor.read_u8()? {
         1 => {
-            let result = parse_query_result(&mut cursor);
+            let result = parse_query_result(&mut cursor)?;
             ClientExecutionResult::Query(result)
         }
         2 => {
-            let result = parse_insert_result(&mut cursor);
+            let result = parse_insert_result(&mut cursor)?;
             ClientExecutionResult::Insert(result)
         }
-        _ => panic!("Not supported")
-    }
+        3 => {
+            let result = parse_error_result(&mut cursor)?;
+            ClientExecutionResult::Error(result)
+        }
+        4 => ClientExecutionResult::Authenticated,
+        5 => {
+            let result = parse_command_result(&mut cursor)?;
+            ClientExecutionResult::Command(result)
+        }
+        tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown result type: {}", tag))),
+    };
+
+    Ok(result)
 }
 
-/// Pushes a string onto a buffer, prefixing it with the string's length as a u16
+/// Pushes a string onto a buffer, prefixing it with the string's length as a u16. Fails without
+/// writing anything if the string is longer than a u16 can describe.
 #[inline]
-async fn push_str<T>(buffer: &mut T, str: &str)
+async fn push_str<T>(buffer: &mut T, str: &str) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send
 {
-    let len = str.len() as u16;
-    buffer.write(&len.to_be_bytes()).await;
-    buffer.write(&str.as_bytes()).await;
-}
\ No newline at end of file
+    let len = u16::try_from(str.len()).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("string of {} bytes is too long to be sent, the limit is {}", str.len(), u16::MAX),
+    ))?;
+    buffer.write_all(&len.to_be_bytes()).await?;
+    buffer.write_all(str.as_bytes()).await
+}
+
+/// Consumes a string from a buffer, prefixed with the string's length as a u16
+#[inline]
+fn read_str(buffer: &mut ByteReader) -> io::Result<String> {
+    let len = buffer.read_u16::<BigEndian>()?;
+    let mut bytes = vec![0; len as usize];
+    buffer.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::wire_protocol::{parse_result, push_str};
+
+    #[test]
+    fn rejects_unknown_result_types() {
+        let error = parse_result(&mut [42]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_results() {
+        // an error result, whose message is cut off
+        let error = parse_result(&mut [3, 0, 2, 0, 9, b'n', b'o']).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn rejects_long_strings() {
+        let mut buffer = vec![];
+        push_str(&mut buffer, &"a".repeat(u16::MAX as usize)).await.unwrap();
+        assert_eq!(buffer.len(), 2 + u16::MAX as usize);
+
+        let mut buffer = vec![];
+        let error = push_str(&mut buffer, &"a".repeat(u16::MAX as usize + 1)).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(buffer.is_empty());
+    }
+}
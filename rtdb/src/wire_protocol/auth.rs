@@ -0,0 +1,27 @@
+use std::io;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Write the result of a successful authentication to a buffer. Failed authentication is reported
+/// as an error result instead, so a success carries no data beyond its tag:
+///
+/// [4]
+/// u8
+pub async fn build_auth_result<T>(out: &mut T) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+{
+    out.write_all(&[4]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wire_protocol::auth::build_auth_result;
+
+    #[tokio::test]
+    async fn builds_auth_result() {
+        let mut buf = vec![];
+        build_auth_result(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![4]);
+    }
+}
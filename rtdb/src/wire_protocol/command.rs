@@ -0,0 +1,43 @@
+use std::io;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::execution::CommandResult;
+use crate::wire_protocol::{push_str, read_str};
+use crate::wire_protocol::query::ByteReader;
+
+/// Write the result of a command to a buffer, formatted as:
+///
+/// [5] [MESSAGE]
+/// u8  PStr(u16)
+pub async fn build_command_result<T>(result: &CommandResult, out: &mut T) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+{
+    out.write_all(&[5]).await?;
+    push_str(out, &result.message).await
+}
+
+// TODO: move to client
+pub fn parse_command_result(buffer: &mut ByteReader) -> io::Result<CommandResult> {
+    Ok(CommandResult { message: read_str(buffer)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution::CommandResult;
+    use crate::wire_protocol::command::{build_command_result, parse_command_result};
+    use crate::wire_protocol::query::ByteReader;
+
+    #[tokio::test]
+    async fn builds_and_parses_command_result() {
+        let result = CommandResult { message: String::from("done") };
+
+        let mut buf = vec![];
+        build_command_result(&result, &mut buf).await.unwrap();
+        assert_eq!(buf, [5, 0, 4, b'd', b'o', b'n', b'e']);
+
+        let mut cursor = ByteReader::new(&buf[1..]);
+        assert_eq!(parse_command_result(&mut cursor).unwrap(), result);
+    }
+}
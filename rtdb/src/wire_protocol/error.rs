@@ -0,0 +1,109 @@
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::execution::ExecutionError;
+use crate::wire_protocol::{push_str, read_str};
+use crate::wire_protocol::query::ByteReader;
+
+/// Identifies the kind of error that occurred, so clients can handle errors without having to
+/// parse their messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// The command couldn't be parsed.
+    InvalidQuery = 1,
+    /// The queried series doesn't exist.
+    SeriesNotFound = 2,
+    /// The given credentials were rejected.
+    AuthenticationFailed = 3,
+    /// The connection must authenticate before issuing queries or inserts.
+    Unauthenticated = 4,
+    /// The field doesn't exist in the series.
+    FieldNotFound = 5,
+    /// Data couldn't be read from or written to disk.
+    StorageError = 6,
+    /// The request doesn't follow the protocol, e.g. it has an unknown action. The connection is
+    /// closed after the error is sent.
+    ProtocolError = 7,
+    /// The result can't be encoded in the protocol, e.g. because a string in it is too long.
+    ResultTooLarge = 8,
+    /// An error code this version of the protocol doesn't know about.
+    Unknown = u16::MAX,
+}
+
+impl From<u16> for ErrorCode {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => ErrorCode::InvalidQuery,
+            2 => ErrorCode::SeriesNotFound,
+            3 => ErrorCode::AuthenticationFailed,
+            4 => ErrorCode::Unauthenticated,
+            5 => ErrorCode::FieldNotFound,
+            6 => ErrorCode::StorageError,
+            7 => ErrorCode::ProtocolError,
+            8 => ErrorCode::ResultTooLarge,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// An error to be reported back to the client, in place of a query or insert result.
+#[derive(Debug, PartialEq)]
+pub struct ErrorResult {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl From<ExecutionError> for ErrorResult {
+    fn from(error: ExecutionError) -> Self {
+        let code = match error {
+            ExecutionError::SeriesNotFound(_) => ErrorCode::SeriesNotFound,
+            ExecutionError::FieldNotFound(_) => ErrorCode::FieldNotFound,
+            ExecutionError::InvalidQuery(_) => ErrorCode::InvalidQuery,
+            ExecutionError::Storage(_) => ErrorCode::StorageError,
+        };
+
+        ErrorResult { code, message: error.to_string() }
+    }
+}
+
+/// Write an error result to a buffer, formatted as:
+///
+/// [3] [CODE] [MESSAGE]
+/// u8  u16    PStr(u16)
+pub async fn build_error_result<T>(error: &ErrorResult, out: &mut T) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send
+{
+    out.write_all(&[3]).await?;
+    out.write_all(&(error.code as u16).to_be_bytes()).await?;
+    push_str(out, &error.message).await
+}
+
+// TODO: move to client
+pub fn parse_error_result(buffer: &mut ByteReader) -> io::Result<ErrorResult> {
+    let code = ErrorCode::from(buffer.read_u16::<BigEndian>()?);
+    let message = read_str(buffer)?;
+
+    Ok(ErrorResult { code, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wire_protocol::error::{build_error_result, ErrorCode, ErrorResult, parse_error_result};
+    use crate::wire_protocol::query::ByteReader;
+
+    #[tokio::test]
+    async fn builds_and_parses_error_result() {
+        let error = ErrorResult { code: ErrorCode::SeriesNotFound, message: String::from("not found") };
+
+        let mut buf = vec![];
+        build_error_result(&error, &mut buf).await.unwrap();
+        assert_eq!(buf[..5], [3, 0, 2, 0, 9]);
+
+        let mut cursor = ByteReader::new(&buf[1..]);
+        assert_eq!(parse_error_result(&mut cursor).unwrap(), error);
+    }
+}
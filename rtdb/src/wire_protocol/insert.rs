@@ -1,35 +1,74 @@
-use byteorder::ReadBytesExt;
-use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
-use tokio::net::TcpStream;
+use std::io;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use crate::execution::InsertionResult;
+use crate::wire_protocol::{push_str, read_str};
 use crate::wire_protocol::query::ByteReader;
 
-/// Returns a byte slice containing the result of an insertion.
-/// TODO: on error, return a string describing the error
-pub async fn build_insert_result<T>(result: &InsertionResult, out: &mut T)
+/// Write the result of an insertion to a buffer, formatted as:
+///
+/// [2] [SUCCESS] [ERROR]    [ROWS INSERTED] [FAILED ROW COUNT] [FAILED ROW...]
+/// u8  u8        PStr(u16)  u32             u32                u32
+///
+/// An empty error string means no error occurred. Failed rows are numbered from 1.
+pub async fn build_insert_result<T>(result: &InsertionResult, out: &mut T) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send
 {
 // TODO: define enum or constants, instead of magic numbers
-    out.write(&vec![2, result.success as u8]).await;
+    out.write_all(&[2, result.success as u8]).await?;
+    push_str(out, result.error.as_deref().unwrap_or("")).await?;
+    out.write_all(&result.rows_inserted.to_be_bytes()).await?;
+
+    let mut failed_rows = Vec::with_capacity(4 + result.failed_rows.len() * 4);
+    failed_rows.extend((result.failed_rows.len() as u32).to_be_bytes());
+    for row in &result.failed_rows {
+        failed_rows.extend(row.to_be_bytes());
+    }
+    out.write_all(&failed_rows).await
 }
 
 // TODO: move to client
-pub fn parse_insert_result(buffer: &mut ByteReader) -> InsertionResult {
-    let success = buffer.read_u8().unwrap() == 1;
-    InsertionResult { success }
+pub fn parse_insert_result(buffer: &mut ByteReader) -> io::Result<InsertionResult> {
+    let success = buffer.read_u8()? == 1;
+    let error = Some(read_str(buffer)?).filter(|error| !error.is_empty());
+    let rows_inserted = buffer.read_u32::<BigEndian>()?;
+
+    let count = buffer.read_u32::<BigEndian>()?;
+    let mut failed_rows = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        failed_rows.push(buffer.read_u32::<BigEndian>()?);
+    }
+
+    Ok(InsertionResult { success, error, rows_inserted, failed_rows })
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio::io::{AsyncWriteExt, BufWriter};
+    use std::io::Cursor;
+
     use crate::execution::InsertionResult;
-    use crate::wire_protocol::insert::build_insert_result;
+    use crate::wire_protocol::insert::{build_insert_result, parse_insert_result};
 
     #[tokio::test]
     async fn builds_insert_result() {
         let mut buf = vec![];
-        build_insert_result(&InsertionResult { success: false }, &mut buf).await;
-        assert_eq!(buf, vec![2, 0]);
+        build_insert_result(&InsertionResult { success: false, error: None, rows_inserted: 3, failed_rows: vec![] }, &mut buf).await.unwrap();
+        assert_eq!(buf, vec![2, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0]);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn parses_insert_result_with_error() {
+        let mut buf = vec![];
+        let result = InsertionResult { success: false, error: Some("row 2: bad value".to_owned()), rows_inserted: 1, failed_rows: vec![2, 3] };
+        build_insert_result(&result, &mut buf).await.unwrap();
+
+        let mut cursor = Cursor::new(&buf[1..]);
+        let parsed = parse_insert_result(&mut cursor).unwrap();
+        assert!(!parsed.success);
+        assert_eq!(parsed.error, Some("row 2: bad value".to_owned()));
+        assert_eq!(parsed.rows_inserted, 1);
+        assert_eq!(parsed.failed_rows, vec![2, 3]);
+    }
+}
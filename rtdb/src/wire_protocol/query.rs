@@ -1,13 +1,12 @@
 use std::io;
-use std::io::{Read, Write};
-use std::str::from_utf8;
+use std::io::Read;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::{ClientRecordCollection, DataRow, DataValue};
 use crate::execution::{ClientQueryResult, QueryResult};
-use crate::wire_protocol::{DataType, FieldDescription, push_str};
+use crate::wire_protocol::{DataType, FieldDescription, push_str, read_str};
 
 pub type ByteReader<'a> = io::Cursor<&'a [u8]>;
 
@@ -20,44 +19,46 @@ pub type ByteReader<'a> = io::Cursor<&'a [u8]>;
 //  the client stuff to a new client lib.
 
 /// Append multiple field descriptions to a buffer, prefixed by the number of fields being printed
-/// as a u8.
+/// as a u8. Fails without writing anything if there are more fields than a u8 can describe.
 ///
 /// A field description is formatted as:
 /// [DATA_TYPE] [NAME]
 /// u8          PStr(u8)
 #[inline]
-async fn write_field_descriptions<T>(mut buffer: T, fields: &Vec<FieldDescription>)
+async fn write_field_descriptions<T>(mut buffer: T, fields: &Vec<FieldDescription>) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send
 {
-    buffer.write(&(fields.len() as u8).to_be_bytes()).await;
+    let len = u8::try_from(fields.len()).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} fields are too many to be sent, the limit is {}", fields.len(), u8::MAX),
+    ))?;
+    buffer.write_all(&len.to_be_bytes()).await?;
     for field in fields {
-        let t = (&field.data_type).clone() as u8;
-        buffer.write(&t.to_be_bytes()).await;
-        push_str(&mut buffer, &field.name).await;
+        let t = field.data_type.clone() as u8;
+        buffer.write_all(&t.to_be_bytes()).await?;
+        push_str(&mut buffer, &field.name).await?;
     }
+    Ok(())
 }
 
 #[inline]
-fn parse_field_description(buffer: &mut ByteReader) -> Result<(DataType, String), ()> {
-    let data_type = DataType::try_from(buffer.read_u8().unwrap()).unwrap();
-    let len = buffer.read_u16::<BigEndian>().unwrap();
+fn parse_field_description(buffer: &mut ByteReader) -> io::Result<(DataType, String)> {
+    let data_type = buffer.read_u8()?;
+    let data_type = DataType::try_from(data_type).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let name = read_str(buffer)?;
 
-    let mut name_buf = vec![0; len as usize];
-    buffer.read_exact(&mut name_buf);
-    let name = from_utf8(&name_buf).unwrap();
-
-    Ok((data_type, name.to_owned()))
+    Ok((data_type, name))
 }
 
 
 #[inline]
-fn parse_field_descriptions(buffer: &mut ByteReader) -> Result<Vec<FieldDescription>, ()> {
-    let n = buffer.read_u8().unwrap();
+fn parse_field_descriptions(buffer: &mut ByteReader) -> io::Result<Vec<FieldDescription>> {
+    let n = buffer.read_u8()?;
 
     let mut fields = Vec::with_capacity(n as usize);
     for _ in 0..n {
-        let (data_type, name) = parse_field_description(buffer).unwrap();
+        let (data_type, name) = parse_field_description(buffer)?;
         let f = FieldDescription { name, data_type };
         fields.push(f);
     }
@@ -65,81 +66,121 @@ fn parse_field_descriptions(buffer: &mut ByteReader) -> Result<Vec<FieldDescript
     Ok(fields)
 }
 
-// TODO: generalizing this might be a pain... but oh well
-// TODO: figure out what to do about null values
-// #[inline]
-// fn write_data_row(buffer: &mut Vec<u8>, row: &DataRow, _fields: &Vec<FieldDescription>) {
-//     let time = row.time;
-//     buffer.extend(time.to_be_bytes());
-//
-//     for (_i, entry) in row.elements.iter().enumerate() {
-//         buffer.extend(&entry.to_be_bytes()); // TODO: handle None properly
-//     };
-// }
+/// Append a data row, given as its timestamp followed by a value for each field, to a buffer,
+/// formatted as:
+///
+/// [TIME] [PRESENT]               [VALUE...]
+/// i64    u8[ceil(fields / 8)]    ...
+///
+/// where bit `i % 8` of byte `i / 8` of PRESENT is set if the row has a value for field `i`. Only
+/// the values that are present follow, each serialized according to its field's data type.
+#[inline]
+fn write_data_row(buffer: &mut Vec<u8>, row: &[DataValue]) {
+    buffer.extend(row[0].to_be_bytes());
+
+    let values = &row[1..];
+    let mut present = vec![0u8; values.len().div_ceil(8)];
+    for (i, value) in values.iter().enumerate() {
+        if !matches!(value, DataValue::None) {
+            present[i / 8] |= 1 << (i % 8);
+        }
+    }
+    buffer.extend(present);
 
+    for value in values {
+        buffer.extend(value.to_be_bytes());
+    }
+}
+
+/// Consume a data row written by [write_data_row]. Fields without a value in the row are
+/// DataValue::None.
 #[inline]
-fn parse_data_row(buffer: &mut ByteReader, fields: &Vec<FieldDescription>) -> DataRow {
+fn parse_data_row(buffer: &mut ByteReader, fields: &[FieldDescription]) -> io::Result<DataRow> {
     let mut values = Vec::with_capacity(fields.len()); // TODO: don't alloc per row...
 
-    dbg!(&buffer);
+    let time = buffer.read_i64::<BigEndian>()?;
+    let mut present = vec![0; fields.len().div_ceil(8)];
+    buffer.read_exact(&mut present)?;
+
+    for (i, field) in fields.iter().enumerate() {
+        if present[i / 8] & (1 << (i % 8)) == 0 {
+            values.push(DataValue::None);
+            continue;
+        }
 
-    let time = buffer.read_i64::<BigEndian>().unwrap();
-    for field in fields {
         let value = match field.data_type {
-            // TODO: handle "null" values
-            DataType::Timestamp => DataValue::Timestamp(buffer.read_i64::<BigEndian>().unwrap()),
-            DataType::Float => DataValue::from(buffer.read_f64::<BigEndian>().unwrap()),
-            DataType::Bool => DataValue::Bool(buffer.read_u8().unwrap() == 1),
+            DataType::Timestamp => DataValue::Timestamp(buffer.read_i64::<BigEndian>()?),
+            DataType::Float => DataValue::from(buffer.read_f64::<BigEndian>()?),
+            DataType::Bool => DataValue::Bool(buffer.read_u8()? == 1),
+            DataType::Integer => DataValue::Integer(buffer.read_i64::<BigEndian>()?),
+            DataType::String => {
+                let len = buffer.read_u32::<BigEndian>()?;
+                let mut bytes = vec![0; len as usize];
+                buffer.read_exact(&mut bytes)?;
+                DataValue::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
         };
         values.push(value);
     }
 
-    DataRow { time, elements: values }
+    Ok(DataRow { time, elements: values })
 }
 
-pub async fn build_query_result<T>(result: &QueryResult, mut out: &mut T)
+/// Write a query result to a buffer, formatted as:
+///
+/// [1] [FIELDS]              [ROW COUNT] [ROW...]
+/// u8  FieldDescription[u8]  u32         see [write_data_row]
+pub async fn build_query_result<T>(result: &QueryResult, mut out: &mut T) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send
 {
     let fields = &result.records.fields;
 
-    out.write(&1u8.to_be_bytes()).await; // TODO: replace with constant?
-    write_field_descriptions(&mut out, &fields).await;
+    out.write_all(&1u8.to_be_bytes()).await?; // TODO: replace with constant?
+    write_field_descriptions(&mut out, fields).await?;
 
-    out.write(&(result.count as u32).to_be_bytes()).await;
-    for elem in &result.records.elements {
-        out.write(&elem.to_be_bytes()).await; // TODO: handle None properly
+    out.write_all(&(result.count as u32).to_be_bytes()).await?;
+    let mut row = Vec::with_capacity(64);
+    for elements in result.records.elements.chunks_exact(fields.len() + 1) {
+        row.clear();
+        write_data_row(&mut row, elements);
+        out.write_all(&row).await?;
     }
+    Ok(())
 }
 
-pub fn parse_query_result(mut buffer: &mut ByteReader) -> ClientQueryResult {
-    let fields = parse_field_descriptions(&mut buffer).unwrap();
+pub fn parse_query_result(buffer: &mut ByteReader) -> io::Result<ClientQueryResult> {
+    let fields = parse_field_descriptions(buffer)?;
     let field_count = fields.len();
 
-    let count = buffer.read_u32::<BigEndian>().unwrap();
+    let count = buffer.read_u32::<BigEndian>()?;
     let mut rows = Vec::with_capacity(field_count * count as usize);
     for _ in 0..count {
-        rows.push(parse_data_row(&mut buffer, &fields));
+        rows.push(parse_data_row(buffer, &fields)?);
     }
 
-    ClientQueryResult {
+    Ok(ClientQueryResult {
         count: count as usize,
         records: ClientRecordCollection {
             fields,
             rows,
         },
-    }
+    })
 }
 
 /// Approximate a buffer size to reduce allocations, which are the biggest cost in serializing and
 /// deserializing query results.
 #[inline]
-fn estimate_mem(fields: &Vec<FieldDescription>, row_count: usize) -> usize {
+#[allow(dead_code)]
+fn estimate_mem(fields: &[FieldDescription], row_count: usize) -> usize {
     let mem_estimate = fields.iter().map(|field| {
         let data_size = match field.data_type {
             DataType::Timestamp => 8,
             DataType::Float => 8,
             DataType::Bool => 1,
+            DataType::Integer => 8,
+            // strings vary in length, so assume they're short
+            DataType::String => 16,
         } as usize;
 
         // 32 comes from nowhere, fyi
@@ -155,7 +196,6 @@ fn estimate_mem(fields: &Vec<FieldDescription>, row_count: usize) -> usize {
 // -
 #[cfg(test)]
 mod tests {
-    use std::str::from_utf8;
     use crate::{ClientRecordCollection, DataValue};
     use crate::{DataRow, RecordCollection};
     use crate::execution::QueryResult;
@@ -176,10 +216,10 @@ mod tests {
 
         // dbg!(&result);
         let mut buf = vec![];
-        build_query_result(&result, &mut buf).await;
+        build_query_result(&result, &mut buf).await.unwrap();
         dbg!(&buf);
 
-        let result = parse_result(&mut buf);
+        let result = parse_result(&mut buf).unwrap();
         match result {
             ClientExecutionResult::Query(result) => {
                 assert_eq!(result.count, 1);
@@ -192,17 +232,116 @@ mod tests {
                     }],
                 });
             }
-            _ => assert!(false)
+            _ => panic!("expected a query result")
+        }
+    }
+
+    #[tokio::test]
+    async fn string_query_response() {
+        let fields = vec![FieldDescription { name: String::from("status"), data_type: DataType::String },
+                          FieldDescription { name: String::from("value"), data_type: DataType::Float }];
+        let result = QueryResult {
+            count: 2,
+            records: RecordCollection {
+                fields: fields.clone(),
+                elements: vec![DataValue::Timestamp(1), DataValue::from("ok"), DataValue::from(1.0),
+                               DataValue::Timestamp(2), DataValue::from("I'm \"fine\""), DataValue::from(2.0)],
+            },
+        };
+
+        let mut buf = vec![];
+        build_query_result(&result, &mut buf).await.unwrap();
+
+        match parse_result(&mut buf).unwrap() {
+            ClientExecutionResult::Query(result) => assert_eq!(result.records, ClientRecordCollection {
+                fields,
+                rows: vec![
+                    DataRow { time: 1, elements: vec![DataValue::from("ok"), DataValue::from(1.0)] },
+                    DataRow { time: 2, elements: vec![DataValue::from("I'm \"fine\""), DataValue::from(2.0)] },
+                ],
+            }),
+            _ => panic!("expected a query result"),
         }
     }
 
+    #[tokio::test]
+    async fn integer_query_response() {
+        let fields = vec![FieldDescription { name: String::from("count"), data_type: DataType::Integer }];
+        let result = QueryResult {
+            count: 2,
+            records: RecordCollection {
+                fields: fields.clone(),
+                elements: vec![DataValue::Timestamp(1), DataValue::Integer(i64::MAX),
+                               DataValue::Timestamp(2), DataValue::Integer(-3)],
+            },
+        };
+
+        let mut buf = vec![];
+        build_query_result(&result, &mut buf).await.unwrap();
+
+        match parse_result(&mut buf).unwrap() {
+            ClientExecutionResult::Query(result) => assert_eq!(result.records, ClientRecordCollection {
+                fields,
+                rows: vec![
+                    DataRow { time: 1, elements: vec![DataValue::Integer(i64::MAX)] },
+                    DataRow { time: 2, elements: vec![DataValue::Integer(-3)] },
+                ],
+            }),
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sparse_query_response() {
+        // enough fields for the presence bitmap to span more than a byte
+        let mut fields = vec![FieldDescription { name: String::from("status"), data_type: DataType::String }];
+        fields.extend((1..9).map(|i| FieldDescription { name: format!("flag{}", i), data_type: DataType::Bool }));
+
+        let mut first = vec![DataValue::from("up")];
+        first.extend((1..9).map(|i| if i % 3 == 0 { DataValue::None } else { DataValue::from(i % 2 == 0) }));
+        let mut second = vec![DataValue::None; 8];
+        second.push(DataValue::from(true));
+
+        let mut elements = vec![DataValue::Timestamp(1)];
+        elements.extend(first.clone());
+        elements.push(DataValue::Timestamp(2));
+        elements.extend(second.clone());
+        let result = QueryResult { count: 2, records: RecordCollection { fields: fields.clone(), elements } };
+
+        let mut buf = vec![];
+        build_query_result(&result, &mut buf).await.unwrap();
+
+        match parse_result(&mut buf).unwrap() {
+            ClientExecutionResult::Query(result) => assert_eq!(result.records, ClientRecordCollection {
+                fields,
+                rows: vec![DataRow { time: 1, elements: first }, DataRow { time: 2, elements: second }],
+            }),
+            _ => panic!("expected a query result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn too_many_field_descs() {
+        let fields: Vec<_> = (0..=u8::MAX as usize)
+            .map(|i| FieldDescription { data_type: DataType::Float, name: format!("field{}", i) })
+            .collect();
+
+        let mut buffer = vec![];
+        write_field_descriptions(&mut buffer, &fields[..u8::MAX as usize].to_vec()).await.unwrap();
+
+        let mut buffer = vec![];
+        let err = write_field_descriptions(&mut buffer, &fields).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(buffer.is_empty());
+    }
+
     #[tokio::test]
     async fn field_descs() {
         let mut buffer = vec![];
         write_field_descriptions(&mut buffer, &vec![
             FieldDescription { data_type: DataType::Float, name: String::from("field1") },
             FieldDescription { data_type: DataType::Float, name: String::from("field2") },
-        ]).await;
+        ]).await.unwrap();
 
         dbg!(&buffer);
 
@@ -256,6 +395,4 @@ mod tests {
     //
     //     assert_eq!(parsed_row, row);
     // }
-
-// TODO: test like ^ but with Some (null values) for both float and bool
 }
\ No newline at end of file
@@ -1,8 +1,22 @@
+use std::env;
+
 use rtdb::network;
+use rtdb::network::ServerOptions;
+use rtdb::storage::block_manager::DEFAULT_CACHE_CAPACITY;
 
 
 #[tokio::main]
 async fn main() {
+    let options = ServerOptions {
+        require_auth: env::var("RTDB_REQUIRE_AUTH").is_ok_and(|v| v == "1" || v == "true"),
+        block_cache_capacity: match env::var("RTDB_BLOCK_CACHE_MB").ok().and_then(|v| v.parse::<usize>().ok()) {
+            Some(megabytes) => megabytes * 1024 * 1024,
+            None => DEFAULT_CACHE_CAPACITY,
+        },
+        data_dir: env::var("RTDB_DATA_DIR").ok(),
+        ..ServerOptions::default()
+    };
+
     // HttpServer::start().await;
-    network::start_tcp_listener().await;
+    network::start_tcp_listener_with(options).await;
 }